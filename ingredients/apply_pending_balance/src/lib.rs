@@ -1,106 +1,278 @@
-use std::{error::Error, sync::Arc};
+use std::error::Error;
 
-use utils::{
-    get_non_blocking_rpc_client, get_or_create_keypair, get_rpc_client, load_value, print_transaction_url,
+use utils::{current_mint, get_or_create_keypair, get_rpc_client, nonce, nonce::BlockhashSource, print_transaction_url, proof_generation::run_proof_job};
+use solana_client::rpc_client::RpcClient;
+use solana_sdk::{
+    pubkey::Pubkey,
+    signature::{Keypair, Signature},
+    signer::Signer,
 };
-use solana_sdk::{signer::Signer, transaction::Transaction};
 use spl_associated_token_account::get_associated_token_address_with_program_id;
 use spl_token_2022::{
     error::TokenError,
     extension::{
         confidential_transfer::{
-            account_info::ApplyPendingBalanceAccountInfo, instruction, ConfidentialTransferAccount,
+            instruction, ConfidentialTransferAccount, EncryptedBalance, PENDING_BALANCE_LO_BIT_LENGTH,
         },
-        BaseStateWithExtensions,
+        BaseStateWithExtensions, StateWithExtensionsOwned,
     },
-    solana_zk_sdk::encryption::{auth_encryption::AeKey, elgamal::ElGamalKeypair},
-};
-use spl_token_client::{
-    client::{ProgramRpcClient, ProgramRpcClientSendTransaction},
-    token::Token,
+    solana_zk_sdk::encryption::{
+        auth_encryption::{AeCiphertext, AeKey},
+        elgamal::{ElGamalCiphertext, ElGamalKeypair, ElGamalSecretKey},
+    },
+    state::Account,
 };
 
+/// Number of apply transactions sent before giving up on a pending balance that keeps being credited.
+const MAX_APPLY_ATTEMPTS: usize = 3;
+
+/// Bit length of the largest late credit total `decrypt_late_credits` recovers: the range of a
+/// confidential transfer amount (`pending_balance_lo` plus `pending_balance_hi`).
+const MAX_LATE_CREDITS_BIT_LENGTH: u32 = 48;
+
+/// Outcome of `apply_pending_balance`.
+#[derive(Debug)]
+pub struct AppliedPendingBalance {
+    /// Signatures of the apply transactions sent; empty if nothing was pending.
+    pub signatures: Vec<Signature>,
+    /// Amount moved from the pending balance into the available balance.
+    pub applied_amount: u64,
+    pub new_available_balance: u64,
+}
+
+/// Moves the pending balance into the available balance, skipping the transaction when nothing is pending.
+///
+/// The program applies whatever is pending when the transaction executes, but the new decryptable
+/// available balance is computed from what was pending when the account was fetched. If credits
+/// arrived in between, the recorded expected and actual credit counters differ and the decryptable
+/// balance is short by the late credits: they are recovered from the available balance ciphertext
+/// and the pending balance is applied again with the corrected value.
+///
+/// With `use_durable_nonces`, the applies use one of the authority's durable nonces if it has one.
 pub async fn apply_pending_balance(
-    token_account_authority: &dyn Signer
-) -> Result<(), Box<dyn Error>> {
-    let fee_payer_keypair = Arc::new(get_or_create_keypair("fee_payer_keypair")?);
+    token_account_authority: &dyn Signer,
+    use_durable_nonces: bool,
+) -> Result<AppliedPendingBalance, Box<dyn Error>> {
+    let fee_payer_keypair = get_or_create_keypair("fee_payer_keypair")?;
     let client = get_rpc_client()?;
-    let mint = get_or_create_keypair("mint")?;
-    let decimals = load_value("mint_decimals")?;
+    let mint = current_mint()?;
 
     let token_account_pubkey = get_associated_token_address_with_program_id(
         &token_account_authority.pubkey(),
-        &mint.pubkey(),
+        &mint,
         &spl_token_2022::id(),
     );
     
-    let sender_elgamal_keypair =
-        ElGamalKeypair::new_from_signer(&token_account_authority, &token_account_pubkey.to_bytes())
-            .unwrap();
-    let sender_aes_key =
-        AeKey::new_from_signer(&token_account_authority, &token_account_pubkey.to_bytes()).unwrap();
-    
-    // The "pending" balance must be applied to "available" balance before it can be transferred
-
-    // A "non-blocking" RPC client (for async calls)
-    let token = {
-        let rpc_client = get_non_blocking_rpc_client()?;
-
-        let program_client =
-            ProgramRpcClient::new(Arc::new(rpc_client), ProgramRpcClientSendTransaction);
-
-        // Create a "token" client, to use various helper functions for Token Extensions
-        Token::new(
-            Arc::new(program_client),
-            &spl_token_2022::id(),
-            &mint.pubkey(),
-            Some(decimals),
-            fee_payer_keypair.clone(),
-        )
-    };
+    let elgamal_keypair =
+        ElGamalKeypair::new_from_signer(&token_account_authority, &token_account_pubkey.to_bytes())?;
+    let aes_key =
+        AeKey::new_from_signer(&token_account_authority, &token_account_pubkey.to_bytes())?;
+
+    let blockhash_source = nonce::sequential_blockhash_source(&token_account_authority.pubkey(), use_durable_nonces);
+
+    let mut available_balance = 0;
+    let mut applied_amount = 0;
+    let mut signatures = Vec::new();
+
+    // Set once the on-chain decryptable available balance no longer matches the available balance
+    let mut stale_decryptable_balance = false;
+
+    for _ in 0..MAX_APPLY_ATTEMPTS {
+        let token_account = get_token_account(&client, &token_account_pubkey)?;
+        let confidential_transfer_account = token_account.get_extension::<ConfidentialTransferAccount>()?;
+        if !stale_decryptable_balance {
+            available_balance = decrypt_available_balance(confidential_transfer_account, &aes_key)?;
+        }
+
+        // Return the number of times the pending balance has been credited
+        let expected_pending_balance_credit_counter =
+            u64::from(confidential_transfer_account.pending_balance_credit_counter);
+        if expected_pending_balance_credit_counter == 0 && !stale_decryptable_balance {
+            if signatures.is_empty() {
+                println!("No pending balance to apply for {}", token_account_pubkey);
+            }
+            return Ok(AppliedPendingBalance {
+                signatures,
+                applied_amount,
+                new_available_balance: available_balance,
+            });
+        }
+
+        let pending_balance = decrypt_pending_balance(confidential_transfer_account, elgamal_keypair.secret())?;
+        let new_available_balance = available_balance
+            .checked_add(pending_balance)
+            .ok_or(TokenError::Overflow)?;
 
-    // Get sender token account data
-    let token_account_info = token
-        .get_account_info(&token_account_pubkey)
-        .await?;
+        let transaction_signature = send_apply_pending_balance(
+            &client,
+            token_account_authority,
+            &fee_payer_keypair,
+            &token_account_pubkey,
+            expected_pending_balance_credit_counter,
+            aes_key.encrypt(new_available_balance),
+            &blockhash_source,
+        )?;
+        signatures.push(transaction_signature);
 
-    // Unpack the ConfidentialTransferAccount extension portion of the token account data
-    let confidential_transfer_account =
-        token_account_info.get_extension::<ConfidentialTransferAccount>()?;
+        let token_account = get_token_account(&client, &token_account_pubkey)?;
+        let confidential_transfer_account = token_account.get_extension::<ConfidentialTransferAccount>()?;
+        let actual_pending_balance_credit_counter =
+            u64::from(confidential_transfer_account.actual_pending_balance_credit_counter);
 
-    // ConfidentialTransferAccount extension information needed to construct an `ApplyPendingBalance` instruction.
-    let apply_pending_balance_account_info =
-        ApplyPendingBalanceAccountInfo::new(confidential_transfer_account);
+        if actual_pending_balance_credit_counter == expected_pending_balance_credit_counter {
+            return Ok(AppliedPendingBalance {
+                signatures,
+                applied_amount: applied_amount + pending_balance,
+                new_available_balance,
+            });
+        }
 
-    // Return the number of times the pending balance has been credited
-    let expected_pending_balance_credit_counter =
-        apply_pending_balance_account_info.pending_balance_credit_counter();
+        // The available balance ciphertext includes the late credits; the difference recovers them.
+        let available_balance_ciphertext: ElGamalCiphertext = confidential_transfer_account
+            .available_balance
+            .try_into()
+            .map_err(|_| TokenError::MalformedCiphertext)?;
+        let late_credits = decrypt_late_credits(
+            elgamal_keypair.secret(),
+            &available_balance_ciphertext.subtract_amount(new_available_balance),
+        ).await?;
+        println!(
+            "{} credit(s) arrived while applying the pending balance ({} expected); re-applying",
+            actual_pending_balance_credit_counter - expected_pending_balance_credit_counter,
+            expected_pending_balance_credit_counter
+        );
 
-    // Update the decryptable available balance (add pending balance to available balance)
-    let new_decryptable_available_balance = apply_pending_balance_account_info
-        .new_decryptable_available_balance(&sender_elgamal_keypair.secret(), &sender_aes_key)
-        .map_err(|_| TokenError::AccountDecryption)?;
+        applied_amount += pending_balance + late_credits;
+        available_balance = new_available_balance + late_credits;
+        stale_decryptable_balance = true;
+    }
 
+    Err(format!(
+        "Pending balance of {} kept changing after {} applies; its decryptable available balance may be stale",
+        token_account_pubkey, MAX_APPLY_ATTEMPTS
+    ).into())
+}
+
+fn send_apply_pending_balance(
+    client: &RpcClient,
+    token_account_authority: &dyn Signer,
+    fee_payer_keypair: &Keypair,
+    token_account_pubkey: &Pubkey,
+    expected_pending_balance_credit_counter: u64,
+    new_decryptable_available_balance: AeCiphertext,
+    blockhash_source: &BlockhashSource,
+) -> Result<Signature, Box<dyn Error>> {
     // Create a `ApplyPendingBalance` instruction
     let apply_pending_balance_instruction = instruction::apply_pending_balance(
         &spl_token_2022::id(),
-        &token_account_pubkey,         // Token account
+        token_account_pubkey,         // Token account
         expected_pending_balance_credit_counter, // Expected number of times the pending balance has been credited
         &new_decryptable_available_balance.into(), // Cipher text of the new decryptable available balance
         &token_account_authority.pubkey(),                       // Token account owner
         &[&token_account_authority.pubkey()],                    // Additional signers
     )?;
 
-    let recent_blockhash = client.get_latest_blockhash()?;
-    let transaction = Transaction::new_signed_with_payer(
+    let transaction = nonce::signed_transaction(
+        client,
         &[apply_pending_balance_instruction],
-        Some(&fee_payer_keypair.pubkey()),
-        &[&token_account_authority, &fee_payer_keypair as &dyn Signer],
-        recent_blockhash,
-    );
+        &fee_payer_keypair.pubkey(),
+        &[token_account_authority, fee_payer_keypair as &dyn Signer],
+        blockhash_source,
+    )?;
 
     let transaction_signature = client.send_and_confirm_transaction(&transaction)?;
 
     print_transaction_url("Apply Pending Balance", &transaction_signature.to_string());
-    Ok(())
-}
\ No newline at end of file
+    Ok(transaction_signature)
+}
+
+fn get_token_account(
+    client: &RpcClient,
+    token_account_pubkey: &Pubkey,
+) -> Result<StateWithExtensionsOwned<Account>, Box<dyn Error>> {
+    Ok(StateWithExtensionsOwned::<Account>::unpack(client.get_account(token_account_pubkey)?.data)?)
+}
+
+/// Decrypts the pending balance (`pending_balance_lo` plus `pending_balance_hi` shifted by
+/// `PENDING_BALANCE_LO_BIT_LENGTH`) with the owner's ElGamal secret key.
+pub fn decrypt_pending_balance(
+    confidential_transfer_account: &ConfidentialTransferAccount,
+    elgamal_secret_key: &ElGamalSecretKey,
+) -> Result<u64, TokenError> {
+    let decrypt = |pending_balance: EncryptedBalance| -> Result<u64, TokenError> {
+        let pending_balance: ElGamalCiphertext = pending_balance
+            .try_into()
+            .map_err(|_| TokenError::MalformedCiphertext)?;
+        elgamal_secret_key
+            .decrypt_u32(&pending_balance)
+            .ok_or(TokenError::AccountDecryption)
+    };
+
+    let pending_balance_lo = decrypt(confidential_transfer_account.pending_balance_lo)?;
+    let pending_balance_hi = decrypt(confidential_transfer_account.pending_balance_hi)?;
+    pending_balance_hi
+        .checked_shl(PENDING_BALANCE_LO_BIT_LENGTH)
+        .and_then(|pending_balance_hi| pending_balance_hi.checked_add(pending_balance_lo))
+        .ok_or(TokenError::AccountDecryption)
+}
+
+/// Decrypts the credits that landed between reading the pending balance and applying it, given the
+/// available balance ciphertext minus the expected new available balance.
+///
+/// The apply folds `pending_balance_lo` and `pending_balance_hi` into a single ciphertext, so the
+/// late credits can't be decrypted in 32-bit halves like the pending balance. Amounts up to 32 bits
+/// decrypt directly; larger ones are found by trying each value of the upper bits in the 48-bit
+/// range of a confidential transfer amount. That search can take minutes, so the decryption runs on
+/// the proof generation pool rather than the async runtime.
+async fn decrypt_late_credits(
+    elgamal_secret_key: &ElGamalSecretKey,
+    late_credits: &ElGamalCiphertext,
+) -> Result<u64, Box<dyn Error>> {
+    let elgamal_secret_key = elgamal_secret_key.clone();
+    let late_credits = *late_credits;
+
+    run_proof_job("late_credits", move || {
+        if let Some(late_credits) = elgamal_secret_key.decrypt_u32(&late_credits) {
+            return Some(late_credits);
+        }
+
+        println!("Late credits exceed 32 bits; re-syncing them from the available balance ciphertext");
+        (1..1u64 << (MAX_LATE_CREDITS_BIT_LENGTH - u32::BITS)).find_map(|upper_bits| {
+            let upper = upper_bits << u32::BITS;
+            elgamal_secret_key
+                .decrypt_u32(&late_credits.subtract_amount(upper))
+                .map(|lower| upper + lower)
+        })
+    })
+    .await?
+    .ok_or_else(|| format!("Late credits exceed the {}-bit range of a confidential transfer amount", MAX_LATE_CREDITS_BIT_LENGTH).into())
+}
+
+/// Decrypts the available balance from its AES-encrypted `decryptable_available_balance`.
+pub fn decrypt_available_balance(
+    confidential_transfer_account: &ConfidentialTransferAccount,
+    aes_key: &AeKey,
+) -> Result<u64, TokenError> {
+    let decryptable_available_balance: AeCiphertext = confidential_transfer_account
+        .decryptable_available_balance
+        .try_into()
+        .map_err(|_| TokenError::MalformedCiphertext)?;
+    aes_key
+        .decrypt(&decryptable_available_balance)
+        .ok_or(TokenError::AccountDecryption)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_decrypt_late_credits() {
+        let elgamal_keypair = ElGamalKeypair::new_rand();
+        // A single value above 32 bits, with small upper bits: each step of the search is a full 32-bit decryption.
+        for late_credits in [0, 50_00, u32::MAX as u64, (1 << 32) + 7] {
+            let ciphertext = elgamal_keypair.pubkey().encrypt(late_credits);
+            assert_eq!(decrypt_late_credits(elgamal_keypair.secret(), &ciphertext).await.unwrap(), late_credits);
+        }
+    }
+}
@@ -0,0 +1,143 @@
+use std::error::Error;
+
+use utils::{get_rpc_client, nonce, print_transaction_url};
+use solana_client::{
+    rpc_config::{RpcAccountInfoConfig, RpcProgramAccountsConfig},
+    rpc_filter::{Memcmp, RpcFilterType},
+};
+use solana_sdk::{
+    pubkey::Pubkey,
+    signature::{Signature, Signer},
+};
+use spl_token_2022::{
+    extension::{
+        confidential_transfer::{instruction, ConfidentialTransferAccount},
+        BaseStateWithExtensions, StateWithExtensionsOwned,
+    },
+    state::Account,
+};
+
+/// Number of `ApproveAccount` instructions packed into each approval transaction.
+const APPROVE_BATCH_SIZE: usize = 10;
+
+/// Approves a configured token account of a mint created with `auto_approve_new_accounts: false`.
+/// Until approved, the account can't deposit, transfer or receive confidentially.
+///
+/// With `use_durable_nonces`, the transaction uses one of the authority's durable nonces if it has one.
+pub async fn approve_account(
+    confidential_transfer_authority: &dyn Signer,
+    token_account: &Pubkey,
+    use_durable_nonces: bool,
+) -> Result<Signature, Box<dyn Error>> {
+    let client = get_rpc_client()?;
+    let mint = StateWithExtensionsOwned::<Account>::unpack(client.get_account(token_account)?.data)?
+        .base
+        .mint;
+
+    let approve_instruction = instruction::approve_account(
+        &spl_token_2022::id(),
+        token_account,                             // Token account to approve
+        &mint,                                     // Mint
+        &confidential_transfer_authority.pubkey(), // Confidential transfer authority of the mint
+        &[],                                       // Multisig signers
+    )?;
+
+    let transaction = nonce::signed_transaction(
+        &client,
+        &[approve_instruction],
+        &confidential_transfer_authority.pubkey(),
+        &[confidential_transfer_authority],
+        &nonce::sequential_blockhash_source(&confidential_transfer_authority.pubkey(), use_durable_nonces),
+    )?;
+
+    let transaction_signature = client.send_and_confirm_transaction(&transaction)?;
+    print_transaction_url("Approve Account", &transaction_signature.to_string());
+
+    Ok(transaction_signature)
+}
+
+/// Finds the token accounts of `mint` that are configured for confidential transfers but not yet approved.
+pub fn pending_approvals(mint: &Pubkey) -> Result<Vec<Pubkey>, Box<dyn Error>> {
+    let client = get_rpc_client()?;
+
+    // `mint` is the first field of a token account.
+    let config = RpcProgramAccountsConfig {
+        filters: Some(vec![RpcFilterType::Memcmp(Memcmp::new_base58_encoded(
+            0,
+            &mint.to_bytes(),
+        ))]),
+        account_config: RpcAccountInfoConfig::default(),
+        ..RpcProgramAccountsConfig::default()
+    };
+
+    let accounts = client.get_program_accounts_with_config(&spl_token_2022::id(), config)?;
+
+    Ok(accounts
+        .into_iter()
+        .filter(|(_, account)| {
+            StateWithExtensionsOwned::<Account>::unpack(account.data.clone())
+                .ok()
+                .and_then(|token_account| {
+                    token_account
+                        .get_extension::<ConfidentialTransferAccount>()
+                        .ok()
+                        .map(|confidential_transfer_account| !bool::from(confidential_transfer_account.approved))
+                })
+                .unwrap_or(false)
+        })
+        .map(|(address, _)| address)
+        .collect())
+}
+
+/// Approves every account in the pending-approvals queue of `mint` that `should_approve` accepts
+/// (e.g. after a KYC lookup). Returns the approved accounts.
+///
+/// With `use_durable_nonces`, the batches share one of the authority's durable nonces if it has one.
+pub async fn approve_pending_accounts(
+    confidential_transfer_authority: &dyn Signer,
+    mint: &Pubkey,
+    use_durable_nonces: bool,
+    should_approve: impl Fn(&Pubkey) -> bool,
+) -> Result<Vec<Pubkey>, Box<dyn Error>> {
+    let client = get_rpc_client()?;
+    let approved: Vec<Pubkey> = pending_approvals(mint)?
+        .into_iter()
+        .filter(|token_account| should_approve(token_account))
+        .collect();
+
+    if approved.is_empty() {
+        println!("No pending approvals for mint {}", mint);
+        return Ok(approved);
+    }
+
+    let blockhash_source = nonce::sequential_blockhash_source(&confidential_transfer_authority.pubkey(), use_durable_nonces);
+    for batch in approved.chunks(APPROVE_BATCH_SIZE) {
+        let approve_instructions = batch
+            .iter()
+            .map(|token_account| {
+                instruction::approve_account(
+                    &spl_token_2022::id(),
+                    token_account,
+                    mint,
+                    &confidential_transfer_authority.pubkey(),
+                    &[],
+                )
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let transaction = nonce::signed_transaction(
+            &client,
+            &approve_instructions,
+            &confidential_transfer_authority.pubkey(),
+            &[confidential_transfer_authority],
+            &blockhash_source,
+        )?;
+
+        let transaction_signature = client.send_and_confirm_transaction(&transaction)?;
+        print_transaction_url("Approve Accounts", &transaction_signature.to_string());
+    }
+
+    println!("Approved {} accounts for mint {}", approved.len(), mint);
+
+    Ok(approved)
+}
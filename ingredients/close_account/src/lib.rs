@@ -0,0 +1,175 @@
+use std::{error::Error, sync::Arc};
+
+use utils::{current_mint, get_or_create_keypair, get_rpc_client, load_value, nonce, print_transaction_url};
+use solana_sdk::{
+    instruction::Instruction,
+    pubkey::Pubkey,
+    signature::{Signature, Signer},
+};
+use spl_associated_token_account::get_associated_token_address_with_program_id;
+use spl_token_2022::{
+    extension::{
+        confidential_transfer::{
+            account_info::EmptyAccountAccountInfo,
+            instruction::{disable_confidential_credits, empty_account},
+            ConfidentialTransferAccount,
+        },
+        confidential_transfer_fee::{instruction::harvest_withheld_tokens_to_mint, ConfidentialTransferFeeAmount},
+        BaseStateWithExtensions, StateWithExtensionsOwned,
+    },
+    instruction::{close_account as close_account_instruction, transfer_checked},
+    solana_zk_sdk::encryption::elgamal::ElGamalKeypair,
+    state::Account,
+};
+use spl_token_confidential_transfer_proof_extraction::instruction::{ProofData, ProofLocation};
+use withdraw_tokens::WithdrawReceipt;
+
+/// Outcome of a completed `close_account`.
+#[derive(Debug)]
+pub struct CloseAccountReceipt {
+    pub token_account: Pubkey,
+    pub disable_confidential_credits_signature: Option<Signature>,
+    /// The withdrawal of the whole available balance, if there was anything to withdraw.
+    pub withdraw: Option<WithdrawReceipt>,
+    /// Public tokens moved to the destination token account by the closing transaction.
+    pub public_balance_transferred: u64,
+    pub close_signature: Signature,
+    /// Token account rent returned to the owner.
+    pub rent_reclaimed: u64,
+}
+
+/// Retires the owner's confidential token account for the current mint.
+///
+/// Applies any pending balance, withdraws the whole available balance, then in a single transaction
+/// empties the confidential balance (with a zero-ciphertext proof), moves the public balance to
+/// `public_balance_destination` and closes the account, returning its rent to the owner.
+///
+/// With `disable_credits`, confidential credits are disabled first, so no incoming transfer can
+/// land a pending balance between the apply and the close. With `use_durable_nonces`, every
+/// transaction uses the owner's durable nonces if it has enough of them.
+pub async fn close_account(
+    owner: Arc<dyn Signer>,
+    public_balance_destination: &Pubkey,
+    disable_credits: bool,
+    use_durable_nonces: bool,
+) -> Result<CloseAccountReceipt, Box<dyn Error>> {
+    let client = get_rpc_client()?;
+    let fee_payer_keypair = get_or_create_keypair("fee_payer_keypair")?;
+    let mint = current_mint()?;
+    let decimals = load_value("mint_decimals")?;
+
+    let token_account_pubkey = get_associated_token_address_with_program_id(
+        &owner.pubkey(),
+        &mint,
+        &spl_token_2022::id(),
+    );
+
+    let elgamal_keypair = ElGamalKeypair::new_from_signer(owner.as_ref(), &token_account_pubkey.to_bytes())?;
+    let signers = [owner.as_ref(), &fee_payer_keypair as &dyn Signer];
+    let blockhash_source = nonce::sequential_blockhash_source(&owner.pubkey(), use_durable_nonces);
+
+    let disable_confidential_credits_signature = if disable_credits {
+        let disable_instruction = disable_confidential_credits(
+            &spl_token_2022::id(),
+            &token_account_pubkey,
+            &owner.pubkey(),
+            &[],
+        )?;
+        let transaction = nonce::signed_transaction(
+            &client,
+            &[disable_instruction],
+            &fee_payer_keypair.pubkey(),
+            &signers,
+            &blockhash_source,
+        )?;
+        let transaction_signature = client.send_and_confirm_transaction(&transaction)?;
+        print_transaction_url("Disable Confidential Credits", &transaction_signature.to_string());
+        Some(transaction_signature)
+    } else {
+        None
+    };
+
+    // Step 1. Move any pending balance into the available balance
+    let available_balance = apply_pending_balance::apply_pending_balance(owner.as_ref(), use_durable_nonces)
+        .await?
+        .new_available_balance;
+
+    // Step 2. Withdraw the whole available balance into the public balance
+    let withdraw = if available_balance > 0 {
+        Some(withdraw_tokens::withdraw_tokens(available_balance, owner.clone(), None, use_durable_nonces).await?)
+    } else {
+        None
+    };
+
+    // Step 3. Empty, drain and close the account in one transaction
+    let token_account_data = client.get_account(&token_account_pubkey)?;
+    let rent_reclaimed = token_account_data.lamports;
+    let token_account = StateWithExtensionsOwned::<Account>::unpack(token_account_data.data)?;
+    let confidential_transfer_account = token_account.get_extension::<ConfidentialTransferAccount>()?;
+
+    // Proves the available balance ciphertext encrypts zero, so `EmptyAccount` can reset it.
+    let proof_data = EmptyAccountAccountInfo::new(confidential_transfer_account)
+        .generate_proof_data(&elgamal_keypair)?;
+
+    // `InstructionOffset` indicates that proof is included in the same transaction
+    // This means that the proof instruction offset must be always be 1.
+    let mut instructions: Vec<Instruction> = empty_account(
+        &spl_token_2022::id(),
+        &token_account_pubkey,
+        &owner.pubkey(),
+        &[],
+        ProofLocation::InstructionOffset(1.try_into().unwrap(), ProofData::InstructionData(&proof_data)),
+    )?;
+
+    // Confidential fees withheld in the account block the close; harvesting them to the mint is permissionless.
+    if let Ok(fee_amount) = token_account.get_extension::<ConfidentialTransferFeeAmount>() {
+        if fee_amount.closable().is_err() {
+            instructions.push(harvest_withheld_tokens_to_mint(
+                &spl_token_2022::id(),
+                &mint,
+                &[&token_account_pubkey],
+            )?);
+        }
+    }
+
+    let public_balance_transferred = token_account.base.amount;
+    if public_balance_transferred > 0 {
+        instructions.push(transfer_checked(
+            &spl_token_2022::id(),
+            &token_account_pubkey,
+            &mint,
+            public_balance_destination,
+            &owner.pubkey(),
+            &[],
+            public_balance_transferred,
+            decimals,
+        )?);
+    }
+
+    instructions.push(close_account_instruction(
+        &spl_token_2022::id(),
+        &token_account_pubkey,
+        &owner.pubkey(), // Rent destination
+        &owner.pubkey(),
+        &[],
+    )?);
+
+    let transaction = nonce::signed_transaction(
+        &client,
+        &instructions,
+        &fee_payer_keypair.pubkey(),
+        &signers,
+        &blockhash_source,
+    )?;
+    let close_signature = client.send_and_confirm_transaction(&transaction)?;
+    print_transaction_url("Empty And Close Token Account", &close_signature.to_string());
+
+    Ok(CloseAccountReceipt {
+        token_account: token_account_pubkey,
+        disable_confidential_credits_signature,
+        withdraw,
+        public_balance_transferred,
+        close_signature,
+        rent_reclaimed,
+    })
+}
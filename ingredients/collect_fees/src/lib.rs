@@ -0,0 +1,355 @@
+use std::{error::Error, sync::Arc};
+
+use utils::{
+    current_mint, get_or_create_keypair, get_rpc_client, nonce, print_transaction_url,
+    nonce::BlockhashSource,
+    proof_context::context_state_instructions,
+    proof_generation::run_proof_job,
+};
+use solana_sdk::{
+    instruction::Instruction,
+    pubkey::Pubkey,
+    signature::{Keypair, Signer},
+};
+use spl_associated_token_account::get_associated_token_address_with_program_id;
+use spl_token_2022::{
+    error::TokenError,
+    extension::{
+        confidential_transfer::ConfidentialTransferAccount,
+        confidential_transfer_fee::{
+            account_info::WithheldTokensInfo, instruction, ConfidentialTransferFeeAmount,
+            ConfidentialTransferFeeConfig, EncryptedWithheldAmount,
+        },
+        BaseStateWithExtensions, StateWithExtensionsOwned,
+    },
+    solana_zk_sdk::{
+        encryption::{
+            auth_encryption::{AeCiphertext, AeKey},
+            elgamal::{ElGamalCiphertext, ElGamalKeypair, ElGamalPubkey},
+        },
+        zk_elgamal_proof_program::instruction::{close_context_state, ContextStateInfo},
+    },
+    state::{Account, Mint},
+};
+use spl_token_confidential_transfer_proof_extraction::instruction::ProofLocation;
+
+/// Decrypts the fees harvested into the mint, using the withdraw withheld authority ElGamal key.
+pub fn withheld_amount_in_mint(
+    withdraw_withheld_authority_elgamal_keypair: &ElGamalKeypair,
+) -> Result<u64, Box<dyn Error>> {
+    let withheld_amount = get_withheld_amount_in_mint()?;
+    decrypt_withheld_amount(&withheld_amount, withdraw_withheld_authority_elgamal_keypair)
+}
+
+/// Decrypts the fees still withheld in the given token accounts, using the withdraw withheld authority ElGamal key.
+pub fn withheld_amount_in_accounts(
+    sources: &[Pubkey],
+    withdraw_withheld_authority_elgamal_keypair: &ElGamalKeypair,
+) -> Result<u64, Box<dyn Error>> {
+    let withheld_amount = get_aggregate_withheld_amount_in_accounts(sources)?;
+    decrypt_withheld_amount(&withheld_amount, withdraw_withheld_authority_elgamal_keypair)
+}
+
+/// Moves the encrypted fees withheld in `sources` into the mint.
+/// Harvesting is permissionless, so only the fee payer signs, against one of its durable nonces with
+/// `use_durable_nonces` (if it has one).
+pub async fn harvest_withheld_tokens_to_mint(sources: &[Pubkey], use_durable_nonces: bool) -> Result<(), Box<dyn Error>> {
+    let mint = current_mint()?;
+    let fee_payer_keypair = get_or_create_keypair("fee_payer_keypair")?;
+    let sources: Vec<&Pubkey> = sources.iter().collect();
+
+    let harvest_instruction =
+        instruction::harvest_withheld_tokens_to_mint(&spl_token_2022::id(), &mint, &sources)?;
+
+    send_instructions(
+        "Harvest Withheld Tokens To Mint",
+        &[harvest_instruction],
+        &[],
+        &nonce::sequential_blockhash_source(&fee_payer_keypair.pubkey(), use_durable_nonces),
+    )
+}
+
+/// Withdraws all fees harvested into the mint to the confidential available balance of
+/// `destination_owner`'s token account.
+/// Returns the withdrawn amount.
+pub async fn withdraw_withheld_tokens_from_mint(
+    withdraw_withheld_authority: Arc<dyn Signer>,
+    withdraw_withheld_authority_elgamal_keypair: &ElGamalKeypair,
+    destination_owner: &dyn Signer,
+    use_durable_nonces: bool,
+) -> Result<u64, Box<dyn Error>> {
+    let withheld_amount = get_withheld_amount_in_mint()?;
+
+    withdraw_withheld_tokens(
+        withdraw_withheld_authority,
+        withdraw_withheld_authority_elgamal_keypair,
+        destination_owner,
+        withheld_amount,
+        None,
+        use_durable_nonces,
+    )
+    .await
+}
+
+/// Withdraws the fees withheld in `sources` directly to the confidential available balance of
+/// `destination_owner`'s token account, without harvesting them to the mint first.
+/// Returns the withdrawn amount.
+pub async fn withdraw_withheld_tokens_from_accounts(
+    withdraw_withheld_authority: Arc<dyn Signer>,
+    withdraw_withheld_authority_elgamal_keypair: &ElGamalKeypair,
+    destination_owner: &dyn Signer,
+    sources: &[Pubkey],
+    use_durable_nonces: bool,
+) -> Result<u64, Box<dyn Error>> {
+    let withheld_amount = get_aggregate_withheld_amount_in_accounts(sources)?;
+
+    withdraw_withheld_tokens(
+        withdraw_withheld_authority,
+        withdraw_withheld_authority_elgamal_keypair,
+        destination_owner,
+        withheld_amount,
+        Some(sources),
+        use_durable_nonces,
+    )
+    .await
+}
+
+/// Allows fees withheld in token accounts to be harvested into the mint.
+pub async fn enable_harvest_to_mint(authority: &dyn Signer, use_durable_nonces: bool) -> Result<(), Box<dyn Error>> {
+    let mint = current_mint()?;
+
+    let enable_instruction = instruction::enable_harvest_to_mint(
+        &spl_token_2022::id(),
+        &mint,
+        &authority.pubkey(),
+        &[],
+    )?;
+
+    send_instructions(
+        "Enable Harvest To Mint",
+        &[enable_instruction],
+        &[authority],
+        &nonce::sequential_blockhash_source(&authority.pubkey(), use_durable_nonces),
+    )
+}
+
+/// Stops fees withheld in token accounts from being harvested into the mint.
+/// Withheld fees can still be withdrawn from the accounts directly.
+pub async fn disable_harvest_to_mint(authority: &dyn Signer, use_durable_nonces: bool) -> Result<(), Box<dyn Error>> {
+    let mint = current_mint()?;
+
+    let disable_instruction = instruction::disable_harvest_to_mint(
+        &spl_token_2022::id(),
+        &mint,
+        &authority.pubkey(),
+        &[],
+    )?;
+
+    send_instructions(
+        "Disable Harvest To Mint",
+        &[disable_instruction],
+        &[authority],
+        &nonce::sequential_blockhash_source(&authority.pubkey(), use_durable_nonces),
+    )
+}
+
+/// Shared flow for withdrawing withheld fees from the mint (`sources` is `None`) or from token accounts.
+///
+/// With `use_durable_nonces`, its transactions share one of the authority's durable nonces if it has one.
+async fn withdraw_withheld_tokens(
+    withdraw_withheld_authority: Arc<dyn Signer>,
+    withdraw_withheld_authority_elgamal_keypair: &ElGamalKeypair,
+    destination_owner: &dyn Signer,
+    withheld_amount: EncryptedWithheldAmount,
+    sources: Option<&[Pubkey]>,
+    use_durable_nonces: bool,
+) -> Result<u64, Box<dyn Error>> {
+    let mint = current_mint()?;
+    let client = get_rpc_client()?;
+
+    let destination_associated_token_address = get_associated_token_address_with_program_id(
+        &destination_owner.pubkey(),
+        &mint,
+        &spl_token_2022::id(),
+    );
+
+    let withdrawn_amount = decrypt_withheld_amount(&withheld_amount, withdraw_withheld_authority_elgamal_keypair)?;
+    if withdrawn_amount == 0 {
+        println!("No withheld fees to withdraw");
+        return Ok(0);
+    }
+
+    // The withheld amount is re-encrypted under the destination's ElGamal key,
+    // and the destination's decryptable balance is updated to include it.
+    let destination_aes_key =
+        AeKey::new_from_signer(&destination_owner, &destination_associated_token_address.to_bytes())?;
+
+    let (destination_elgamal_pubkey, new_decryptable_available_balance) = {
+        let destination_account = StateWithExtensionsOwned::<Account>::unpack(
+            client.get_account(&destination_associated_token_address)?.data,
+        )?;
+        let confidential_transfer_account = destination_account.get_extension::<ConfidentialTransferAccount>()?;
+
+        let destination_elgamal_pubkey: ElGamalPubkey = confidential_transfer_account.elgamal_pubkey.try_into()?;
+        let current_decryptable_available_balance: AeCiphertext =
+            confidential_transfer_account.decryptable_available_balance.try_into()?;
+        let current_available_balance = destination_aes_key
+            .decrypt(&current_decryptable_available_balance)
+            .ok_or(TokenError::AccountDecryption)?;
+
+        let new_available_balance = current_available_balance
+            .checked_add(withdrawn_amount)
+            .ok_or(TokenError::Overflow)?;
+
+        (destination_elgamal_pubkey, destination_aes_key.encrypt(new_available_balance))
+    };
+
+    // Create the ciphertext-ciphertext equality proof data on the dedicated proof pool
+    let equality_proof_data = {
+        let withheld_tokens_info = WithheldTokensInfo::new(&withheld_amount);
+        let withdraw_withheld_authority_elgamal_keypair = withdraw_withheld_authority_elgamal_keypair.clone();
+
+        run_proof_job("withdraw_withheld", move || {
+            withheld_tokens_info.generate_proof_data(
+                &withdraw_withheld_authority_elgamal_keypair,
+                &destination_elgamal_pubkey,
+            )
+        }).await??
+    };
+
+    // Authority for the equality proof account (to close the account)
+    let context_state_authority_pubkey = withdraw_withheld_authority.pubkey();
+    let blockhash_source = nonce::sequential_blockhash_source(&context_state_authority_pubkey, use_durable_nonces);
+
+    let equality_proof_context_state_keypair = Keypair::new();
+    let equality_proof_context_state_pubkey = equality_proof_context_state_keypair.pubkey();
+
+    // The withdraw withheld authority pays for the proof account, and gets its rent back on close
+    let (equality_create_ix, equality_verify_ix, _) = context_state_instructions(
+        &client,
+        &context_state_authority_pubkey,
+        &equality_proof_context_state_pubkey,
+        &context_state_authority_pubkey,
+        &equality_proof_data,
+    )?;
+    send_instructions(
+        "Equality Proof Context State Account",
+        &[equality_create_ix, equality_verify_ix],
+        &[withdraw_withheld_authority.as_ref(), &equality_proof_context_state_keypair],
+        &blockhash_source,
+    )?;
+
+    let proof_location = ProofLocation::ContextStateAccount(&equality_proof_context_state_pubkey);
+
+    let (label, withdraw_instructions) = match sources {
+        None => (
+            "Withdraw Withheld Tokens From Mint",
+            instruction::withdraw_withheld_tokens_from_mint(
+                &spl_token_2022::id(),
+                &mint,
+                &destination_associated_token_address,
+                &new_decryptable_available_balance.into(),
+                &withdraw_withheld_authority.pubkey(),
+                &[],
+                proof_location,
+            )?,
+        ),
+        Some(sources) => (
+            "Withdraw Withheld Tokens From Accounts",
+            instruction::withdraw_withheld_tokens_from_accounts(
+                &spl_token_2022::id(),
+                &mint,
+                &destination_associated_token_address,
+                &new_decryptable_available_balance.into(),
+                &withdraw_withheld_authority.pubkey(),
+                &[],
+                &sources.iter().collect::<Vec<_>>(),
+                proof_location,
+            )?,
+        ),
+    };
+
+    let withdraw_result = send_instructions(label, &withdraw_instructions, &[withdraw_withheld_authority.as_ref()], &blockhash_source);
+
+    // Close the equality proof account whether or not the withdrawal landed, returning its rent to
+    // the withdraw withheld authority (who paid for it) rather than to the destination token account
+    let close_instruction = close_context_state(
+        ContextStateInfo {
+            context_state_account: &equality_proof_context_state_pubkey,
+            context_state_authority: &context_state_authority_pubkey,
+        },
+        &context_state_authority_pubkey,
+    );
+    send_instructions(
+        "Close Equality Proof Context State Account",
+        &[close_instruction],
+        &[withdraw_withheld_authority.as_ref()],
+        &blockhash_source,
+    )?;
+
+    withdraw_result?;
+    println!("Withdrew {} in withheld fees", withdrawn_amount);
+
+    Ok(withdrawn_amount)
+}
+
+fn get_withheld_amount_in_mint() -> Result<EncryptedWithheldAmount, Box<dyn Error>> {
+    let client = get_rpc_client()?;
+    let mint = current_mint()?;
+
+    let mint_account = StateWithExtensionsOwned::<Mint>::unpack(client.get_account(&mint)?.data)?;
+    Ok(mint_account.get_extension::<ConfidentialTransferFeeConfig>()?.withheld_amount)
+}
+
+/// Sums the withheld amounts of `sources` homomorphically; the withdraw proof covers their total.
+fn get_aggregate_withheld_amount_in_accounts(sources: &[Pubkey]) -> Result<EncryptedWithheldAmount, Box<dyn Error>> {
+    let client = get_rpc_client()?;
+
+    let mut aggregate_withheld_amount = ElGamalCiphertext::default();
+    for account in client.get_multiple_accounts(sources)? {
+        let account = account.ok_or("Withheld fee source account not found")?;
+        let token_account = StateWithExtensionsOwned::<Account>::unpack(account.data)?;
+        let withheld_amount: ElGamalCiphertext = token_account
+            .get_extension::<ConfidentialTransferFeeAmount>()?
+            .withheld_amount
+            .try_into()?;
+        aggregate_withheld_amount = aggregate_withheld_amount + withheld_amount;
+    }
+
+    Ok(aggregate_withheld_amount.into())
+}
+
+fn decrypt_withheld_amount(
+    withheld_amount: &EncryptedWithheldAmount,
+    withdraw_withheld_authority_elgamal_keypair: &ElGamalKeypair,
+) -> Result<u64, Box<dyn Error>> {
+    let withheld_amount: ElGamalCiphertext = (*withheld_amount).try_into()?;
+    Ok(withheld_amount
+        .decrypt_u32(withdraw_withheld_authority_elgamal_keypair.secret())
+        .ok_or(TokenError::AccountDecryption)?)
+}
+
+fn send_instructions(
+    label: &str,
+    instructions: &[Instruction],
+    signers: &[&dyn Signer],
+    blockhash_source: &BlockhashSource,
+) -> Result<(), Box<dyn Error>> {
+    let fee_payer_keypair = get_or_create_keypair("fee_payer_keypair")?;
+    let client = get_rpc_client()?;
+
+    let mut all_signers: Vec<&dyn Signer> = vec![&fee_payer_keypair];
+    all_signers.extend_from_slice(signers);
+
+    let transaction = nonce::signed_transaction(
+        &client,
+        instructions,
+        &fee_payer_keypair.pubkey(),
+        &all_signers,
+        blockhash_source,
+    )?;
+
+    let transaction_signature = client.send_and_confirm_transaction(&transaction)?;
+    print_transaction_url(label, &transaction_signature.to_string());
+    Ok(())
+}
@@ -0,0 +1,104 @@
+use std::error::Error;
+
+use utils::{current_mint, get_or_create_keypair, get_rpc_client, nonce, print_transaction_url};
+use solana_sdk::{
+    instruction::Instruction,
+    pubkey::Pubkey,
+    signature::{Signature, Signer},
+};
+use spl_associated_token_account::get_associated_token_address_with_program_id;
+use spl_token_2022::{
+    extension::{
+        confidential_transfer::{instruction, ConfidentialTransferAccount},
+        BaseStateWithExtensions, StateWithExtensionsOwned,
+    },
+    state::Account,
+};
+
+/// Which incoming transfers a confidential token account accepts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CreditPolicy {
+    /// Confidential transfers into the pending balance.
+    pub allow_confidential_credits: bool,
+    /// Regular transfers and mints into the public balance.
+    pub allow_non_confidential_credits: bool,
+}
+
+impl CreditPolicy {
+    /// Only confidential transfers are accepted, for accounts that must never receive public funds.
+    pub const CONFIDENTIAL_ONLY: Self = Self {
+        allow_confidential_credits: true,
+        allow_non_confidential_credits: false,
+    };
+}
+
+/// Reads the credit policy of `owner`'s token account for the current mint.
+pub fn credit_policy(owner: &Pubkey) -> Result<CreditPolicy, Box<dyn Error>> {
+    let client = get_rpc_client()?;
+    let token_account_pubkey = token_account_address(owner)?;
+
+    let token_account = StateWithExtensionsOwned::<Account>::unpack(client.get_account(&token_account_pubkey)?.data)?;
+    let confidential_transfer_account = token_account.get_extension::<ConfidentialTransferAccount>()?;
+
+    Ok(CreditPolicy {
+        allow_confidential_credits: confidential_transfer_account.allow_confidential_credits.into(),
+        allow_non_confidential_credits: confidential_transfer_account.allow_non_confidential_credits.into(),
+    })
+}
+
+/// Enables or disables each kind of credit on `owner`'s token account to match `policy`.
+///
+/// Only flags that differ from the current ones are toggled, all in one transaction. Returns `None`
+/// if the account already has the requested policy. With `use_durable_nonces`, the transaction uses
+/// one of the owner's durable nonces if it has one.
+pub async fn configure_credits(owner: &dyn Signer, policy: CreditPolicy, use_durable_nonces: bool) -> Result<Option<Signature>, Box<dyn Error>> {
+    let client = get_rpc_client()?;
+    let fee_payer_keypair = get_or_create_keypair("fee_payer_keypair")?;
+    let token_account_pubkey = token_account_address(&owner.pubkey())?;
+    let current_policy = credit_policy(&owner.pubkey())?;
+
+    let mut instructions: Vec<Instruction> = Vec::new();
+    if policy.allow_confidential_credits != current_policy.allow_confidential_credits {
+        let toggle = if policy.allow_confidential_credits {
+            instruction::enable_confidential_credits
+        } else {
+            instruction::disable_confidential_credits
+        };
+        instructions.push(toggle(&spl_token_2022::id(), &token_account_pubkey, &owner.pubkey(), &[])?);
+    }
+    if policy.allow_non_confidential_credits != current_policy.allow_non_confidential_credits {
+        let toggle = if policy.allow_non_confidential_credits {
+            instruction::enable_non_confidential_credits
+        } else {
+            instruction::disable_non_confidential_credits
+        };
+        instructions.push(toggle(&spl_token_2022::id(), &token_account_pubkey, &owner.pubkey(), &[])?);
+    }
+
+    if instructions.is_empty() {
+        println!("Token account {} already has credit policy {:?}", token_account_pubkey, policy);
+        return Ok(None);
+    }
+
+    let transaction = nonce::signed_transaction(
+        &client,
+        &instructions,
+        &fee_payer_keypair.pubkey(),
+        &[owner, &fee_payer_keypair as &dyn Signer],
+        &nonce::sequential_blockhash_source(&owner.pubkey(), use_durable_nonces),
+    )?;
+
+    let transaction_signature = client.send_and_confirm_transaction(&transaction)?;
+
+    print_transaction_url("Configure Credits", &transaction_signature.to_string());
+    Ok(Some(transaction_signature))
+}
+
+fn token_account_address(owner: &Pubkey) -> Result<Pubkey, Box<dyn Error>> {
+    let mint = current_mint()?;
+    Ok(get_associated_token_address_with_program_id(
+        owner,
+        &mint,
+        &spl_token_2022::id(),
+    ))
+}
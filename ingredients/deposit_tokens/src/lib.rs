@@ -1,13 +1,30 @@
 use std::error::Error;
 
-use utils::{get_or_create_keypair, get_rpc_client, load_value, print_transaction_url};
-use solana_sdk::{signer::Signer, transaction::Transaction};
+use utils::{current_mint, get_rpc_client, load_value, nonce, print_transaction_url};
+use solana_client::rpc_client::RpcClient;
+use solana_sdk::{instruction::Instruction, signer::Signer};
 use spl_associated_token_account::get_associated_token_address_with_program_id;
-use spl_token_2022::extension::confidential_transfer::instruction::deposit;
+use spl_token_2022::{
+    extension::{
+        confidential_transfer::{
+            account_info::{ApplyPendingBalanceAccountInfo, TransferAccountInfo},
+            instruction::{apply_pending_balance, deposit},
+            ConfidentialTransferAccount, PENDING_BALANCE_LO_BIT_LENGTH,
+        },
+        BaseStateWithExtensions, StateWithExtensionsOwned,
+    },
+    solana_zk_sdk::encryption::{
+        auth_encryption::AeKey,
+        elgamal::{ElGamalCiphertext, ElGamalKeypair},
+    },
+    state::Account,
+};
 
-pub async fn deposit_tokens(deposit_amount: u64, depositor_signer: &dyn Signer) -> Result<(), Box<dyn Error>> {
+/// Deposits `deposit_amount` from the public balance into the pending confidential balance. With
+/// `use_durable_nonces`, the transaction uses one of the depositor's durable nonces if it has one.
+pub async fn deposit_tokens(deposit_amount: u64, depositor_signer: &dyn Signer, use_durable_nonces: bool) -> Result<(), Box<dyn Error>> {
     let client = get_rpc_client()?;
-    let mint = get_or_create_keypair("mint")?;
+    let mint = current_mint()?;
     let decimals = load_value("mint_decimals")?;
 
     // Confidential balance has separate "pending" and "available" balances
@@ -15,7 +32,7 @@ pub async fn deposit_tokens(deposit_amount: u64, depositor_signer: &dyn Signer)
 
     let depositor_token_account = get_associated_token_address_with_program_id(
         &depositor_signer.pubkey(), // Token account owner
-        &mint.pubkey(),        // Mint
+        &mint,        // Mint
         &spl_token_2022::id(),
     );
 
@@ -23,23 +40,129 @@ pub async fn deposit_tokens(deposit_amount: u64, depositor_signer: &dyn Signer)
     let deposit_instruction = deposit(
         &spl_token_2022::id(),
         &depositor_token_account, // Token account
-        &mint.pubkey(),                   // Mint
+        &mint,                   // Mint
         deposit_amount,                   // Amount to deposit
         decimals,                         // Mint decimals
         &depositor_signer.pubkey(),               // Token account owner
         &[&depositor_signer.pubkey()],            // Signers
     )?;
 
-    let recent_blockhash = client.get_latest_blockhash()?;
-    let transaction = Transaction::new_signed_with_payer(
+    let transaction = nonce::signed_transaction(
+        &client,
         &[deposit_instruction],
-        Some(&depositor_signer.pubkey()),
+        &depositor_signer.pubkey(),
         &[&depositor_signer],
-        recent_blockhash,
-    );
+        &nonce::sequential_blockhash_source(&depositor_signer.pubkey(), use_durable_nonces),
+    )?;
 
     let transaction_signature = client.send_and_confirm_transaction(&transaction)?;
 
     print_transaction_url("Deposit Tokens", &transaction_signature.to_string());
     Ok(())
-}
\ No newline at end of file
+}
+
+/// A deposit immediately followed by an apply of the whole pending balance, and the balances it leaves.
+pub struct DepositAndApply {
+    pub instructions: Vec<Instruction>,
+    /// Plaintext available balance once the instructions execute.
+    pub new_available_balance: u64,
+    /// The account's balances once the instructions execute, for proofs generated before they land.
+    pub new_transfer_account_info: TransferAccountInfo,
+}
+
+/// Deposits `deposit_amount` and applies the pending balance in one transaction, so the tokens are
+/// spendable without a separate `apply_pending_balance`. Returns the new available balance.
+///
+/// With `use_durable_nonces`, the transaction uses one of the depositor's durable nonces if it has one.
+pub async fn deposit_and_apply(deposit_amount: u64, depositor_signer: &dyn Signer, use_durable_nonces: bool) -> Result<u64, Box<dyn Error>> {
+    let client = get_rpc_client()?;
+    let deposit_and_apply = deposit_and_apply_instructions(&client, deposit_amount, depositor_signer)?;
+
+    let transaction = nonce::signed_transaction(
+        &client,
+        &deposit_and_apply.instructions,
+        &depositor_signer.pubkey(),
+        &[&depositor_signer],
+        &nonce::sequential_blockhash_source(&depositor_signer.pubkey(), use_durable_nonces),
+    )?;
+
+    let transaction_signature = client.send_and_confirm_transaction(&transaction)?;
+
+    print_transaction_url("Deposit And Apply Pending Balance", &transaction_signature.to_string());
+    Ok(deposit_and_apply.new_available_balance)
+}
+
+/// Builds a `Deposit` followed by an `ApplyPendingBalance` that also applies the deposit.
+///
+/// The new balances are computed locally from the current account state: the apply expects one
+/// more credit than the account holds now, and the resulting available balance ciphertext is derived
+/// the same way the program derives it, so later proofs can be generated without re-fetching.
+pub fn deposit_and_apply_instructions(
+    client: &RpcClient,
+    deposit_amount: u64,
+    depositor_signer: &dyn Signer,
+) -> Result<DepositAndApply, Box<dyn Error>> {
+    let mint = current_mint()?;
+    let decimals = load_value("mint_decimals")?;
+
+    let depositor_token_account = get_associated_token_address_with_program_id(
+        &depositor_signer.pubkey(),
+        &mint,
+        &spl_token_2022::id(),
+    );
+
+    let elgamal_keypair = ElGamalKeypair::new_from_signer(depositor_signer, &depositor_token_account.to_bytes())?;
+    let aes_key = AeKey::new_from_signer(depositor_signer, &depositor_token_account.to_bytes())?;
+
+    let token_account = StateWithExtensionsOwned::<Account>::unpack(client.get_account(&depositor_token_account)?.data)?;
+    let confidential_transfer_account = token_account.get_extension::<ConfidentialTransferAccount>()?;
+
+    let pending_balance = apply_pending_balance::decrypt_pending_balance(confidential_transfer_account, elgamal_keypair.secret())?;
+    let available_balance = apply_pending_balance::decrypt_available_balance(confidential_transfer_account, &aes_key)?;
+    let new_available_balance = available_balance
+        .checked_add(pending_balance)
+        .and_then(|balance| balance.checked_add(deposit_amount))
+        .ok_or("Available balance would overflow")?;
+
+    // The program adds `pending_balance_lo + (pending_balance_hi << 16)` to the available balance,
+    // with the deposit already added to the pending halves.
+    let available_balance_ciphertext: ElGamalCiphertext = confidential_transfer_account.available_balance.try_into()?;
+    let pending_balance_lo: ElGamalCiphertext = confidential_transfer_account.pending_balance_lo.try_into()?;
+    let mut shifted_pending_balance_hi: ElGamalCiphertext = confidential_transfer_account.pending_balance_hi.try_into()?;
+    for _ in 0..PENDING_BALANCE_LO_BIT_LENGTH {
+        shifted_pending_balance_hi = &shifted_pending_balance_hi + &shifted_pending_balance_hi;
+    }
+    let new_available_balance_ciphertext =
+        (available_balance_ciphertext + pending_balance_lo + shifted_pending_balance_hi).add_amount(deposit_amount);
+
+    let new_decryptable_available_balance = aes_key.encrypt(new_available_balance);
+    let expected_pending_balance_credit_counter =
+        ApplyPendingBalanceAccountInfo::new(confidential_transfer_account).pending_balance_credit_counter() + 1;
+
+    let deposit_instruction = deposit(
+        &spl_token_2022::id(),
+        &depositor_token_account,
+        &mint,
+        deposit_amount,
+        decimals,
+        &depositor_signer.pubkey(),
+        &[&depositor_signer.pubkey()],
+    )?;
+    let apply_pending_balance_instruction = apply_pending_balance(
+        &spl_token_2022::id(),
+        &depositor_token_account,
+        expected_pending_balance_credit_counter,
+        &new_decryptable_available_balance.clone().into(),
+        &depositor_signer.pubkey(),
+        &[&depositor_signer.pubkey()],
+    )?;
+
+    Ok(DepositAndApply {
+        instructions: vec![deposit_instruction, apply_pending_balance_instruction],
+        new_available_balance,
+        new_transfer_account_info: TransferAccountInfo {
+            available_balance: new_available_balance_ciphertext.into(),
+            decryptable_available_balance: new_decryptable_available_balance.into(),
+        },
+    })
+}
@@ -0,0 +1,68 @@
+use std::{error::Error, sync::Arc};
+
+use close_account::CloseAccountReceipt;
+use configure_credits::CreditPolicy;
+use solana_sdk::{pubkey::Pubkey, signer::Signer};
+
+/// Outcome of `migrate_account`.
+#[derive(Debug)]
+pub struct MigrationReceipt {
+    pub new_token_account: Pubkey,
+    /// Confidential balance transferred from the old account to the new one.
+    pub migrated_amount: u64,
+    /// Emptying and closing the old account; its public balance went to the new account.
+    pub close: CloseAccountReceipt,
+}
+
+/// Moves a confidential balance to a token account owned by `new_signer`, e.g. when the old
+/// account's derived ElGamal/AE keys may be compromised or the owner switches signer backends.
+///
+/// ElGamal and AE keys are derived from the owner's signer and can't be rotated in place, so the
+/// new signer gets its own configured account. The old account stops accepting confidential
+/// credits, applies its pending balance and confidentially transfers its whole available balance
+/// across. It is then emptied and closed, its public balance and anything credited in the meantime
+/// going to the new account and its rent to the old owner.
+pub async fn migrate_account(
+    old_signer: Arc<dyn Signer>,
+    new_signer: Arc<dyn Signer>,
+    use_durable_nonces: bool,
+) -> Result<MigrationReceipt, Box<dyn Error>> {
+    // Step 1. Create and configure the new account
+    let new_token_account = setup_token_account::setup_token_account(new_signer.as_ref(), use_durable_nonces).await?;
+
+    // Step 2. Freeze the old account's confidential balance: nothing new can land while it drains
+    let old_policy = configure_credits::credit_policy(&old_signer.pubkey())?;
+    configure_credits::configure_credits(
+        old_signer.as_ref(),
+        CreditPolicy {
+            allow_confidential_credits: false,
+            ..old_policy
+        },
+        use_durable_nonces,
+    ).await?;
+
+    // Step 3. Apply pending funds on the old account
+    let migrated_amount = apply_pending_balance::apply_pending_balance(old_signer.as_ref(), use_durable_nonces)
+        .await?
+        .new_available_balance;
+
+    // Step 4. Confidentially transfer the full available balance across
+    if migrated_amount > 0 {
+        transfer::with_split_proofs(old_signer.clone(), new_signer.clone(), migrated_amount, use_durable_nonces).await?;
+        apply_pending_balance::apply_pending_balance(new_signer.as_ref(), use_durable_nonces).await?;
+    }
+
+    // Step 5. Empty and close the old account
+    let close = close_account::close_account(old_signer, &new_token_account, false, use_durable_nonces).await?;
+
+    println!(
+        "Migrated {} confidential tokens to {}; closed {}",
+        migrated_amount, new_token_account, close.token_account
+    );
+
+    Ok(MigrationReceipt {
+        new_token_account,
+        migrated_amount,
+        close,
+    })
+}
@@ -1,9 +1,8 @@
 use std::error::Error;
 
-use utils::{get_or_create_keypair, get_rpc_client, print_transaction_url};
+use utils::{current_mint, get_or_create_keypair, get_rpc_client, nonce, print_transaction_url};
 use solana_sdk::{
     instruction::Instruction, pubkey::Pubkey, signature::Keypair, signer::Signer,
-    transaction::Transaction,
 };
 //use solana_zk_sdk::encryption::pod::elgamal::PodElGamalCiphertext;
 use spl_associated_token_account::get_associated_token_address_with_program_id;
@@ -26,12 +25,12 @@ pub async fn go_with_confidential_mintburn(
     Err("Not yet implemented".into())
 
     // let client = get_rpc_client()?;
-    // let mint = get_or_create_keypair("mint")?;
+    // let mint = current_mint()?;
     // let fee_payer_keypair = get_or_create_keypair("fee_payer_keypair")?;
 
     // let receiving_token_account = get_associated_token_address_with_program_id(
     //     &token_account_owner,
-    //     &mint.pubkey(),
+    //     &mint,
     //     &spl_token_2022::id(),
     // );
 
@@ -42,7 +41,7 @@ pub async fn go_with_confidential_mintburn(
     //     confidential_mint_burn::instruction::confidential_mint_with_split_proofs(
     //         &spl_token_2022::id(),
     //         &receiving_token_account,
-    //         &mint.pubkey(),
+    //         &mint,
     //         Some(supply_elgamal_pubkey.pubkey_owned()),
     //         &PodElGamalCiphertext::default(),
     //         &PodElGamalCiphertext::default(),
@@ -70,37 +69,41 @@ pub async fn go_with_confidential_mintburn(
     // Ok(())
 }
 
+/// Mints `mint_amount` to the owner's token account. With `use_durable_nonces`, the transaction uses
+/// one of the mint authority's durable nonces if it has one.
 pub async fn go(
     mint_authority: &Keypair,
     token_account_owner: &Pubkey,
-    mint_amount: u64
+    mint_amount: u64,
+    use_durable_nonces: bool,
 ) -> Result<(), Box<dyn Error>> {
     let client = get_rpc_client()?;
-    let mint = get_or_create_keypair("mint")?;
+    let mint = current_mint()?;
     let fee_payer_keypair = get_or_create_keypair("fee_payer_keypair")?;
 
     let receiving_token_account = get_associated_token_address_with_program_id(
         &token_account_owner, // Token account owner
-        &mint.pubkey(),        // Mint
+        &mint,        // Mint
         &spl_token_2022::id(),
     );
 
     // Instruction to mint tokens
     let mint_to_instruction: Instruction = mint_to(
         &spl_token_2022::id(),
-        &mint.pubkey(),              // Mint
+        &mint,              // Mint
         &receiving_token_account,     // Token account to mint to
         &mint_authority.pubkey(),         // Token account owner
         &[&mint_authority.pubkey()], // Additional signers (mint authority)
         mint_amount,                      // Amount to mint
     )?;
 
-    let transaction = Transaction::new_signed_with_payer(
+    let transaction = nonce::signed_transaction(
+        &client,
         &[mint_to_instruction],
-        Some(&fee_payer_keypair.pubkey()),
+        &fee_payer_keypair.pubkey(),
         &[&fee_payer_keypair, &mint_authority],
-        client.get_latest_blockhash()?,
-    );
+        &nonce::sequential_blockhash_source(&mint_authority.pubkey(), use_durable_nonces),
+    )?;
 
     let transaction_signature = client.send_and_confirm_transaction(&transaction)?;
 
@@ -0,0 +1,363 @@
+use {
+    crate::ConfidentialTransferFeeParams,
+    utils::{get_or_create_keypair, get_rpc_client, nonce::{self, BlockhashSource}, print_transaction_url},
+    solana_sdk::{
+        pubkey::Pubkey, signature::Keypair, signer::Signer, system_instruction::create_account, transaction::Transaction,
+    },
+    spl_token_2022::{
+        extension::{confidential_mint_burn, ExtensionType},
+        instruction::initialize_mint,
+        solana_zk_sdk::encryption::{auth_encryption::AeKey, elgamal::ElGamalPubkey, pod::elgamal::PodElGamalPubkey},
+        state::Mint,
+    },
+    spl_token_client::token::ExtensionInitializationParams,
+    std::error::Error,
+};
+
+/// Characters allowed in a base58 address, and so in a vanity prefix.
+const BASE58_ALPHABET: &str = "123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+
+/// Keypairs generated before giving up on a vanity prefix. Enough for about four characters; each
+/// extra character multiplies the expected attempts by 58.
+const MAX_GRIND_ATTEMPTS: u64 = 50_000_000;
+
+/// Where the mint account is created.
+enum MintAddress<'a> {
+    /// A fresh random address.
+    Random,
+    /// The address of the given signer, e.g. a pre-generated or HSM-held mint key.
+    Provided(&'a dyn Signer),
+    /// A fresh address starting with the given base58 prefix.
+    Vanity(String),
+}
+
+/// Configuration of a confidential transfer mint, built up with chained setters and created with
+/// `create`.
+///
+/// ```ignore
+/// let mint = MintConfig::new()
+///     .decimals(6)
+///     .mint_authority(&issuer)
+///     .confidential_transfer_authority(&compliance)
+///     .auditor(auditor_elgamal_keypair.pubkey())
+///     .auto_approve_new_accounts(false)
+///     .create()
+///     .await?;
+/// ```
+pub struct MintConfig<'a> {
+    decimals: u8,
+    mint_authority: Option<&'a dyn Signer>,
+    freeze_authority: Option<&'a dyn Signer>,
+    confidential_transfer_authority: Option<&'a dyn Signer>,
+    auditor_elgamal_pubkey: Option<ElGamalPubkey>,
+    auto_approve_new_accounts: bool,
+    mint_address: MintAddress<'a>,
+    payer: Option<&'a dyn Signer>,
+    transfer_fee: Option<&'a ConfidentialTransferFeeParams<'a>>,
+    confidential_mint_burn: bool,
+    use_durable_nonces: bool,
+}
+
+impl Default for MintConfig<'_> {
+    fn default() -> Self {
+        Self {
+            decimals: 2,
+            mint_authority: None,
+            freeze_authority: None,
+            confidential_transfer_authority: None,
+            auditor_elgamal_pubkey: None,
+            auto_approve_new_accounts: true,
+            mint_address: MintAddress::Random,
+            payer: None,
+            transfer_fee: None,
+            confidential_mint_burn: false,
+            use_durable_nonces: false,
+        }
+    }
+}
+
+impl<'a> MintConfig<'a> {
+    /// Two decimals, auto-approved accounts, no auditor, at a random address, paid by the fee payer keypair.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of base 10 digits to the right of the decimal point. Defaults to 2.
+    pub fn decimals(mut self, decimals: u8) -> Self {
+        self.decimals = decimals;
+        self
+    }
+
+    /// Authority to mint tokens. Defaults to the payer.
+    pub fn mint_authority(mut self, mint_authority: &'a dyn Signer) -> Self {
+        self.mint_authority = Some(mint_authority);
+        self
+    }
+
+    /// Authority to freeze token accounts. Without one, accounts can't be frozen.
+    pub fn freeze_authority(mut self, freeze_authority: &'a dyn Signer) -> Self {
+        self.freeze_authority = Some(freeze_authority);
+        self
+    }
+
+    /// Authority to update the `ConfidentialTransferMint` configuration, approve new accounts and,
+    /// with a transfer fee, configure fees and withdraw withheld fees. Without one, the
+    /// configuration is immutable.
+    pub fn confidential_transfer_authority(mut self, confidential_transfer_authority: &'a dyn Signer) -> Self {
+        self.confidential_transfer_authority = Some(confidential_transfer_authority);
+        self
+    }
+
+    /// ElGamal public key of the auditor, who can decrypt every confidential transfer amount.
+    pub fn auditor(mut self, auditor_elgamal_pubkey: &ElGamalPubkey) -> Self {
+        self.auditor_elgamal_pubkey = Some(*auditor_elgamal_pubkey);
+        self
+    }
+
+    /// If `false`, new accounts can't transact until the confidential transfer authority approves them.
+    pub fn auto_approve_new_accounts(mut self, auto_approve_new_accounts: bool) -> Self {
+        self.auto_approve_new_accounts = auto_approve_new_accounts;
+        self
+    }
+
+    /// Creates the mint at the signer's address.
+    pub fn mint_keypair(mut self, mint: &'a dyn Signer) -> Self {
+        self.mint_address = MintAddress::Provided(mint);
+        self
+    }
+
+    /// Creates the mint at a fresh address starting with `prefix`. Each extra character makes the
+    /// search about 58 times longer, so keep it to a few characters.
+    pub fn vanity_prefix(mut self, prefix: &str) -> Self {
+        self.mint_address = MintAddress::Vanity(prefix.to_string());
+        self
+    }
+
+    /// Pays for the transaction and the mint's rent. Defaults to the fee payer keypair.
+    pub fn payer(mut self, payer: &'a dyn Signer) -> Self {
+        self.payer = Some(payer);
+        self
+    }
+
+    /// Charges a fee on (confidential) transfers. Needs a confidential transfer authority, which
+    /// configures the fee and withdraws the withheld fees.
+    pub fn transfer_fee(mut self, transfer_fee: &'a ConfidentialTransferFeeParams<'a>) -> Self {
+        self.transfer_fee = Some(transfer_fee);
+        self
+    }
+
+    /// Mints and burns confidentially, with the supply encrypted under the auditor's key.
+    pub fn confidential_mint_burn(mut self, confidential_mint_burn: bool) -> Self {
+        self.confidential_mint_burn = confidential_mint_burn;
+        self
+    }
+
+    /// Signs the transaction against the payer's durable nonce, if it has one, instead of a recent blockhash.
+    pub fn use_durable_nonces(mut self, use_durable_nonces: bool) -> Self {
+        self.use_durable_nonces = use_durable_nonces;
+        self
+    }
+
+    /// Creates and initializes the mint. Returns its address.
+    ///
+    /// The mint is not recorded as the one the other ingredients work with; see `set_current_mint`.
+    pub async fn create(&self) -> Result<Pubkey, Box<dyn Error>> {
+        let client = get_rpc_client()?;
+        let fee_payer_keypair = get_or_create_keypair("fee_payer_keypair")?;
+        let payer = self.payer.unwrap_or(&fee_payer_keypair);
+        let blockhash_source = nonce::sequential_blockhash_source(&payer.pubkey(), self.use_durable_nonces);
+        let (mint, transaction) = self.prepare(payer, &blockhash_source).await?;
+
+        let transaction_signature = client.send_and_confirm_transaction(&transaction)?;
+        print_transaction_url("Create Mint Account", &transaction_signature.to_string());
+
+        Ok(mint.pubkey())
+    }
+
+    /// Creates the mint in a local fork of the current on-chain state, without submitting anything.
+    #[cfg(feature = "dry-run")]
+    pub async fn create_dry_run(&self) -> Result<utils::dry_run::DryRunReport, Box<dyn Error>> {
+        let fee_payer_keypair = get_or_create_keypair("fee_payer_keypair")?;
+        let payer = self.payer.unwrap_or(&fee_payer_keypair);
+        // The fork only holds the payer and the mint, so the dry run signs against a recent blockhash.
+        let (mint, transaction) = self.prepare(payer, &BlockhashSource::Recent).await?;
+
+        let mut dry_run = utils::dry_run::DryRun::fork(&[payer.pubkey(), mint.pubkey()], &[spl_token_2022::id()]).await?;
+        dry_run.process("Create Mint Account", transaction, &[payer, mint.as_ref()]).await?;
+
+        let report = dry_run.finish().await?;
+        report.print();
+        Ok(report)
+    }
+
+    /// The mint's signer and the signed transaction creating it, shared by `create` and `create_dry_run`.
+    async fn prepare(&self, payer: &dyn Signer, blockhash_source: &BlockhashSource) -> Result<(Box<dyn Signer + 'a>, Transaction), Box<dyn Error>> {
+        self.validate()?;
+        let mint: Box<dyn Signer + 'a> = match &self.mint_address {
+            MintAddress::Provided(mint) => Box::new(*mint),
+            address => Box::new(generate_mint_keypair(address).await?),
+        };
+
+        let transaction = self.transaction(payer, mint.as_ref(), blockhash_source)?;
+        Ok((mint, transaction))
+    }
+
+    /// Rejects configurations that would create a mint whose authorities can't do their job.
+    fn validate(&self) -> Result<(), &'static str> {
+        if self.confidential_transfer_authority.is_some() {
+            return Ok(());
+        }
+        if !self.auto_approve_new_accounts {
+            return Err("Accounts can't be approved without a confidential transfer authority");
+        }
+        if self.transfer_fee.is_some() {
+            return Err("Withheld fees can't be withdrawn without a confidential transfer authority");
+        }
+        Ok(())
+    }
+
+    /// Builds the transaction that creates and initializes the mint with its extensions.
+    fn transaction(&self, payer: &dyn Signer, mint: &dyn Signer, blockhash_source: &BlockhashSource) -> Result<Transaction, Box<dyn Error>> {
+        let client = get_rpc_client()?;
+        let mint_pubkey = mint.pubkey();
+        let authority = self.confidential_transfer_authority.map(|authority| authority.pubkey());
+
+        // ConfidentialTransferMint extension parameters
+        let mut extensions = vec![ExtensionInitializationParams::ConfidentialTransferMint {
+            authority,
+            auto_approve_new_accounts: self.auto_approve_new_accounts,
+            auditor_elgamal_pubkey: self.auditor_elgamal_pubkey.map(Into::into),
+        }];
+        let mut extension_types = vec![ExtensionType::ConfidentialTransferMint];
+
+        // TransferFeeConfig and ConfidentialTransferFeeConfig extension parameters (fees are optional)
+        if let Some(transfer_fee) = self.transfer_fee {
+            extensions.push(ExtensionInitializationParams::TransferFeeConfig {
+                transfer_fee_config_authority: authority,
+                withdraw_withheld_authority: authority,
+                transfer_fee_basis_points: transfer_fee.transfer_fee_basis_points,
+                maximum_fee: transfer_fee.maximum_fee,
+            });
+            extensions.push(ExtensionInitializationParams::ConfidentialTransferFeeConfig {
+                authority,
+                withdraw_withheld_authority_elgamal_pubkey: (*transfer_fee
+                    .withdraw_withheld_authority_elgamal_keypair
+                    .pubkey())
+                .into(),
+            });
+            extension_types.push(ExtensionType::TransferFeeConfig);
+            extension_types.push(ExtensionType::ConfidentialTransferFeeConfig);
+        }
+
+        if self.confidential_mint_burn {
+            extension_types.push(ExtensionType::ConfidentialMintBurn);
+        }
+
+        // Calculate the space required for the mint account with the extensions
+        let space = ExtensionType::try_calculate_account_len::<Mint>(&extension_types)?;
+
+        // Calculate the lamports required for the mint account
+        let rent = client.get_minimum_balance_for_rent_exemption(space)?;
+
+        // Instructions to create the mint account
+        let mut instructions = vec![create_account(
+            &payer.pubkey(),
+            &mint_pubkey,
+            rent,
+            space as u64,
+            &spl_token_2022::id(),
+        )];
+
+        for extension in extensions {
+            instructions.push(extension.instruction(&spl_token_2022::id(), &mint_pubkey)?);
+        }
+
+        // The confidential supply is encrypted under the auditor's key
+        if self.confidential_mint_burn {
+            let supply_elgamal_pubkey: PodElGamalPubkey = self
+                .auditor_elgamal_pubkey
+                .ok_or("Confidential mint/burn needs an auditor ElGamal key to encrypt the supply")?
+                .into();
+            instructions.push(confidential_mint_burn::instruction::initialize_mint(
+                &spl_token_2022::id(),
+                &mint_pubkey,
+                &supply_elgamal_pubkey,
+                &AeKey::new_rand().encrypt(0).into(),
+            )?);
+        }
+
+        // Initialize the mint account (after its extensions)
+        //TODO: Use program-2022/src/extension/confidential_transfer/instruction/initialize_mint()
+        let mint_authority = self.mint_authority.unwrap_or(payer).pubkey();
+        let freeze_authority = self.freeze_authority.map(|freeze_authority| freeze_authority.pubkey());
+        instructions.push(initialize_mint(
+            &spl_token_2022::id(),
+            &mint_pubkey,
+            &mint_authority,
+            freeze_authority.as_ref(),
+            self.decimals,
+        )?);
+
+        nonce::signed_transaction(
+            &client,
+            &instructions,
+            &payer.pubkey(),
+            &[payer, mint],
+            blockhash_source,
+        )
+    }
+}
+
+/// A fresh mint keypair, at a random address or one starting with the vanity prefix. Grinding runs
+/// on a blocking thread so it doesn't stall the async runtime.
+async fn generate_mint_keypair(address: &MintAddress<'_>) -> Result<Keypair, Box<dyn Error>> {
+    match address {
+        MintAddress::Vanity(prefix) => {
+            let prefix = prefix.clone();
+            Ok(tokio::task::spawn_blocking(move || grind_keypair(&prefix)).await??)
+        }
+        _ => Ok(Keypair::new()),
+    }
+}
+
+/// Generates keypairs until one's address starts with `prefix`, for at most `MAX_GRIND_ATTEMPTS`.
+fn grind_keypair(prefix: &str) -> Result<Keypair, String> {
+    if let Some(invalid) = prefix.chars().find(|c| !BASE58_ALPHABET.contains(*c)) {
+        return Err(format!("Vanity prefix {:?} contains {:?}, which never appears in an address", prefix, invalid));
+    }
+
+    (0..MAX_GRIND_ATTEMPTS)
+        .map(|_| Keypair::new())
+        .find(|keypair| keypair.pubkey().to_string().starts_with(prefix))
+        .ok_or_else(|| format!("No address starting with {:?} in {} attempts; try a shorter prefix", prefix, MAX_GRIND_ATTEMPTS))
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, spl_token_2022::solana_zk_sdk::encryption::elgamal::ElGamalKeypair};
+
+    #[test]
+    fn test_grind_keypair_matches_prefix() {
+        let keypair = grind_keypair("A").unwrap();
+        assert!(keypair.pubkey().to_string().starts_with('A'));
+    }
+
+    #[test]
+    fn test_grind_keypair_rejects_non_base58_prefix() {
+        assert!(grind_keypair("0x").is_err());
+    }
+
+    #[test]
+    fn test_transfer_fee_needs_confidential_transfer_authority() {
+        let withdraw_withheld_authority_elgamal_keypair = ElGamalKeypair::new_rand();
+        let transfer_fee = ConfidentialTransferFeeParams {
+            transfer_fee_basis_points: 100,
+            maximum_fee: 1_000,
+            withdraw_withheld_authority_elgamal_keypair: &withdraw_withheld_authority_elgamal_keypair,
+        };
+        let authority = Keypair::new();
+
+        assert!(MintConfig::new().transfer_fee(&transfer_fee).validate().is_err());
+        assert!(MintConfig::new().transfer_fee(&transfer_fee).confidential_transfer_authority(&authority).validate().is_ok());
+    }
+}
@@ -1,24 +1,26 @@
-use utils::get_rpc_client;
+use utils::{get_rpc_client, nonce};
 use {
     solana_sdk::{
-        pubkey::Pubkey, signature::Keypair
+        pubkey::Pubkey, signature::Keypair, signer::Signer, system_instruction
     },
     std::error::Error,
 };
 
-pub async fn setup_basic_participant(participant_pubkey: &Pubkey, fee_payer_keypair: Option<&Keypair>, initial_lamports: u64) -> Result<(), Box<dyn Error>> {
+/// Funds a participant from `fee_payer_keypair`, or by airdrop without one. With `use_durable_nonces`,
+/// the funding transfer uses one of the fee payer's durable nonces if it has one.
+pub async fn setup_basic_participant(participant_pubkey: &Pubkey, fee_payer_keypair: Option<&Keypair>, initial_lamports: u64, use_durable_nonces: bool) -> Result<(), Box<dyn Error>> {
 
     let client = get_rpc_client()?;
 
     match fee_payer_keypair {
         Some(keypair) => {
-            let recent_blockhash = client.get_latest_blockhash()?;
-            let tx = solana_sdk::system_transaction::transfer(
-                keypair,
-                participant_pubkey,
-                initial_lamports,
-                recent_blockhash,
-            );
+            let tx = nonce::signed_transaction(
+                &client,
+                &[system_instruction::transfer(&keypair.pubkey(), participant_pubkey, initial_lamports)],
+                &keypair.pubkey(),
+                &[keypair],
+                &nonce::sequential_blockhash_source(&keypair.pubkey(), use_durable_nonces),
+            )?;
             client.send_and_confirm_transaction(&tx)?;
         }
         None => {
@@ -47,7 +49,7 @@ mod tests {
     async fn test_setup_basic_participant() -> Result<(), Box<dyn Error>> {
         let participant_keypair = get_or_create_keypair("SOLO_TEST_participant_keypair")?;
 
-        setup_basic_participant(&participant_keypair.pubkey(), None, 2 * LAMPORTS_PER_SOL).await?;
+        setup_basic_participant(&participant_keypair.pubkey(), None, 2 * LAMPORTS_PER_SOL, false).await?;
         Ok(())
     }
 }
@@ -1,54 +1,241 @@
 use std::error::Error;
 
-use utils::{get_or_create_keypair, get_rpc_client, print_transaction_url};
-use solana_sdk::{signer::Signer, transaction::Transaction};
+use utils::{current_mint, get_or_create_keypair, get_rpc_client, nonce, nonce::BlockhashSource, print_transaction_url, required_signers};
+#[cfg(feature = "dry-run")]
+use utils::dry_run::{DryRun, DryRunReport};
+use solana_sdk::{
+    pubkey::Pubkey, signer::Signer, system_instruction::create_account, transaction::Transaction,
+};
 use spl_associated_token_account::{
-    get_associated_token_address_with_program_id, instruction::create_associated_token_account,
+    get_associated_token_address_with_program_id, instruction::create_associated_token_account_idempotent,
 };
 use spl_token_2022::{
     error::TokenError,
     extension::{
-        confidential_transfer::instruction::{configure_account, PubkeyValidityProofData},
-        ExtensionType,
+        confidential_transfer::{
+            instruction::{configure_account, PubkeyValidityProofData},
+            ConfidentialTransferAccount,
+        },
+        confidential_transfer_fee::ConfidentialTransferFeeConfig,
+        BaseStateWithExtensions, ExtensionType, StateWithExtensionsOwned,
     },
-    instruction::reallocate,
+    instruction::{initialize_account3, reallocate},
     solana_zk_sdk::encryption::{auth_encryption::AeKey, elgamal::ElGamalKeypair},
+    state::{Account, Mint},
 };
 use spl_token_confidential_transfer_proof_extraction::instruction::{ProofData, ProofLocation};
 
+/// Which token account to set up for the owner.
+pub enum TokenAccountAddress<'a> {
+    /// The owner's associated token account for the mint, created if missing.
+    Associated,
+    /// An account at the signer's address, created and initialized if missing.
+    /// The signer is only needed to create the account.
+    Keypair(&'a dyn Signer),
+    /// An existing token account of the owner, e.g. one created by another program.
+    Existing(Pubkey),
+}
+
+/// Options for `setup_token_account_with`.
+pub struct TokenAccountParams<'a> {
+    pub address: TokenAccountAddress<'a>,
+    /// The maximum number of `Deposit` and `Transfer` instructions that can
+    /// credit `pending_balance` before the `ApplyPendingBalance` instruction is executed
+    pub maximum_pending_balance_credit_counter: u64,
+    /// Pays for the transaction and the account's rent. Defaults to the fee payer keypair.
+    pub payer: Option<&'a dyn Signer>,
+    /// Sign against one of the owner's durable nonces, if it has one, instead of a recent blockhash.
+    pub use_durable_nonces: bool,
+}
+
+impl Default for TokenAccountParams<'_> {
+    fn default() -> Self {
+        Self {
+            address: TokenAccountAddress::Associated,
+            maximum_pending_balance_credit_counter: 65536,
+            payer: None,
+            use_durable_nonces: false,
+        }
+    }
+}
+
+/// The transaction completing a token account's setup, if anything is left to do.
+struct TokenAccountSetup {
+    token_account_pubkey: Pubkey,
+    transaction: Option<Transaction>,
+}
+
+/// Sets up the owner's associated token account for confidential transfers and returns its address.
 pub async fn setup_token_account(
-    token_account_authority: &dyn Signer
-) -> Result<(), Box<dyn Error>> {
+    token_account_authority: &dyn Signer,
+    use_durable_nonces: bool,
+) -> Result<Pubkey, Box<dyn Error>> {
+    let params = TokenAccountParams { use_durable_nonces, ..TokenAccountParams::default() };
+    setup_token_account_with(token_account_authority, &params).await
+}
 
+/// Creates (if missing) and configures (if not yet configured) a token account for confidential
+/// transfers, skipping whatever is already done. Returns the token account address.
+pub async fn setup_token_account_with(
+    token_account_authority: &dyn Signer,
+    params: &TokenAccountParams<'_>,
+) -> Result<Pubkey, Box<dyn Error>> {
     let client = get_rpc_client()?;
-    let mint = get_or_create_keypair("mint")?;
     let fee_payer_keypair = get_or_create_keypair("fee_payer_keypair")?;
+    let payer = params.payer.unwrap_or(&fee_payer_keypair);
 
-    // Associated token address of the sender
-    let token_account_pubkey = get_associated_token_address_with_program_id(
-        &token_account_authority.pubkey(), // Token account owner
-        &mint.pubkey(),                    // Mint
-        &spl_token_2022::id(),
-    );
+    let blockhash_source = nonce::sequential_blockhash_source(&token_account_authority.pubkey(), params.use_durable_nonces);
+    let TokenAccountSetup { token_account_pubkey, transaction } =
+        token_account_setup(token_account_authority, payer, params, &blockhash_source)?;
 
-    // Instruction to create associated token account
-    let create_associated_token_account_instruction = create_associated_token_account(
-        &fee_payer_keypair.pubkey(),       // Funding account
-        &token_account_authority.pubkey(), // Token account owner
-        &mint.pubkey(),                    // Mint
-        &spl_token_2022::id(),
-    );
+    match transaction {
+        Some(transaction) => {
+            let transaction_signature = client.send_and_confirm_transaction(&transaction)?;
+            print_transaction_url("Create Token Account", &transaction_signature.to_string());
+        }
+        None => println!("Token account {} is already configured", token_account_pubkey),
+    }
+
+    Ok(token_account_pubkey)
+}
 
-    // Instruction to reallocate the token account to include the `ConfidentialTransferAccount` extension
-    let reallocate_instruction = reallocate(
-        &spl_token_2022::id(),
-        &token_account_pubkey,                         // Token account
-        &fee_payer_keypair.pubkey(),                   // Payer
-        &token_account_authority.pubkey(),             // Token account owner
-        &[&token_account_authority.pubkey()],          // Signers
-        &[ExtensionType::ConfidentialTransferAccount], // Extension to reallocate space for
+/// Creates and configures the token account in a local fork of the current on-chain state,
+/// without submitting anything.
+#[cfg(feature = "dry-run")]
+pub async fn setup_token_account_dry_run(
+    token_account_authority: &dyn Signer
+) -> Result<DryRunReport, Box<dyn Error>> {
+    let mint = current_mint()?;
+    let fee_payer_keypair = get_or_create_keypair("fee_payer_keypair")?;
+
+    // The fork only holds the accounts below, so the dry run signs against a recent blockhash.
+    let TokenAccountSetup { token_account_pubkey, transaction } = token_account_setup(
+        token_account_authority,
+        &fee_payer_keypair,
+        &TokenAccountParams::default(),
+        &BlockhashSource::Recent,
     )?;
 
+    let mut dry_run = DryRun::fork(
+        &[fee_payer_keypair.pubkey(), token_account_pubkey, mint],
+        &[spl_token_2022::id(), spl_associated_token_account::id()],
+    ).await?;
+    if let Some(transaction) = transaction {
+        dry_run.process(
+            "Create Token Account",
+            transaction,
+            &[token_account_authority, &fee_payer_keypair as &dyn Signer],
+        ).await?;
+    }
+
+    let report = dry_run.finish().await?;
+    report.print();
+    Ok(report)
+}
+
+/// Builds the transaction that creates the token account if missing and configures it for
+/// confidential transfers if it isn't yet.
+fn token_account_setup(
+    token_account_authority: &dyn Signer,
+    payer: &dyn Signer,
+    params: &TokenAccountParams<'_>,
+    blockhash_source: &BlockhashSource,
+) -> Result<TokenAccountSetup, Box<dyn Error>> {
+    let client = get_rpc_client()?;
+    let mint = current_mint()?;
+
+    let token_account_pubkey = match &params.address {
+        TokenAccountAddress::Associated => get_associated_token_address_with_program_id(
+            &token_account_authority.pubkey(), // Token account owner
+            &mint,                    // Mint
+            &spl_token_2022::id(),
+        ),
+        TokenAccountAddress::Keypair(token_account_keypair) => token_account_keypair.pubkey(),
+        TokenAccountAddress::Existing(token_account_pubkey) => *token_account_pubkey,
+    };
+
+    // Mints charging confidential transfer fees also need space for the encrypted withheld fee
+    let mint_account = client.get_account(&mint)?;
+    let mut extension_types = vec![ExtensionType::ConfidentialTransferAccount];
+    if StateWithExtensionsOwned::<Mint>::unpack(mint_account.data)?
+        .get_extension::<ConfidentialTransferFeeConfig>()
+        .is_ok()
+    {
+        extension_types.push(ExtensionType::ConfidentialTransferFeeAmount);
+    }
+
+    let mut instructions = Vec::new();
+    let existing_account = client
+        .get_account_with_commitment(&token_account_pubkey, client.commitment())?
+        .value;
+
+    match existing_account {
+        Some(account) => {
+            let token_account = StateWithExtensionsOwned::<Account>::unpack(account.data)?;
+            if token_account.base.owner != token_account_authority.pubkey() || token_account.base.mint != mint {
+                return Err(format!(
+                    "Token account {} is not owned by {} for mint {}",
+                    token_account_pubkey,
+                    token_account_authority.pubkey(),
+                    mint
+                ).into());
+            }
+            if token_account.get_extension::<ConfidentialTransferAccount>().is_ok() {
+                return Ok(TokenAccountSetup { token_account_pubkey, transaction: None });
+            }
+
+            // Instruction to reallocate the token account to include the `ConfidentialTransferAccount` extension
+            instructions.push(reallocate(
+                &spl_token_2022::id(),
+                &token_account_pubkey,                         // Token account
+                &payer.pubkey(),                               // Payer
+                &token_account_authority.pubkey(),             // Token account owner
+                &[&token_account_authority.pubkey()],          // Signers
+                &extension_types,                              // Extensions to reallocate space for
+            )?);
+        }
+        None => match &params.address {
+            TokenAccountAddress::Associated => {
+                // Instructions to create the associated token account, then make room for the extensions
+                instructions.push(create_associated_token_account_idempotent(
+                    &payer.pubkey(),                   // Funding account
+                    &token_account_authority.pubkey(), // Token account owner
+                    &mint,                    // Mint
+                    &spl_token_2022::id(),
+                ));
+                instructions.push(reallocate(
+                    &spl_token_2022::id(),
+                    &token_account_pubkey,
+                    &payer.pubkey(),
+                    &token_account_authority.pubkey(),
+                    &[&token_account_authority.pubkey()],
+                    &extension_types,
+                )?);
+            }
+            TokenAccountAddress::Keypair(_) => {
+                // Allocate the account with room for the extensions up front
+                let space = ExtensionType::try_calculate_account_len::<Account>(&extension_types)?;
+                let rent = client.get_minimum_balance_for_rent_exemption(space)?;
+                instructions.push(create_account(
+                    &payer.pubkey(),
+                    &token_account_pubkey,
+                    rent,
+                    space as u64,
+                    &spl_token_2022::id(),
+                ));
+                instructions.push(initialize_account3(
+                    &spl_token_2022::id(),
+                    &token_account_pubkey,
+                    &mint,
+                    &token_account_authority.pubkey(),
+                )?);
+            }
+            TokenAccountAddress::Existing(_) => {
+                return Err(format!("Token account {} does not exist", token_account_pubkey).into());
+            }
+        },
+    }
+
     // Create the ElGamal keypair and AES key for the sender token account
     let token_account_authority_elgamal_keypair =
         ElGamalKeypair::new_from_signer(&token_account_authority, &token_account_pubkey.to_bytes())
@@ -56,10 +243,6 @@ pub async fn setup_token_account(
     let token_account_authority_aes_key =
         AeKey::new_from_signer(&token_account_authority, &token_account_pubkey.to_bytes()).unwrap();
 
-    // The maximum number of `Deposit` and `Transfer` instructions that can
-    // credit `pending_balance` before the `ApplyPendingBalance` instruction is executed
-    let maximum_pending_balance_credit_counter = 65536;
-
     // Initial token balance is 0
     let decryptable_balance = token_account_authority_aes_key.encrypt(0);
 
@@ -81,9 +264,9 @@ pub async fn setup_token_account(
     let configure_account_instruction = configure_account(
         &spl_token_2022::id(),                 // Program ID
         &token_account_pubkey,                 // Token account
-        &mint.pubkey(),                        // Mint
+        &mint,                        // Mint
         &decryptable_balance.into(),             // Initial balance
-        maximum_pending_balance_credit_counter, // Maximum pending balance credit counter
+        params.maximum_pending_balance_credit_counter, // Maximum pending balance credit counter
         &token_account_authority.pubkey(),     // Token Account Owner
         &[],                                   // Additional signers
         proof_location,                         // Proof location
@@ -91,25 +274,26 @@ pub async fn setup_token_account(
     .unwrap();
 
     // Instructions to configure account must come after `initialize_account` instruction
-    let mut instructions = vec![
-        create_associated_token_account_instruction,
-        reallocate_instruction,
-    ];
     instructions.extend(configure_account_instruction);
 
-    let recent_blockhash = client.get_latest_blockhash()?;
-    let transaction = Transaction::new_signed_with_payer(
-        &instructions,
-        Some(&fee_payer_keypair.pubkey()),
-        &[&token_account_authority, &fee_payer_keypair as &dyn Signer],
-        recent_blockhash,
-    );
-
-    let transaction_signature = client.send_and_confirm_transaction(&transaction)?;
+    let mut signers = vec![token_account_authority, payer];
+    if let TokenAccountAddress::Keypair(token_account_keypair) = &params.address {
+        signers.push(*token_account_keypair);
+    }
+    let message = Transaction::new_with_payer(&instructions, Some(&payer.pubkey())).message;
 
-    print_transaction_url("Create Token Account", &transaction_signature.to_string());
+    let transaction = nonce::signed_transaction(
+        &client,
+        &instructions,
+        &payer.pubkey(),
+        &required_signers(&message, &signers)[..],
+        blockhash_source,
+    )?;
 
-    Ok(())
+    Ok(TokenAccountSetup {
+        token_account_pubkey,
+        transaction: Some(transaction),
+    })
 }
 
 #[cfg(test)]
@@ -120,7 +304,10 @@ mod tests {
     async fn test_setup_token_account() -> Result<(), Box<dyn Error>> {
         let sender_keypair = get_or_create_keypair("sender_keypair")?;
 
-        setup_token_account(&sender_keypair).await?;
+        let token_account = setup_token_account(&sender_keypair, false).await?;
+
+        // Setting up an already configured account is a no-op
+        assert_eq!(setup_token_account(&sender_keypair, false).await?, token_account);
         Ok(())
     }
 }
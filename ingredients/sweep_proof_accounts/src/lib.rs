@@ -0,0 +1,194 @@
+use std::{collections::HashSet, error::Error};
+
+use transfer::plan::TransferPlan;
+use utils::{get_rpc_client, nonce, print_transaction_url};
+use solana_client::{
+    rpc_config::{RpcAccountInfoConfig, RpcProgramAccountsConfig},
+    rpc_filter::{Memcmp, RpcFilterType},
+};
+use solana_sdk::{pubkey::Pubkey, signer::Signer};
+use spl_token_2022::solana_zk_sdk::zk_elgamal_proof_program::{
+    self,
+    instruction::{close_context_state, ContextStateInfo},
+    proof_data::ProofType,
+    state::ProofContextStateMeta,
+};
+
+/// Number of `CloseContextState` instructions packed into each sweep transaction.
+const CLOSE_BATCH_SIZE: usize = 10;
+
+/// A ZK ElGamal proof context state account still holding rent.
+#[derive(Debug)]
+pub struct ProofContextAccount {
+    pub address: Pubkey,
+    pub proof_type: ProofType,
+    pub lamports: u64,
+    pub data_len: usize,
+}
+
+/// Finds every proof context state account whose `context_state_authority` is `authority`.
+///
+/// Accounts that were allocated but never had a proof verified into them are zeroed, so they
+/// neither match this filter nor can be closed by the authority.
+pub fn find_proof_context_accounts(authority: &Pubkey) -> Result<Vec<ProofContextAccount>, Box<dyn Error>> {
+    let client = get_rpc_client()?;
+
+    // `context_state_authority` is the first field of the context state header.
+    let config = RpcProgramAccountsConfig {
+        filters: Some(vec![RpcFilterType::Memcmp(Memcmp::new_base58_encoded(
+            0,
+            &authority.to_bytes(),
+        ))]),
+        account_config: RpcAccountInfoConfig::default(),
+        ..RpcProgramAccountsConfig::default()
+    };
+
+    let accounts = client.get_program_accounts_with_config(&zk_elgamal_proof_program::id(), config)?;
+
+    accounts
+        .into_iter()
+        .map(|(address, account)| {
+            let meta = ProofContextStateMeta::try_from_bytes(&account.data)?;
+            Ok(ProofContextAccount {
+                address,
+                proof_type: ProofType::try_from(meta.proof_type)?,
+                lamports: account.lamports,
+                data_len: account.data.len(),
+            })
+        })
+        .collect()
+}
+
+/// The accounts that are safe to close: those not referenced by an unfinished transfer plan, which
+/// still needs them to resume (or to abort).
+fn sweepable_accounts(accounts: Vec<ProofContextAccount>, plans: &[TransferPlan]) -> Vec<ProofContextAccount> {
+    let reserved: HashSet<String> = plans
+        .iter()
+        .filter(|plan| plan.is_unfinished())
+        .flat_map(|plan| plan.context_state_accounts.iter().cloned())
+        .collect();
+
+    accounts
+        .into_iter()
+        .filter(|account| {
+            let in_use = reserved.contains(&account.address.to_string());
+            if in_use {
+                println!("Skipping proof context state account {} of an unfinished transfer plan", account.address);
+            }
+            !in_use
+        })
+        .collect()
+}
+
+/// Closes every proof context state account owned by `authority`, sending the reclaimed lamports to `destination`.
+/// Accounts of the authority's unfinished transfer plans are left alone; resume or abort those plans instead.
+/// Returns the total number of lamports reclaimed.
+///
+/// With `use_durable_nonces`, the close batches share one of the authority's durable nonces if it has one.
+pub async fn sweep_proof_context_accounts(
+    authority: &dyn Signer,
+    destination: &Pubkey,
+    use_durable_nonces: bool,
+) -> Result<u64, Box<dyn Error>> {
+    let client = get_rpc_client()?;
+    let proof_context_accounts = sweepable_accounts(
+        find_proof_context_accounts(&authority.pubkey())?,
+        &TransferPlan::plans_of(&authority.pubkey()),
+    );
+
+    if proof_context_accounts.is_empty() {
+        println!("No proof context state accounts found for {}", authority.pubkey());
+        return Ok(0);
+    }
+
+    for account in &proof_context_accounts {
+        println!(
+            "Proof context state account {}: {:?}, {} bytes, {} lamports",
+            account.address, account.proof_type, account.data_len, account.lamports
+        );
+    }
+
+    let authority_pubkey = authority.pubkey();
+    let blockhash_source = nonce::sequential_blockhash_source(&authority_pubkey, use_durable_nonces);
+    let mut reclaimed_lamports = 0;
+
+    for batch in proof_context_accounts.chunks(CLOSE_BATCH_SIZE) {
+        let close_instructions: Vec<_> = batch
+            .iter()
+            .map(|account| {
+                close_context_state(
+                    ContextStateInfo {
+                        context_state_account: &account.address,
+                        context_state_authority: &authority_pubkey,
+                    },
+                    destination,
+                )
+            })
+            .collect();
+
+        let transaction = nonce::signed_transaction(
+            &client,
+            &close_instructions,
+            &authority_pubkey,
+            &[&authority],
+            &blockhash_source,
+        )?;
+
+        let transaction_signature = client.send_and_confirm_transaction(&transaction)?;
+        print_transaction_url("Close Proof Context State Accounts", &transaction_signature.to_string());
+
+        reclaimed_lamports += batch.iter().map(|account| account.lamports).sum::<u64>();
+    }
+
+    println!("Reclaimed {} lamports from {} proof context state accounts", reclaimed_lamports, proof_context_accounts.len());
+
+    Ok(reclaimed_lamports)
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, solana_sdk::signature::Signature, transfer::plan::TRANSFER_STAGES};
+
+    fn account(address: Pubkey) -> ProofContextAccount {
+        ProofContextAccount {
+            address,
+            proof_type: ProofType::CiphertextCommitmentEquality,
+            lamports: 1,
+            data_len: 0,
+        }
+    }
+
+    fn plan_with_accounts(accounts: &[Pubkey], completed_stages: usize, aborted: bool) -> TransferPlan {
+        let mut plan = TransferPlan::new(&Pubkey::new_unique(), &Pubkey::new_unique(), 1);
+        plan.context_state_accounts = accounts.iter().map(Pubkey::to_string).collect();
+        plan.completed_stages = (0..completed_stages).map(|_| Signature::new_unique().to_string()).collect();
+        plan.aborted = aborted;
+        plan
+    }
+
+    #[test]
+    fn test_sweep_skips_accounts_of_unfinished_plans() {
+        let in_flight = Pubkey::new_unique();
+        let executed_not_closed = Pubkey::new_unique();
+        let aborted = Pubkey::new_unique();
+        let completed = Pubkey::new_unique();
+        let orphaned = Pubkey::new_unique();
+
+        let plans = [
+            plan_with_accounts(&[in_flight], 2, false),
+            plan_with_accounts(&[executed_not_closed], TRANSFER_STAGES.len() - 1, false),
+            plan_with_accounts(&[aborted], 2, true),
+            plan_with_accounts(&[completed], TRANSFER_STAGES.len(), false),
+        ];
+
+        let swept: Vec<Pubkey> = sweepable_accounts(
+            [in_flight, executed_not_closed, aborted, completed, orphaned].into_iter().map(account).collect(),
+            &plans,
+        )
+        .into_iter()
+        .map(|account| account.address)
+        .collect();
+
+        assert_eq!(swept, vec![aborted, completed, orphaned]);
+    }
+}
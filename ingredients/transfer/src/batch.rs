@@ -0,0 +1,266 @@
+use {
+    crate::{
+        close_proof_accounts_instructions,
+        plan::{EXECUTE_TRANSFER_STAGE, TRANSFER_STAGES},
+        prepare_transactions, transfer_stage_instructions, PreparedTransfer, ProofContextKeypairs,
+    },
+    solana_client::nonblocking::rpc_client::RpcClient,
+    solana_sdk::{
+        pubkey::Pubkey,
+        signature::{Signature, Signer},
+        transaction::Transaction,
+    },
+    spl_token_2022::extension::confidential_transfer::account_info::TransferAccountInfo,
+    std::{error::Error, sync::Arc},
+    utils::{get_non_blocking_rpc_client, print_transaction_url},
+};
+
+/// Outcome of a single recipient's transfer within a batch.
+#[derive(Debug)]
+pub enum BatchTransferOutcome {
+    Transferred { signature: Signature },
+    Failed { error: String },
+}
+
+#[derive(Debug)]
+pub struct BatchTransferResult {
+    pub recipient: Pubkey,
+    pub amount: u64,
+    pub outcome: BatchTransferOutcome,
+}
+
+/// A transfer whose proof accounts are allocated and encoded on-chain, ready to execute.
+struct EncodedTransfer {
+    prepared: PreparedTransfer,
+    context_keypairs: ProofContextKeypairs,
+}
+
+/// Confidentially transfers to many recipients (e.g. payroll) from one sender token account.
+///
+/// Transfers execute strictly in order, since each one debits the same available balance. The next
+/// recipient's proofs are generated against the balance the sender *will* have once the current
+/// transfer lands, so its proof accounts can be allocated and encoded while the current transfer
+/// executes.
+///
+/// If a transfer fails, the proofs built on top of it are discarded and the remaining recipients
+/// are re-proven against the sender's on-chain balance, so one bad recipient doesn't derail the batch.
+///
+/// With `use_durable_nonces`, each transfer's transactions are signed against the sender's durable
+/// nonces (when it has enough), so a transfer encoded ahead of time can't expire while it waits.
+pub async fn with_split_proofs_batch(
+    sender_keypair: Arc<dyn Signer>,
+    transfers: &[(Pubkey, u64)],
+    use_durable_nonces: bool,
+) -> Result<Vec<BatchTransferResult>, Box<dyn Error>> {
+    let rpc_client = get_non_blocking_rpc_client()?;
+    let mut results = Vec::with_capacity(transfers.len());
+
+    let mut next_encoded = match transfers.first() {
+        Some((recipient, amount)) => Some(prepare_and_encode(&rpc_client, &sender_keypair, recipient, *amount, None, use_durable_nonces).await),
+        None => None,
+    };
+
+    for (index, (recipient, amount)) in transfers.iter().enumerate() {
+        let following = transfers.get(index + 1);
+
+        let current = match next_encoded.take() {
+            Some(Ok(current)) => current,
+            Some(Err(e)) => {
+                println!("Batch transfer to {} failed while encoding proofs: {}", recipient, e);
+                results.push(BatchTransferResult {
+                    recipient: *recipient,
+                    amount: *amount,
+                    outcome: BatchTransferOutcome::Failed { error: e.to_string() },
+                });
+
+                // Nothing was executed, so the on-chain balance is still authoritative.
+                next_encoded = match following {
+                    Some((recipient, amount)) => Some(prepare_and_encode(&rpc_client, &sender_keypair, recipient, *amount, None, use_durable_nonces).await),
+                    None => None,
+                };
+                continue;
+            }
+            None => unreachable!("every recipient is prepared before its turn"),
+        };
+
+        // Execute the current transfer on its own task, so the following recipient's proofs are
+        // generated and encoded (which also makes blocking RPC calls) while it lands.
+        let execute_transaction = current.prepared.transactions[EXECUTE_TRANSFER_STAGE].clone();
+        let execute = tokio::spawn(async move {
+            let rpc_client = get_non_blocking_rpc_client().map_err(|e| e.to_string())?;
+            send_and_confirm_or_landed(&rpc_client, &execute_transaction).await
+        });
+
+        let expected_sender_transfer_account_info = current.prepared.new_sender_transfer_account_info;
+        let following_result = match following {
+            Some((recipient, amount)) => Some(
+                prepare_and_encode(
+                    &rpc_client,
+                    &sender_keypair,
+                    recipient,
+                    *amount,
+                    Some(expected_sender_transfer_account_info),
+                    use_durable_nonces,
+                )
+                .await,
+            ),
+            None => None,
+        };
+
+        let execute_result = match execute.await {
+            Ok(execute_result) => execute_result,
+            Err(e) => Err(e.to_string()),
+        };
+        if let Ok(signature) = &execute_result {
+            print_transaction_url("Batch Transfer [Execute Transfer]", &signature.to_string());
+        }
+
+        // The current proof accounts are done with either way; the prepared close transaction may
+        // have an expired blockhash by now, so build a fresh one.
+        match close_proof_accounts(&rpc_client, &sender_keypair, &current.context_keypairs).await {
+            Ok(signature) => print_transaction_url("Batch Transfer [Close Proof Accounts]", &signature.to_string()),
+            Err(e) => println!("Failed to close proof accounts: {}", e),
+        }
+
+        match execute_result {
+            Ok(signature) => {
+                results.push(BatchTransferResult {
+                    recipient: *recipient,
+                    amount: *amount,
+                    outcome: BatchTransferOutcome::Transferred { signature },
+                });
+                next_encoded = following_result;
+            }
+            Err(e) => {
+                println!("Batch transfer to {} failed: {}", recipient, e);
+                results.push(BatchTransferResult {
+                    recipient: *recipient,
+                    amount: *amount,
+                    outcome: BatchTransferOutcome::Failed { error: e },
+                });
+
+                // The following proofs assumed this transfer would land; they no longer match the sender's balance.
+                if let Some(Ok(stale)) = following_result {
+                    if let Err(e) = close_proof_accounts(&rpc_client, &sender_keypair, &stale.context_keypairs).await {
+                        println!("Failed to close stale proof accounts: {}", e);
+                    }
+                }
+                next_encoded = match following {
+                    Some((recipient, amount)) => Some(prepare_and_encode(&rpc_client, &sender_keypair, recipient, *amount, None, use_durable_nonces).await),
+                    None => None,
+                };
+            }
+        }
+    }
+
+    let transferred = results
+        .iter()
+        .filter(|result| matches!(result.outcome, BatchTransferOutcome::Transferred { .. }))
+        .count();
+    println!("Batch transfer complete: {} of {} recipients paid", transferred, results.len());
+
+    Ok(results)
+}
+
+/// Generates a transfer's proofs and encodes them into freshly allocated context state accounts.
+async fn prepare_and_encode(
+    rpc_client: &RpcClient,
+    sender_keypair: &Arc<dyn Signer>,
+    recipient: &Pubkey,
+    amount: u64,
+    sender_transfer_account_info: Option<TransferAccountInfo>,
+    use_durable_nonces: bool,
+) -> Result<EncodedTransfer, Box<dyn Error>> {
+    let context_keypairs = ProofContextKeypairs::generate();
+    let prepared = prepare_transactions(
+        sender_keypair.clone(),
+        recipient,
+        amount,
+        &context_keypairs,
+        sender_transfer_account_info,
+        use_durable_nonces,
+    )
+    .await?;
+
+    let allocate_signature = rpc_client.send_and_confirm_transaction(&prepared.transactions[0]).await?;
+    print_transaction_url(&format!("Batch Transfer to {} [Allocate Proof Accounts]", recipient), &allocate_signature.to_string());
+
+    for stage in 1..EXECUTE_TRANSFER_STAGE {
+        match rpc_client.send_and_confirm_transaction(&prepared.transactions[stage]).await {
+            Ok(signature) => {
+                print_transaction_url(&format!("Batch Transfer to {} [{}]", recipient, TRANSFER_STAGES[stage]), &signature.to_string());
+            }
+            Err(e) => {
+                let reclaimed = match reclaim_proof_accounts(rpc_client, sender_keypair, recipient, &context_keypairs, &prepared, stage).await {
+                    Ok(signature) => format!("proof accounts closed in {}", signature),
+                    Err(e) => format!("proof accounts (equality {}) left allocated: {}", context_keypairs.equality.pubkey(), e),
+                };
+                return Err(format!("{} failed ({}): {}", TRANSFER_STAGES[stage], reclaimed, e).into());
+            }
+        }
+    }
+
+    Ok(EncodedTransfer { prepared, context_keypairs })
+}
+
+/// Sends a transaction, treating a send or confirmation error as success if the transaction
+/// actually landed (e.g. confirmation timed out).
+async fn send_and_confirm_or_landed(rpc_client: &RpcClient, transaction: &Transaction) -> Result<Signature, String> {
+    let error = match rpc_client.send_and_confirm_transaction(transaction).await {
+        Ok(signature) => return Ok(signature),
+        Err(e) => e.to_string(),
+    };
+
+    let signature = transaction.signatures[0];
+    match rpc_client.get_signature_status(&signature).await {
+        Ok(Some(Ok(()))) => {
+            println!("Transaction {} landed despite: {}", signature, error);
+            Ok(signature)
+        }
+        _ => Err(error),
+    }
+}
+
+/// Closes the proof accounts of a transfer whose encoding stopped at `failed_stage`. Only accounts
+/// holding a verified proof can be closed, so the remaining proofs are encoded first.
+async fn reclaim_proof_accounts(
+    rpc_client: &RpcClient,
+    sender_keypair: &Arc<dyn Signer>,
+    recipient: &Pubkey,
+    context_keypairs: &ProofContextKeypairs,
+    prepared: &PreparedTransfer,
+    failed_stage: usize,
+) -> Result<Signature, Box<dyn Error>> {
+    let stages = transfer_stage_instructions(
+        &sender_keypair.pubkey(),
+        recipient,
+        &context_keypairs.pubkeys(),
+        &prepared.proofs,
+        &[],
+    )?;
+    for instructions in &stages[failed_stage..EXECUTE_TRANSFER_STAGE] {
+        let transaction = Transaction::new_signed_with_payer(
+            instructions,
+            Some(&sender_keypair.pubkey()),
+            &[sender_keypair],
+            rpc_client.get_latest_blockhash().await?,
+        );
+        rpc_client.send_and_confirm_transaction(&transaction).await?;
+    }
+
+    close_proof_accounts(rpc_client, sender_keypair, context_keypairs).await
+}
+
+async fn close_proof_accounts(
+    rpc_client: &RpcClient,
+    sender_keypair: &Arc<dyn Signer>,
+    context_keypairs: &ProofContextKeypairs,
+) -> Result<Signature, Box<dyn Error>> {
+    let transaction = Transaction::new_signed_with_payer(
+        &close_proof_accounts_instructions(&sender_keypair.pubkey(), &sender_keypair.pubkey(), &context_keypairs.pubkeys()),
+        Some(&sender_keypair.pubkey()),
+        &[sender_keypair],
+        rpc_client.get_latest_blockhash().await?,
+    );
+    Ok(rpc_client.send_and_confirm_transaction(&transaction).await?)
+}
@@ -0,0 +1,401 @@
+use {
+    crate::{close_proof_accounts_instructions, preflight},
+    solana_client::rpc_client::RpcClient,
+    solana_sdk::{
+        instruction::Instruction,
+        pubkey::Pubkey,
+        signature::{Keypair, Signature, Signer},
+        system_instruction,
+    },
+    spl_associated_token_account::get_associated_token_address_with_program_id,
+    spl_record::state::RecordData,
+    spl_token_2022::{
+        extension::{
+            confidential_transfer::{
+                account_info::TransferAccountInfo, instruction::transfer_with_fee, ConfidentialTransferAccount,
+            },
+            confidential_transfer_fee::ConfidentialTransferFeeConfig,
+            transfer_fee::TransferFeeConfig,
+            BaseStateWithExtensions, StateWithExtensionsOwned,
+        },
+        solana_zk_sdk::{
+            encryption::{auth_encryption::AeKey, elgamal::{ElGamalKeypair, ElGamalPubkey}},
+            zk_elgamal_proof_program::{
+                instruction::ContextStateInfo,
+                proof_data::{BatchedRangeProofU256Data, ZkProofData},
+            },
+        },
+        state::{Account, Mint},
+    },
+    spl_token_confidential_transfer_proof_extraction::instruction::{zk_proof_type_to_instruction, ProofLocation},
+    spl_token_confidential_transfer_proof_generation::transfer_with_fee::TransferWithFeeProofData,
+    std::{error::Error, sync::Arc},
+    utils::{
+        current_mint, get_rpc_client,
+        nonce::{self, BlockhashSource},
+        print_transaction_url, record_value,
+        proof_context::context_state_instructions,
+        proof_generation::run_proof_job,
+    },
+};
+
+/// Bytes of proof data written into the record account per transaction.
+const RECORD_WRITE_CHUNK_SIZE: usize = 800;
+
+/// One transaction of a transfer with fee.
+struct FeeTransferStage<'a> {
+    label: String,
+    instructions: Vec<Instruction>,
+    /// Accounts created by this stage, which must sign it alongside the sender.
+    new_account_signers: Vec<&'a Keypair>,
+    /// The context state account that holds a verified proof once this stage lands.
+    encodes: Option<Pubkey>,
+}
+
+/// Confidential transfer on a mint with the `ConfidentialTransferFeeConfig` extension.
+///
+/// On top of the regular transfer proofs, the sender proves that the fee was computed from the
+/// mint's current fee rate (fee sigma, i.e. percentage-with-cap proof) and that the fee ciphertexts
+/// are valid for the recipient and the withdraw withheld authority (fee ciphertext validity proof).
+///
+/// The U256 range proof doesn't fit in a transaction with its verify instruction, so it is uploaded
+/// in chunks to a record account and verified from there. Every context state account is allocated
+/// in the same transaction that verifies its proof, so any account that exists can be closed: if a
+/// stage fails before the transfer executes, the accounts created so far are closed again.
+///
+/// The stages are sent one after another, so with `use_durable_nonces` they all share the sender's nonce.
+pub async fn transfer_with_fee(
+    sender_keypair: Arc<dyn Signer>,
+    recipient_keypair: Arc<dyn Signer>,
+    confidential_transfer_amount: u64,
+    use_durable_nonces: bool,
+) -> Result<(), Box<dyn Error>> {
+    let client = get_rpc_client()?;
+    let blockhash_source = nonce::sequential_blockhash_source(&sender_keypair.pubkey(), use_durable_nonces);
+
+    let mint = current_mint()?;
+
+    let sender_associated_token_address = get_associated_token_address_with_program_id(
+        &sender_keypair.pubkey(),
+        &mint,
+        &spl_token_2022::id(),
+    );
+    let recipient_associated_token_address = get_associated_token_address_with_program_id(
+        &recipient_keypair.pubkey(),
+        &mint,
+        &spl_token_2022::id(),
+    );
+
+    let sender_elgamal_keypair =
+        ElGamalKeypair::new_from_signer(&sender_keypair, &sender_associated_token_address.to_bytes())?;
+    let sender_aes_key =
+        AeKey::new_from_signer(&sender_keypair, &sender_associated_token_address.to_bytes())?;
+
+    // ConfidentialTransferAccount extension information needed to create proof data
+    let sender_transfer_account_info = TransferAccountInfo::new(
+        StateWithExtensionsOwned::<Account>::unpack(client.get_account(&sender_associated_token_address)?.data)?
+            .get_extension::<ConfidentialTransferAccount>()?,
+    );
+    let recipient_account = StateWithExtensionsOwned::<Account>::unpack(client.get_account(&recipient_associated_token_address)?.data)?;
+    let mint_state = StateWithExtensionsOwned::<Mint>::unpack(client.get_account(&mint)?.data)?;
+
+    // Pre-flight: fail before spending fees and rent on a transfer that can't succeed
+    let preflight::PreflightKeys {
+        recipient_elgamal_pubkey,
+        auditor_elgamal_pubkey,
+    } = preflight::check_transfer(
+        &sender_transfer_account_info,
+        &sender_aes_key,
+        &recipient_account,
+        &mint_state,
+        confidential_transfer_amount,
+    )?;
+
+    let withdraw_withheld_authority_elgamal_pubkey: ElGamalPubkey = mint_state
+        .get_extension::<ConfidentialTransferFeeConfig>()?
+        .withdraw_withheld_authority_elgamal_pubkey
+        .try_into()?;
+
+    // The fee in effect for the current epoch
+    let epoch = client.get_epoch_info()?.epoch;
+    let transfer_fee = *mint_state.get_extension::<TransferFeeConfig>()?.get_epoch_fee(epoch);
+    let transfer_fee_basis_points = u16::from(transfer_fee.transfer_fee_basis_points);
+    let maximum_fee = u64::from(transfer_fee.maximum_fee);
+
+    // Generate proof data on the dedicated proof pool
+    let TransferWithFeeProofData {
+        equality_proof_data,
+        transfer_amount_ciphertext_validity_proof_data_with_ciphertext,
+        percentage_with_cap_proof_data,
+        fee_ciphertext_validity_proof_data,
+        range_proof_data,
+    } = {
+        let sender_elgamal_keypair = sender_elgamal_keypair.clone();
+        let sender_aes_key = sender_aes_key.clone();
+
+        run_proof_job("transfer_with_fee", move || {
+            sender_transfer_account_info.generate_split_transfer_with_fee_proof_data(
+                confidential_transfer_amount,
+                &sender_elgamal_keypair,
+                &sender_aes_key,
+                &recipient_elgamal_pubkey,
+                Some(&auditor_elgamal_pubkey),
+                &withdraw_withheld_authority_elgamal_pubkey,
+                transfer_fee_basis_points,
+                maximum_fee,
+            )
+        }).await??
+    };
+
+    // Context state accounts for the five proofs, closable by the sender, and the record account
+    // the range proof is uploaded to
+    let equality_proof_context_state_account = Keypair::new();
+    let transfer_amount_ciphertext_validity_proof_context_state_account = Keypair::new();
+    let fee_sigma_proof_context_state_account = Keypair::new();
+    let fee_ciphertext_validity_proof_context_state_account = Keypair::new();
+    let range_proof_context_state_account = Keypair::new();
+    let range_proof_record_account = Keypair::new();
+    let context_state_authority_pubkey = sender_keypair.pubkey();
+
+    let equality_proof_pubkey = equality_proof_context_state_account.pubkey();
+    let transfer_amount_cv_proof_pubkey = transfer_amount_ciphertext_validity_proof_context_state_account.pubkey();
+    let fee_sigma_proof_pubkey = fee_sigma_proof_context_state_account.pubkey();
+    let fee_cv_proof_pubkey = fee_ciphertext_validity_proof_context_state_account.pubkey();
+    let range_proof_pubkey = range_proof_context_state_account.pubkey();
+    let record_pubkey = range_proof_record_account.pubkey();
+
+    let mut stages = range_proof_record_stages(
+        &client,
+        &sender_keypair.pubkey(),
+        &range_proof_record_account,
+        &range_proof_context_state_account,
+        &range_proof_data,
+    )?;
+
+    let (equality_create_ix, equality_verify_ix, _) = context_state_instructions(
+        &client,
+        &sender_keypair.pubkey(),
+        &equality_proof_pubkey,
+        &context_state_authority_pubkey,
+        &equality_proof_data,
+    )?;
+    let (transfer_amount_cv_create_ix, transfer_amount_cv_verify_ix, _) = context_state_instructions(
+        &client,
+        &sender_keypair.pubkey(),
+        &transfer_amount_cv_proof_pubkey,
+        &context_state_authority_pubkey,
+        &transfer_amount_ciphertext_validity_proof_data_with_ciphertext.proof_data,
+    )?;
+    let (fee_sigma_create_ix, fee_sigma_verify_ix, _) = context_state_instructions(
+        &client,
+        &sender_keypair.pubkey(),
+        &fee_sigma_proof_pubkey,
+        &context_state_authority_pubkey,
+        &percentage_with_cap_proof_data,
+    )?;
+    let (fee_cv_create_ix, fee_cv_verify_ix, _) = context_state_instructions(
+        &client,
+        &sender_keypair.pubkey(),
+        &fee_cv_proof_pubkey,
+        &context_state_authority_pubkey,
+        &fee_ciphertext_validity_proof_data,
+    )?;
+
+    // Each remaining proof is allocated and encoded on its own; pairing any two exceeds the packet size.
+    for (label, create_ix, verify_ix, context_state_account) in [
+        ("Encode Equality Proof", equality_create_ix, equality_verify_ix, &equality_proof_context_state_account),
+        ("Encode Transfer Amount Validity Proof", transfer_amount_cv_create_ix, transfer_amount_cv_verify_ix, &transfer_amount_ciphertext_validity_proof_context_state_account),
+        ("Encode Fee Sigma Proof", fee_sigma_create_ix, fee_sigma_verify_ix, &fee_sigma_proof_context_state_account),
+        ("Encode Fee Validity Proof", fee_cv_create_ix, fee_cv_verify_ix, &fee_ciphertext_validity_proof_context_state_account),
+    ] {
+        stages.push(FeeTransferStage {
+            label: label.to_string(),
+            instructions: vec![create_ix, verify_ix],
+            new_account_signers: vec![context_state_account],
+            encodes: Some(context_state_account.pubkey()),
+        });
+    }
+
+    // Execute transfer with fee, referencing the proof accounts.
+    let new_decryptable_available_balance = sender_transfer_account_info
+        .new_decryptable_available_balance(confidential_transfer_amount, &sender_aes_key)?;
+
+    let transfer_with_fee_instructions = transfer_with_fee(
+        &spl_token_2022::id(),
+        &sender_associated_token_address,
+        &mint,
+        &recipient_associated_token_address,
+        &new_decryptable_available_balance.into(),
+        &transfer_amount_ciphertext_validity_proof_data_with_ciphertext.ciphertext_lo,
+        &transfer_amount_ciphertext_validity_proof_data_with_ciphertext.ciphertext_hi,
+        &sender_keypair.pubkey(),
+        &[],
+        ProofLocation::ContextStateAccount(&equality_proof_pubkey),
+        ProofLocation::ContextStateAccount(&transfer_amount_cv_proof_pubkey),
+        ProofLocation::ContextStateAccount(&fee_sigma_proof_pubkey),
+        ProofLocation::ContextStateAccount(&fee_cv_proof_pubkey),
+        ProofLocation::ContextStateAccount(&range_proof_pubkey),
+    )?;
+
+    let mut encoded_accounts = vec![];
+    let mut record_open = false;
+    for stage in stages {
+        let signature = match send_stage(&client, &sender_keypair, &stage, &blockhash_source) {
+            Ok(signature) => signature,
+            Err(e) => {
+                abort(&client, &sender_keypair, record_open.then_some(&record_pubkey), &encoded_accounts, &blockhash_source);
+                return Err(format!("Transfer with fee failed at [{}]: {}", stage.label, e).into());
+            }
+        };
+        print_transaction_url(&format!("Transfer With Fee [{}]", stage.label), &signature.to_string());
+
+        // The record account is closed in the same transaction that encodes the range proof.
+        if stage.new_account_signers.iter().any(|keypair| keypair.pubkey() == record_pubkey) {
+            record_open = true;
+        }
+        if stage.encodes == Some(range_proof_pubkey) {
+            record_open = false;
+        }
+        encoded_accounts.extend(stage.encodes);
+    }
+
+    let execute_stage = FeeTransferStage {
+        label: "Execute Transfer".to_string(),
+        instructions: transfer_with_fee_instructions,
+        new_account_signers: vec![],
+        encodes: None,
+    };
+    let transfer_signature = match send_stage(&client, &sender_keypair, &execute_stage, &blockhash_source) {
+        Ok(signature) => signature,
+        Err(e) => {
+            abort(&client, &sender_keypair, None, &encoded_accounts, &blockhash_source);
+            return Err(format!("Transfer with fee failed at [{}]: {}", execute_stage.label, e).into());
+        }
+    };
+    print_transaction_url("Transfer With Fee [Execute Transfer]", &transfer_signature.to_string());
+    record_value("last_confidential_transfer_with_fee_signature", &transfer_signature.to_string())?;
+
+    // Close the proof accounts, returning rent to the sender.
+    let close_stage = FeeTransferStage {
+        label: "Close Proof Accounts".to_string(),
+        instructions: close_proof_accounts_instructions(&context_state_authority_pubkey, &sender_keypair.pubkey(), &encoded_accounts),
+        new_account_signers: vec![],
+        encodes: None,
+    };
+    print_transaction_url("Transfer With Fee [Close Proof Accounts]", &send_stage(&client, &sender_keypair, &close_stage, &blockhash_source)?.to_string());
+
+    Ok(())
+}
+
+/// Stages uploading the range proof to `record_account` in chunks, then allocating its context
+/// state account and verifying the proof from the record, which is closed in the same transaction.
+fn range_proof_record_stages<'a>(
+    client: &RpcClient,
+    sender_pubkey: &Pubkey,
+    record_account: &'a Keypair,
+    range_proof_context_state_account: &'a Keypair,
+    range_proof_data: &BatchedRangeProofU256Data,
+) -> Result<Vec<FeeTransferStage<'a>>, Box<dyn Error>> {
+    let record_pubkey = record_account.pubkey();
+    let proof_bytes = bytemuck::bytes_of(range_proof_data);
+
+    let record_len = RecordData::WRITABLE_START_INDEX + proof_bytes.len();
+    let record_rent = client.get_minimum_balance_for_rent_exemption(record_len)?;
+
+    let mut stages = vec![FeeTransferStage {
+        label: "Allocate Range Proof Record".to_string(),
+        instructions: vec![
+            system_instruction::create_account(sender_pubkey, &record_pubkey, record_rent, record_len as u64, &spl_record::id()),
+            spl_record::instruction::initialize(&record_pubkey, sender_pubkey),
+        ],
+        new_account_signers: vec![record_account],
+        encodes: None,
+    }];
+
+    let chunk_count = proof_bytes.len().div_ceil(RECORD_WRITE_CHUNK_SIZE);
+    for (index, chunk) in proof_bytes.chunks(RECORD_WRITE_CHUNK_SIZE).enumerate() {
+        stages.push(FeeTransferStage {
+            label: format!("Upload Range Proof ({}/{})", index + 1, chunk_count),
+            instructions: vec![spl_record::instruction::write(
+                &record_pubkey,
+                sender_pubkey,
+                (index * RECORD_WRITE_CHUNK_SIZE) as u64,
+                chunk,
+            )],
+            new_account_signers: vec![],
+            encodes: None,
+        });
+    }
+
+    let range_proof_pubkey = range_proof_context_state_account.pubkey();
+    let (range_create_ix, _, _) = context_state_instructions(
+        client,
+        sender_pubkey,
+        &range_proof_pubkey,
+        sender_pubkey,
+        range_proof_data,
+    )?;
+    let range_verify_ix = zk_proof_type_to_instruction(BatchedRangeProofU256Data::PROOF_TYPE)?.encode_verify_proof_from_account(
+        Some(ContextStateInfo {
+            context_state_account: &range_proof_pubkey,
+            context_state_authority: sender_pubkey,
+        }),
+        &record_pubkey,
+        RecordData::WRITABLE_START_INDEX as u32,
+    );
+
+    stages.push(FeeTransferStage {
+        label: "Encode Range Proof".to_string(),
+        instructions: vec![
+            range_create_ix,
+            range_verify_ix,
+            spl_record::instruction::close_account(&record_pubkey, sender_pubkey, sender_pubkey),
+        ],
+        new_account_signers: vec![range_proof_context_state_account],
+        encodes: Some(range_proof_pubkey),
+    });
+
+    Ok(stages)
+}
+
+fn send_stage(
+    client: &RpcClient,
+    sender_keypair: &Arc<dyn Signer>,
+    stage: &FeeTransferStage,
+    blockhash_source: &BlockhashSource,
+) -> Result<Signature, Box<dyn Error>> {
+    let mut signers: Vec<&dyn Signer> = vec![sender_keypair.as_ref()];
+    signers.extend(stage.new_account_signers.iter().map(|keypair| *keypair as &dyn Signer));
+
+    let transaction = nonce::signed_transaction(client, &stage.instructions, &sender_keypair.pubkey(), signers.as_slice(), blockhash_source)?;
+    Ok(client.send_and_confirm_transaction(&transaction)?)
+}
+
+/// Best-effort reclaim of the rent held by an unfinished transfer: closes the record account, if
+/// still open, and every context state account that holds a verified proof.
+fn abort(
+    client: &RpcClient,
+    sender_keypair: &Arc<dyn Signer>,
+    record_account: Option<&Pubkey>,
+    encoded_accounts: &[Pubkey],
+    blockhash_source: &BlockhashSource,
+) {
+    let mut instructions = close_proof_accounts_instructions(&sender_keypair.pubkey(), &sender_keypair.pubkey(), encoded_accounts);
+    if let Some(record_account) = record_account {
+        instructions.push(spl_record::instruction::close_account(record_account, &sender_keypair.pubkey(), &sender_keypair.pubkey()));
+    }
+    if instructions.is_empty() {
+        return;
+    }
+
+    let close_stage = FeeTransferStage {
+        label: "Abort: Close Proof Accounts".to_string(),
+        instructions,
+        new_account_signers: vec![],
+        encodes: None,
+    };
+    match send_stage(client, sender_keypair, &close_stage, blockhash_source) {
+        Ok(signature) => print_transaction_url("Transfer With Fee [Abort: Close Proof Accounts]", &signature.to_string()),
+        Err(e) => println!("Failed to close proof accounts (rent is still held by {:?}): {}", encoded_accounts, e),
+    }
+}
@@ -1,428 +1,734 @@
+pub mod batch;
+pub mod fee;
+pub mod plan;
+pub mod preflight;
+pub mod shield;
+
 use {
+    plan::{TransferPlan, TransferProofs, ENCODE_RANGE_PROOF_STAGE, EXECUTE_TRANSFER_STAGE, TRANSFER_STAGES},
     utils::{
-        get_non_blocking_rpc_client, get_or_create_keypair, get_rpc_client, jito, load_value, print_transaction_url, record_value
+        current_mint, get_rpc_client, jito, nonce, print_transaction_url, record_value, required_signers, PermanentError,
+        nonce::BlockhashSource,
+        jito::JitoConfig,
+        proof_context::context_state_instructions,
+        proof_generation::transfer_split_proof_data_parallel,
     },
-    serde_json::json,
+    solana_client::rpc_client::RpcClient,
     solana_sdk::{
-        pubkey::Pubkey, signature::{Keypair, Signer}, system_instruction, transaction::Transaction
+        instruction::Instruction, pubkey::Pubkey, signature::{Keypair, Signature, Signer}, transaction::Transaction
     },
     spl_associated_token_account::get_associated_token_address_with_program_id,
     spl_token_2022::{
         extension::{
             confidential_transfer::{
                 account_info::TransferAccountInfo,
-                ConfidentialTransferAccount, ConfidentialTransferMint,
+                instruction::transfer,
+                ConfidentialTransferAccount,
             },
             BaseStateWithExtensions, StateWithExtensionsOwned,
         },
         solana_zk_sdk::{
             encryption::{
-                auth_encryption::AeKey,
-                elgamal::{self, ElGamalKeypair},
-                pod::elgamal::PodElGamalPubkey,
+                auth_encryption::{AeCiphertext, AeKey},
+                elgamal::{ElGamalCiphertext, ElGamalKeypair},
             },
-            zk_elgamal_proof_program::{self, instruction::{close_context_state, ContextStateInfo}},
+            zk_elgamal_proof_program::{instruction::{close_context_state, ContextStateInfo}, proof_data::ZkProofData},
         },
         state::{Account, Mint},
     },
-    spl_token_client::{
-        client::{ProgramRpcClient, ProgramRpcClientSendTransaction},
-        token::{ProofAccount, ProofAccountWithCiphertext, Token},
-    },
+    spl_token_confidential_transfer_proof_extraction::instruction::ProofLocation,
     spl_token_confidential_transfer_proof_generation::transfer::TransferProofData,
-    std::{error::Error, sync::Arc}
+    std::{error::Error, str::FromStr, sync::{atomic::{AtomicU32, Ordering}, Arc, Mutex}}
 };
 
-pub async fn with_split_proofs(sender_keypair: Arc<dyn Signer>, recipient_keypair: Arc<dyn Signer>, confidential_transfer_amount: u64) -> Result<(), Box<dyn Error>> {   
+#[cfg(feature = "dry-run")]
+use utils::dry_run::{DryRun, DryRunReport};
 
-    let client = get_rpc_client()?;
-    let transactions = prepare_transactions(sender_keypair.clone(), recipient_keypair, confidential_transfer_amount).await?;
-    assert!(transactions.len() == 5);
+/// Keypairs of the three ZK proof context state accounts used by a split-proof transfer.
+pub struct ProofContextKeypairs {
+    pub equality: Keypair,
+    pub ciphertext_validity: Keypair,
+    pub range: Keypair,
+}
+
+impl ProofContextKeypairs {
+    pub fn generate() -> Self {
+        Self {
+            equality: Keypair::new(),
+            ciphertext_validity: Keypair::new(),
+            range: Keypair::new(),
+        }
+    }
+
+    pub fn pubkeys(&self) -> [Pubkey; 3] {
+        [
+            self.equality.pubkey(),
+            self.ciphertext_validity.pubkey(),
+            self.range.pubkey(),
+        ]
+    }
+}
 
-    print_transaction_url("Transfer [Allocate Proof Accounts]", &client.send_and_confirm_transaction(&transactions[0])?.to_string());
-    print_transaction_url("Transfer [Encode Range Proof]", &client.send_and_confirm_transaction(&transactions[1])?.to_string());
-    print_transaction_url("Transfer [Encode Remaining Proofs]", &client.send_and_confirm_transaction(&transactions[2])?.to_string());
-    let transfer_signature = client.send_and_confirm_transaction(&transactions[3])?;
-    print_transaction_url("Transfer [Execute Transfer]", &transfer_signature.to_string());
-    print_transaction_url("Transfer [Close Proof Accounts]", &client.send_and_confirm_transaction(&transactions[4])?.to_string());
+/// Signed transactions for a single split-proof transfer, as produced by `prepare_transactions`.
+struct PreparedTransfer {
+    transactions: Vec<Transaction>,
+    /// The proofs the context accounts will hold.
+    proofs: TransferProofs,
+    /// The sender's balances as they will be once this transfer executes.
+    new_sender_transfer_account_info: TransferAccountInfo,
+}
 
-    record_value("last_confidential_transfer_signature", &transfer_signature.to_string())?;
+/// Confidential transfer with its proofs split across context state accounts, run as a persisted plan.
+///
+/// With `use_durable_nonces`, the stages are signed against the sender's durable nonce as each one
+/// is sent; the range proof stage can't fit the nonce advance and uses a recent blockhash.
+pub async fn with_split_proofs(sender_keypair: Arc<dyn Signer>, recipient_keypair: Arc<dyn Signer>, confidential_transfer_amount: u64, use_durable_nonces: bool) -> Result<(), Box<dyn Error>> {   
 
-    Ok(())
+    // Persist the plan before anything lands on-chain, so an interrupted run can be resumed with `resume_transfer`.
+    let plan = TransferPlan::new(&sender_keypair.pubkey(), &recipient_keypair.pubkey(), confidential_transfer_amount);
+    plan.save()?;
+    println!("Transfer plan id: {}", plan.plan_id);
 
+    run_plan(plan, sender_keypair, use_durable_nonces).await
 }
 
-async fn prepare_transactions(sender_keypair: Arc<dyn Signer>, recipient_keypair: Arc<dyn Signer>, confidential_transfer_amount: u64) -> Result<Vec<Transaction>, Box<dyn Error>> {
-    let client = get_rpc_client()?;
+/// Continues an interrupted `with_split_proofs` transfer from its last confirmed stage.
+///
+/// The plan holds the proofs its context accounts were allocated for, so the remaining proofs are
+/// encoded and the transfer executed with exactly those. If the sender's available balance changed
+/// in the meantime the proofs no longer apply: the transfer is aborted instead (see `abort_transfer`)
+/// and an error returned.
+pub async fn resume_transfer(plan_id: &str, sender_keypair: Arc<dyn Signer>, use_durable_nonces: bool) -> Result<(), Box<dyn Error>> {
+    let plan = load_plan_for_sender(plan_id, &sender_keypair)?;
+    run_plan(plan, sender_keypair, use_durable_nonces).await
+}
+
+/// Abandons an interrupted `with_split_proofs` transfer and reclaims the rent held by its proof accounts.
+pub async fn abort_transfer(plan_id: &str, sender_keypair: Arc<dyn Signer>, use_durable_nonces: bool) -> Result<(), Box<dyn Error>> {
+    let mut plan = load_plan_for_sender(plan_id, &sender_keypair)?;
+    plan.reconcile(&get_rpc_client()?)?;
+
+    if plan.next_stage() > EXECUTE_TRANSFER_STAGE {
+        return Err(format!("Transfer plan {} was already executed; use resume_transfer to close its proof accounts", plan.plan_id).into());
+    }
+
+    abort_plan(plan, sender_keypair, use_durable_nonces).await
+}
 
-    let mint = get_or_create_keypair("mint")?;
-    let sender_associated_token_address: Pubkey = get_associated_token_address_with_program_id(
+/// Executes all five `with_split_proofs` transactions in a local fork of the current on-chain
+/// state, without submitting anything. Reports logs, compute units and size per transaction, and
+/// the sender's and recipient's balances afterwards.
+#[cfg(feature = "dry-run")]
+pub async fn with_split_proofs_dry_run(
+    sender_keypair: Arc<dyn Signer>,
+    recipient_pubkey: &Pubkey,
+    confidential_transfer_amount: u64,
+) -> Result<DryRunReport, Box<dyn Error>> {
+    let mint = current_mint()?;
+    let sender_associated_token_address = get_associated_token_address_with_program_id(
         &sender_keypair.pubkey(),
-        &mint.pubkey(),
+        &mint,
         &spl_token_2022::id(),
     );
-    let decimals = load_value("mint_decimals")?;
-
-    let token = {
-        let rpc_client = get_non_blocking_rpc_client()?;
-
-        let program_client: ProgramRpcClient<ProgramRpcClientSendTransaction> =
-            ProgramRpcClient::new(Arc::new(rpc_client), ProgramRpcClientSendTransaction);
-
-        // Create a "token" client, to use various helper functions for Token Extensions
-        Token::new(
-            Arc::new(program_client),
-            &spl_token_2022::id(),
-            &mint.pubkey(),
-            Some(decimals),
-            sender_keypair.clone(),
-            /*
-            Can't use the intended separate fee_payer_keypair because I get the following error:
-            Client(Error { 
-                request: Some(SendTransaction), 
-                kind: RpcError(RpcResponseError { 
-                    code: -32602, 
-                    message: "base64 encoded solana_sdk::transaction::versioned::VersionedTransaction too large: 1652 bytes (max: encoded/raw 1644/1232)", 
-                    data: Empty 
-                }) 
-            })
-
-            Makes me wonder if transaction has one too many signers for range proof.
-            */
-        )
-    };
     let recipient_associated_token_address = get_associated_token_address_with_program_id(
-        &recipient_keypair.pubkey(),
-        &mint.pubkey(),
+        recipient_pubkey,
+        &mint,
         &spl_token_2022::id(),
     );
 
-    // Must first create 3 accounts to store proofs before transferring tokens
-    // This must be done in a separate transactions because the proofs are too large for single transaction
+    let context_keypairs = ProofContextKeypairs::generate();
+    let prepared = prepare_transactions(
+        sender_keypair.clone(),
+        recipient_pubkey,
+        confidential_transfer_amount,
+        &context_keypairs,
+        None,
+        false,
+    ).await?;
 
-    // Equality Proof - prove that two ciphertexts encrypt the same value
-    // Ciphertext Validity Proof - prove that ciphertexts are properly generated
-    // Range Proof - prove that ciphertexts encrypt a value in a specified range (0, u64::MAX)
+    let mut dry_run = DryRun::fork(
+        &[
+            sender_keypair.pubkey(),
+            sender_associated_token_address,
+            recipient_associated_token_address,
+            mint,
+        ],
+        &[spl_token_2022::id()],
+    ).await?;
 
-    // "Authority" for the proof accounts (to close the accounts after the transfer)
-    let context_state_authority = &sender_keypair;
+    for (stage, transaction) in prepared.transactions.into_iter().enumerate() {
+        // Only the allocation stage is signed by the proof accounts themselves.
+        let succeeded = if stage == 0 {
+            dry_run.process(
+                TRANSFER_STAGES[stage],
+                transaction,
+                &[
+                    sender_keypair.as_ref(),
+                    &context_keypairs.equality as &dyn Signer,
+                    &context_keypairs.ciphertext_validity as &dyn Signer,
+                    &context_keypairs.range as &dyn Signer,
+                ],
+            ).await?
+        } else {
+            dry_run.process(TRANSFER_STAGES[stage], transaction, &[sender_keypair.as_ref()]).await?
+        };
+        if !succeeded {
+            break;
+        }
+    }
+
+    let mut report = dry_run.finish().await?;
+
+    // The sender can decrypt its own post-transfer available balance.
+    let sender_aes_key = AeKey::new_from_signer(&sender_keypair, &sender_associated_token_address.to_bytes())?;
+    for balance in report.final_balances.iter_mut().filter(|balance| balance.address == sender_associated_token_address) {
+        if let Some(account) = &balance.account {
+            let decryptable_available_balance: AeCiphertext = StateWithExtensionsOwned::<Account>::unpack(account.data.clone())?
+                .get_extension::<ConfidentialTransferAccount>()?
+                .decryptable_available_balance
+                .try_into()?;
+            balance.confidential_available_balance = sender_aes_key.decrypt(&decryptable_available_balance);
+        }
+    }
 
-    // Generate address for equality proof account
-    let equality_proof_context_state_account = Keypair::new();
-    let equality_proof_pubkey = equality_proof_context_state_account.pubkey();
+    report.print();
+    Ok(report)
+}
 
-    // Generate address for ciphertext validity proof account
-    let ciphertext_validity_proof_context_state_account = Keypair::new();
-    let ciphertext_validity_proof_pubkey = ciphertext_validity_proof_context_state_account.pubkey();
+fn load_plan_for_sender(plan_id: &str, sender_keypair: &Arc<dyn Signer>) -> Result<TransferPlan, Box<dyn Error>> {
+    let plan = TransferPlan::load(plan_id)?;
+    if plan.sender_pubkey()? != sender_keypair.pubkey() {
+        return Err(format!("Signer {} is not the sender of transfer plan {}", sender_keypair.pubkey(), plan_id).into());
+    }
+    Ok(plan)
+}
 
-    // Generate address for range proof account
-    let range_proof_context_state_account = Keypair::new();
-    let range_proof_pubkey = range_proof_context_state_account.pubkey();
+async fn run_plan(mut plan: TransferPlan, sender_keypair: Arc<dyn Signer>, use_durable_nonces: bool) -> Result<(), Box<dyn Error>> {
+    let client = get_rpc_client()?;
+    plan.reconcile(&client)?;
+
+    if plan.aborted {
+        return Err(format!("Transfer plan {} was aborted", plan.plan_id).into());
+    }
+    if plan.is_complete() {
+        println!("Transfer plan {} is already complete", plan.plan_id);
+        return Ok(());
+    }
+
+    // Until the allocation lands nothing exists on-chain, so fresh accounts and proofs are used.
+    let context_keypairs = if plan.next_stage() == 0 {
+        let context_keypairs = ProofContextKeypairs::generate();
+        let (proofs, _) = generate_transfer_proofs(
+            &client,
+            &sender_keypair,
+            &plan.recipient_pubkey()?,
+            plan.amount,
+            None,
+        ).await?;
+        plan.set_proofs(&context_keypairs.pubkeys(), &proofs);
+        plan.save()?;
+        Some(context_keypairs)
+    } else {
+        None
+    };
 
-    // Get sender token account data
-    let sender_token_account_info = token
-        .get_account_info(&sender_associated_token_address)
-        .await?;
+    continue_plan(&client, plan, &sender_keypair, context_keypairs.as_ref(), &[], use_durable_nonces).await?;
+    Ok(())
+}
 
-    let sender_account_extension_data =
-        sender_token_account_info.get_extension::<ConfidentialTransferAccount>()?;
+/// Sends the remaining stages of a plan, built from its persisted proofs. The allocation stage is
+/// also signed by `context_keypairs`, and starts with `leading_instructions`. Returns the signature
+/// of the executed transfer.
+async fn continue_plan(
+    client: &RpcClient,
+    mut plan: TransferPlan,
+    sender_keypair: &Arc<dyn Signer>,
+    context_keypairs: Option<&ProofContextKeypairs>,
+    leading_instructions: &[Instruction],
+    use_durable_nonces: bool,
+) -> Result<Signature, Box<dyn Error>> {
+    let proofs = plan.proofs()?;
+    let next_stage = plan.next_stage();
+
+    // The proofs only apply to the balance they were generated against.
+    if next_stage > 0 && next_stage <= EXECUTE_TRANSFER_STAGE {
+        let sender_transfer_account_info = get_transfer_account_info(client, &sender_associated_token_address(&sender_keypair.pubkey())?)?;
+        if sender_transfer_account_info.available_balance != proofs.source_available_balance {
+            let plan_id = plan.plan_id.clone();
+            abort_plan(plan, sender_keypair.clone(), use_durable_nonces).await?;
+            return Err(format!("Sender balance changed since transfer plan {} was proven; the plan was aborted", plan_id).into());
+        }
+    }
 
-    // ConfidentialTransferAccount extension information needed to create proof data
-    let sender_transfer_account_info = TransferAccountInfo::new(sender_account_extension_data);
+    let stages = transfer_stage_instructions(
+        &sender_keypair.pubkey(),
+        &plan.recipient_pubkey()?,
+        &plan.context_state_pubkeys()?,
+        &proofs,
+        leading_instructions,
+    )?;
 
-    let sender_elgamal_keypair =
-        ElGamalKeypair::new_from_signer(&sender_keypair, &sender_associated_token_address.to_bytes())?;
-    let sender_aes_key =
-        AeKey::new_from_signer(&sender_keypair, &sender_associated_token_address.to_bytes())?;
-
-    // Get recipient token account data
-    let recipient_account = token
-        .get_account(recipient_associated_token_address)
-        .await?;
-
-    // Get recipient ElGamal pubkey from the recipient token account data and convert to elgamal::ElGamalPubkey
-    let recipient_elgamal_pubkey: elgamal::ElGamalPubkey =
-        StateWithExtensionsOwned::<Account>::unpack(recipient_account.data)?
-            .get_extension::<ConfidentialTransferAccount>()?
-            .elgamal_pubkey
-            .try_into()?;
-
-    // Get mint account data
-    let mint_account = token.get_account(mint.pubkey()).await?;
-
-    // Get auditor ElGamal pubkey from the mint account data
-    let auditor_elgamal_pubkey_option = Option::<PodElGamalPubkey>::from(
-        StateWithExtensionsOwned::<Mint>::unpack(mint_account.data)?
-            .get_extension::<ConfidentialTransferMint>()?
-            .auditor_elgamal_pubkey,
-    );
+    let blockhash_source = nonce::sequential_blockhash_source(&sender_keypair.pubkey(), use_durable_nonces);
+    for (stage, instructions) in stages.iter().enumerate().skip(next_stage) {
+        let transaction = sign_stage(client, stage, instructions, sender_keypair, context_keypairs, sequential_stage_source(stage, &blockhash_source))?;
+        send_stage(client, &mut plan, stage, &transaction)?;
+    }
 
-    // Convert auditor ElGamal pubkey to elgamal::ElGamalPubkey type
-    let auditor_elgamal_pubkey: elgamal::ElGamalPubkey = auditor_elgamal_pubkey_option
-        .ok_or("No Auditor ElGamal pubkey")?
-        .try_into()?;
+    let execute_signature = Signature::from_str(&plan.completed_stages[EXECUTE_TRANSFER_STAGE])?;
+    record_value("last_confidential_transfer_signature", execute_signature.to_string())?;
 
-    // Generate proof data
-    let TransferProofData {
-        equality_proof_data,
-        ciphertext_validity_proof_data_with_ciphertext,
-        range_proof_data,
-    } = sender_transfer_account_info.generate_split_transfer_proof_data(
-        confidential_transfer_amount,
-        &sender_elgamal_keypair,
-        &sender_aes_key,
-        &recipient_elgamal_pubkey,
-        Some(&auditor_elgamal_pubkey),
-    )?;
+    Ok(execute_signature)
+}
 
-    // Create 3 proofs ------------------------------------------------------
+async fn abort_plan(mut plan: TransferPlan, sender_keypair: Arc<dyn Signer>, use_durable_nonces: bool) -> Result<(), Box<dyn Error>> {
+    let client = get_rpc_client()?;
+    let blockhash_source = nonce::sequential_blockhash_source(&sender_keypair.pubkey(), use_durable_nonces);
+    let next_stage = plan.next_stage();
+
+    if next_stage == 0 {
+        // No proof accounts were allocated, so there's no rent to reclaim.
+        plan.mark_aborted(None)?;
+        println!("Transfer plan {} aborted before allocating proof accounts", plan.plan_id);
+        return Ok(());
+    }
+
+    let context_state_accounts = plan.context_state_pubkeys()?;
+
+    // A context state account can only be closed once it holds a verified proof (the close
+    // instruction checks the authority recorded by verification). Encode the plan's own proofs
+    // into any account that is still empty.
+    if next_stage < EXECUTE_TRANSFER_STAGE {
+        let stages = transfer_stage_instructions(
+            &sender_keypair.pubkey(),
+            &plan.recipient_pubkey()?,
+            &context_state_accounts,
+            &plan.proofs()?,
+            &[],
+        )?;
+        for (stage, instructions) in stages.iter().enumerate().take(EXECUTE_TRANSFER_STAGE).skip(next_stage) {
+            let transaction = sign_stage(&client, stage, instructions, &sender_keypair, None, sequential_stage_source(stage, &blockhash_source))?;
+            send_stage(&client, &mut plan, stage, &transaction)?;
+        }
+    }
 
-    // Range Proof Instructions------------------------------------------------------------------------------
-    let (range_create_ix, range_verify_ix) = get_zk_proof_context_state_account_creation_instructions(
+    let close_transaction = nonce::signed_transaction(
+        &client,
+        &close_proof_accounts_instructions(&sender_keypair.pubkey(), &sender_keypair.pubkey(), &context_state_accounts),
         &sender_keypair.pubkey(),
-        &range_proof_context_state_account.pubkey(),
-        &context_state_authority.pubkey(),
-        &range_proof_data,
+        &[&sender_keypair],
+        &blockhash_source,
     )?;
+    let close_signature = client.send_and_confirm_transaction(&close_transaction)?;
+    plan.mark_aborted(Some(&close_signature))?;
+    print_transaction_url("Transfer [Abort: Close Proof Accounts]", &close_signature.to_string());
+
+    Ok(())
+}
+
+/// The blockhash source of a plan stage signed just before it is sent: the shared sequential source,
+/// except for the range proof stage, which can't fit the nonce advance.
+fn sequential_stage_source(stage: usize, blockhash_source: &BlockhashSource) -> &BlockhashSource {
+    if stage == ENCODE_RANGE_PROOF_STAGE {
+        &BlockhashSource::Recent
+    } else {
+        blockhash_source
+    }
+}
 
-    // Equality Proof Instructions---------------------------------------------------------------------------
-    let (equality_create_ix, equality_verify_ix) = get_zk_proof_context_state_account_creation_instructions(
+/// Sends one stage of a transfer plan, recording its signature before and after confirmation.
+fn send_stage(
+    client: &RpcClient,
+    plan: &mut TransferPlan,
+    stage: usize,
+    transaction: &Transaction,
+) -> Result<(), Box<dyn Error>> {
+    let signature = transaction.signatures[0];
+    plan.mark_pending(transaction)?;
+    client.send_and_confirm_transaction(transaction)?;
+    plan.mark_confirmed(&signature)?;
+    print_transaction_url(&format!("Transfer [{}]", TRANSFER_STAGES[stage]), &signature.to_string());
+    Ok(())
+}
+
+/// Builds and signs the five transactions of a split-proof transfer.
+///
+/// Proofs are generated against `sender_transfer_account_info` when given (e.g. the expected balance
+/// after an earlier, not yet executed transfer), otherwise against the sender's on-chain balance.
+async fn prepare_transactions(
+    sender_keypair: Arc<dyn Signer>,
+    recipient_pubkey: &Pubkey,
+    confidential_transfer_amount: u64,
+    context_keypairs: &ProofContextKeypairs,
+    sender_transfer_account_info: Option<TransferAccountInfo>,
+    use_durable_nonces: bool,
+) -> Result<PreparedTransfer, Box<dyn Error>> {
+    let client = get_rpc_client()?;
+
+    // Transactions are signed up front, so each one needs its own durable nonce (if requested and the
+    // sender has enough). The range proof transaction can't fit the nonce advance.
+    let blockhash_sources = nonce::blockhash_sources(
         &sender_keypair.pubkey(),
-        &equality_proof_context_state_account.pubkey(),
-        &context_state_authority.pubkey(),
-        &equality_proof_data,
-    )?;
+        TRANSFER_STAGES.len(),
+        use_durable_nonces,
+        &[ENCODE_RANGE_PROOF_STAGE],
+    );
 
-    // Ciphertext Validity Proof Instructions ----------------------------------------------------------------
-    let (cv_create_ix, cv_verify_ix) = get_zk_proof_context_state_account_creation_instructions(
+    let (proofs, new_sender_transfer_account_info) = generate_transfer_proofs(
+        &client,
+        &sender_keypair,
+        recipient_pubkey,
+        confidential_transfer_amount,
+        sender_transfer_account_info,
+    ).await?;
+
+    let stages = transfer_stage_instructions(
         &sender_keypair.pubkey(),
-        &ciphertext_validity_proof_context_state_account.pubkey(),
-        &context_state_authority.pubkey(),
-        &ciphertext_validity_proof_data_with_ciphertext.proof_data,
+        recipient_pubkey,
+        &context_keypairs.pubkeys(),
+        &proofs,
+        &[],
     )?;
 
+    let transactions = stages
+        .iter()
+        .zip(&blockhash_sources)
+        .enumerate()
+        .map(|(stage, (instructions, blockhash_source))| {
+            sign_stage(&client, stage, instructions, &sender_keypair, Some(context_keypairs), blockhash_source)
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(PreparedTransfer {
+        transactions,
+        proofs,
+        new_sender_transfer_account_info,
+    })
+}
 
-    // Transact Proofs ------------------------------------------------------------------------------------
-    
-    // Transaction 1: Allocate all proof accounts at once.
-    let tx1 = Transaction::new_signed_with_payer(
-        &[range_create_ix.clone(), equality_create_ix.clone(), cv_create_ix.clone()],
-        Some(&sender_keypair.pubkey()),
-        &[
-            &sender_keypair, 
-            &range_proof_context_state_account as &dyn Signer, 
-            &equality_proof_context_state_account as &dyn Signer, 
-            &ciphertext_validity_proof_context_state_account as &dyn Signer
-        ],
-        client.get_latest_blockhash()?,
-    );
-    
-    // Transaction 2: Encode Range Proof on its own (because it's the largest).
-    let tx2 = Transaction::new_signed_with_payer(
-        &[range_verify_ix],
-        Some(&sender_keypair.pubkey()),
-        &[&sender_keypair],
-        client.get_latest_blockhash()?,
+/// Checks that the transfer can succeed, then generates its proofs against `sender_transfer_account_info`
+/// (or the sender's on-chain balance). Also returns the sender's balances once the transfer executes.
+async fn generate_transfer_proofs(
+    client: &RpcClient,
+    sender_keypair: &Arc<dyn Signer>,
+    recipient_pubkey: &Pubkey,
+    confidential_transfer_amount: u64,
+    sender_transfer_account_info: Option<TransferAccountInfo>,
+) -> Result<(TransferProofs, TransferAccountInfo), Box<dyn Error>> {
+    let mint = current_mint()?;
+    let sender_associated_token_address = sender_associated_token_address(&sender_keypair.pubkey())?;
+    let recipient_associated_token_address = get_associated_token_address_with_program_id(
+        recipient_pubkey,
+        &mint,
+        &spl_token_2022::id(),
     );
 
-    // Transaction 3: Encode all remaining proofs.
-    let tx3 = Transaction::new_signed_with_payer(
-        &[equality_verify_ix, cv_verify_ix],
-        Some(&sender_keypair.pubkey()),
-        &[&sender_keypair],
-        client.get_latest_blockhash()?,
-    );
-    
-    // Transaction 4: Execute transfer (below)
-    // Transfer with Split Proofs -------------------------------------------
-
-    let equality_proof_context_proof_account = ProofAccount::ContextAccount(equality_proof_pubkey);
-    let ciphertext_validity_proof_context_proof_account =
-        ProofAccount::ContextAccount(ciphertext_validity_proof_pubkey);
-    let range_proof_context_proof_account = ProofAccount::ContextAccount(range_proof_pubkey);
-
-    let ciphertext_validity_proof_account_with_ciphertext = ProofAccountWithCiphertext {
-        proof_account: ciphertext_validity_proof_context_proof_account,
-        ciphertext_lo: ciphertext_validity_proof_data_with_ciphertext.ciphertext_lo,
-        ciphertext_hi: ciphertext_validity_proof_data_with_ciphertext.ciphertext_hi,
+    // ConfidentialTransferAccount extension information needed to create proof data
+    let sender_transfer_account_info = match sender_transfer_account_info {
+        Some(sender_transfer_account_info) => sender_transfer_account_info,
+        None => get_transfer_account_info(client, &sender_associated_token_address)?,
     };
 
-    let tx4 = token.confidential_transfer_transfer_tx(
-        &sender_associated_token_address,
-        &recipient_associated_token_address,
-        &sender_keypair.pubkey(),
-        Some(&equality_proof_context_proof_account),
-        Some(&ciphertext_validity_proof_account_with_ciphertext),
-        Some(&range_proof_context_proof_account),
+    let sender_elgamal_keypair =
+        ElGamalKeypair::new_from_signer(sender_keypair, &sender_associated_token_address.to_bytes())?;
+    let sender_aes_key =
+        AeKey::new_from_signer(sender_keypair, &sender_associated_token_address.to_bytes())?;
+
+    let recipient_account = StateWithExtensionsOwned::<Account>::unpack(client.get_account(&recipient_associated_token_address)?.data)?;
+    let mint_account = StateWithExtensionsOwned::<Mint>::unpack(client.get_account(&mint)?.data)?;
+
+    // Pre-flight: fail before spending fees and rent on a transfer that can't succeed
+    let preflight::PreflightKeys {
+        recipient_elgamal_pubkey,
+        auditor_elgamal_pubkey,
+    } = preflight::check_transfer(
+        &sender_transfer_account_info,
+        &sender_aes_key,
+        &recipient_account,
+        &mint_account,
+        confidential_transfer_amount,
+    )?;
+
+    // Generate the three proofs in parallel on the dedicated proof pool, so the async runtime isn't stalled by curve arithmetic
+    let current_available_balance: ElGamalCiphertext = sender_transfer_account_info.available_balance.try_into()?;
+    let current_decryptable_available_balance: AeCiphertext = sender_transfer_account_info.decryptable_available_balance.try_into()?;
+    let TransferProofData {
+        equality_proof_data,
+        ciphertext_validity_proof_data_with_ciphertext,
+        range_proof_data,
+    } = transfer_split_proof_data_parallel(
+        &current_available_balance,
+        &current_decryptable_available_balance,
         confidential_transfer_amount,
-        Some(sender_transfer_account_info),
         &sender_elgamal_keypair,
         &sender_aes_key,
         &recipient_elgamal_pubkey,
         Some(&auditor_elgamal_pubkey),
-        &[&sender_keypair],
     ).await?;
 
-    // Transaction 5: (below)
-    // Close Proof Accounts --------------------------------------------------
+    let new_decryptable_available_balance = sender_transfer_account_info
+        .new_decryptable_available_balance(confidential_transfer_amount, &sender_aes_key)?
+        .into();
 
-    // Authority to close the proof accounts
-    let context_state_authority_pubkey = context_state_authority.pubkey();
-    // Lamports from the closed proof accounts will be sent to this account
-    let destination_account = &sender_keypair.pubkey();
+    // The equality proof binds the sender's post-transfer available balance ciphertext, which is
+    // exactly what the program will store once the transfer executes.
+    let new_sender_transfer_account_info = TransferAccountInfo {
+        available_balance: equality_proof_data.context_data().ciphertext,
+        decryptable_available_balance: new_decryptable_available_balance,
+    };
 
-    // Close the equality proof account
-    let close_equality_proof_instruction = close_context_state(
-        ContextStateInfo {
-            context_state_account: &equality_proof_pubkey,
-            context_state_authority: &context_state_authority_pubkey,
-        },
-        &destination_account,
-    );
+    let proofs = TransferProofs {
+        equality: equality_proof_data,
+        ciphertext_validity: ciphertext_validity_proof_data_with_ciphertext.proof_data,
+        range: range_proof_data,
+        auditor_ciphertext_lo: ciphertext_validity_proof_data_with_ciphertext.ciphertext_lo,
+        auditor_ciphertext_hi: ciphertext_validity_proof_data_with_ciphertext.ciphertext_hi,
+        source_available_balance: sender_transfer_account_info.available_balance,
+        new_decryptable_available_balance,
+    };
 
-    // Close the ciphertext validity proof account
-    let close_ciphertext_validity_proof_instruction = close_context_state(
-        ContextStateInfo {
-            context_state_account: &ciphertext_validity_proof_pubkey,
-            context_state_authority: &context_state_authority_pubkey,
-        },
-        &destination_account,
-    );
+    Ok((proofs, new_sender_transfer_account_info))
+}
 
-    // Close the range proof account
-    let close_range_proof_instruction = close_context_state(
-        ContextStateInfo {
-            context_state_account: &range_proof_pubkey,
-            context_state_authority: &context_state_authority_pubkey,
-        },
-        &destination_account,
+/// Instructions of each stage in `TRANSFER_STAGES`, encoding `proofs` into `context_state_accounts`
+/// (equality, ciphertext validity, range) and transferring with them. `leading_instructions` are
+/// placed ahead of the proof account allocations.
+fn transfer_stage_instructions(
+    sender_pubkey: &Pubkey,
+    recipient_pubkey: &Pubkey,
+    context_state_accounts: &[Pubkey; 3],
+    proofs: &TransferProofs,
+    leading_instructions: &[Instruction],
+) -> Result<Vec<Vec<Instruction>>, Box<dyn Error>> {
+    let mint = current_mint()?;
+    let sender_associated_token_address = sender_associated_token_address(sender_pubkey)?;
+    let recipient_associated_token_address = get_associated_token_address_with_program_id(
+        recipient_pubkey,
+        &mint,
+        &spl_token_2022::id(),
     );
 
-    let recent_blockhash = client.get_latest_blockhash()?;
-    let tx5 = Transaction::new_signed_with_payer(
+    // The sender is the "authority" for the proof accounts, so it can close them after the transfer.
+    let client = get_rpc_client()?;
+    let [equality_proof_pubkey, ciphertext_validity_proof_pubkey, range_proof_pubkey] = context_state_accounts;
+
+    let (range_create_ix, range_verify_ix, _) = context_state_instructions(
+        &client,
+        sender_pubkey,
+        range_proof_pubkey,
+        sender_pubkey,
+        &proofs.range,
+    )?;
+    let (equality_create_ix, equality_verify_ix, _) = context_state_instructions(
+        &client,
+        sender_pubkey,
+        equality_proof_pubkey,
+        sender_pubkey,
+        &proofs.equality,
+    )?;
+    let (cv_create_ix, cv_verify_ix, _) = context_state_instructions(
+        &client,
+        sender_pubkey,
+        ciphertext_validity_proof_pubkey,
+        sender_pubkey,
+        &proofs.ciphertext_validity,
+    )?;
+
+    // Stage 1: Allocate all proof accounts at once.
+    let mut allocate_instructions = leading_instructions.to_vec();
+    allocate_instructions.extend([range_create_ix, equality_create_ix, cv_create_ix]);
+
+    // Stage 4: Execute the transfer, referencing the proof accounts.
+    let transfer_instructions = transfer(
+        &spl_token_2022::id(),
+        &sender_associated_token_address,
+        &mint,
+        &recipient_associated_token_address,
+        &proofs.new_decryptable_available_balance,
+        &proofs.auditor_ciphertext_lo,
+        &proofs.auditor_ciphertext_hi,
+        sender_pubkey,
+        &[],
+        ProofLocation::ContextStateAccount(equality_proof_pubkey),
+        ProofLocation::ContextStateAccount(ciphertext_validity_proof_pubkey),
+        ProofLocation::ContextStateAccount(range_proof_pubkey),
+    )?;
+
+    Ok(vec![
+        allocate_instructions,
+        // Stage 2: Encode Range Proof on its own (because it's the largest).
+        vec![range_verify_ix],
+        // Stage 3: Encode all remaining proofs.
+        vec![equality_verify_ix, cv_verify_ix],
+        transfer_instructions,
+        // Stage 5: Close the proof accounts, returning their rent to the sender.
+        close_proof_accounts_instructions(sender_pubkey, sender_pubkey, context_state_accounts),
+    ])
+}
+
+/// Signs one transfer stage. Only the allocation stage is also signed by the proof accounts.
+fn sign_stage(
+    client: &RpcClient,
+    stage: usize,
+    instructions: &[Instruction],
+    sender_keypair: &Arc<dyn Signer>,
+    context_keypairs: Option<&ProofContextKeypairs>,
+    blockhash_source: &BlockhashSource,
+) -> Result<Transaction, Box<dyn Error>> {
+    if stage > 0 {
+        return nonce::signed_transaction(client, instructions, &sender_keypair.pubkey(), &[sender_keypair], blockhash_source);
+    }
+
+    let context_keypairs = context_keypairs.ok_or("Allocating proof accounts requires their keypairs")?;
+    nonce::signed_transaction(
+        client,
+        instructions,
+        &sender_keypair.pubkey(),
         &[
-            close_equality_proof_instruction,
-            close_ciphertext_validity_proof_instruction,
-            close_range_proof_instruction,
+            sender_keypair,
+            &context_keypairs.range as &dyn Signer,
+            &context_keypairs.equality as &dyn Signer,
+            &context_keypairs.ciphertext_validity as &dyn Signer,
         ],
-        Some(&sender_keypair.pubkey()),
-        &[&sender_keypair], // Signers
-        recent_blockhash,
-    );
+        blockhash_source,
+    )
+}
 
-    Ok(vec![tx1, tx2, tx3, tx4, tx5])
+fn sender_associated_token_address(sender_pubkey: &Pubkey) -> Result<Pubkey, Box<dyn Error>> {
+    let mint = current_mint()?;
+    Ok(get_associated_token_address_with_program_id(sender_pubkey, &mint, &spl_token_2022::id()))
 }
 
-pub async fn with_split_proofs_atomic(sender_keypair: Arc<dyn Signer>, recipient_keypair: Arc<dyn Signer>, confidential_transfer_amount: u64) -> Result<(), Box<dyn Error>> {
-    
+fn get_transfer_account_info(client: &RpcClient, token_account: &Pubkey) -> Result<TransferAccountInfo, Box<dyn Error>> {
+    let account = StateWithExtensionsOwned::<Account>::unpack(client.get_account(token_account)?.data)?;
+    Ok(TransferAccountInfo::new(account.get_extension::<ConfidentialTransferAccount>()?))
+}
+
+/// Instructions closing the given proof context state accounts.
+fn close_proof_accounts_instructions(
+    context_state_authority_pubkey: &Pubkey,
+    destination_account: &Pubkey,
+    context_state_accounts: &[Pubkey],
+) -> Vec<Instruction> {
+    context_state_accounts
+        .iter()
+        .map(|context_state_account| {
+            close_context_state(
+                ContextStateInfo {
+                    context_state_account,
+                    context_state_authority: context_state_authority_pubkey,
+                },
+                destination_account,
+            )
+        })
+        .collect()
+}
+
+/// Transfers as a single Jito bundle: either every transaction lands, or none does.
+///
+/// The proofs and proof accounts are prepared once. Each attempt re-signs the same bundle against a
+/// fresh blockhash with an escalated tip, so at most one attempt can ever land: a late landing of an
+/// earlier attempt makes the later ones fail on the already-allocated proof accounts. Before a
+/// failure is reported, the transfer signature of every attempt is looked up in case one landed anyway.
+///
+/// The bundle holds all five transactions, so only `TipPlacement::Execute` is supported; `First`
+/// and `Last` are rejected before anything is prepared.
+pub async fn with_split_proofs_atomic(sender_keypair: Arc<dyn Signer>, recipient_keypair: Arc<dyn Signer>, confidential_transfer_amount: u64, jito_config: &JitoConfig) -> Result<(), Box<dyn Error>> {
+    jito_config.check_tip_placement(TRANSFER_STAGES.len())?;
+    let client = get_rpc_client()?;
+
+    let (proofs, _) = generate_transfer_proofs(
+        &client,
+        &sender_keypair,
+        &recipient_keypair.pubkey(),
+        confidential_transfer_amount,
+        None,
+    ).await?;
+    let context_keypairs = ProofContextKeypairs::generate();
+    let stages = transfer_stage_instructions(
+        &sender_keypair.pubkey(),
+        &recipient_keypair.pubkey(),
+        &context_keypairs.pubkeys(),
+        &proofs,
+        &[],
+    )?;
+
+    let signers: [&dyn Signer; 4] = [
+        sender_keypair.as_ref(),
+        &context_keypairs.equality,
+        &context_keypairs.ciphertext_validity,
+        &context_keypairs.range,
+    ];
+
     // When using Jito bundles there are many reasons why a bundle might not land:
     // - Not enough priority fee prolongs transaction inclusion, risking rejection.
     //   - Unfortunately, many transactions in transfer are saturated, lacking room to insert a priority fee instruction.
     //   - This is the most likely reason why bundles fail.
     // - We never know if the leading validator is running the Jito engine.
 
-    // We'll do a best attempt at retrying the bundle.
-    utils::run_with_retry(5, || async {
-
-        let mut transactions = prepare_transactions(sender_keypair.clone(), recipient_keypair.clone(), confidential_transfer_amount).await?;
-
-        // Reconstruct the one transaction to add the jito tip instruction.
-        {
-            // Not-so-early-out check for testnet or mainnet.
-            let client = get_rpc_client()?;
-            assert!(client.url().contains("testnet") || client.url().contains("mainnet"), "This Jito demo only works on testnet or mainnet (adjust code for custom endpoints)");
-            
-            let jito_tip_ix = jito::create_jito_tip_instruction(sender_keypair.pubkey()).await?;
-            
-            // Any transaction can be used. This one is the simplest to edit (and fits within size limits).
-            let tx3 = &mut transactions[2];
-
-            // Include instruction's accounts into the transaction (without duplicates).
-            {
-                let mut unique_pubkeys: std::collections::HashSet<_> = tx3.message.account_keys.iter().cloned().collect();
-                tx3.message.account_keys.extend(
-                    jito_tip_ix
-                        .accounts
-                        .iter()
-                        .map(|account| account.pubkey)
-                        .filter(|pubkey| unique_pubkeys.insert(*pubkey))
-                );
-
-                tx3.message.account_keys.push(solana_sdk::system_program::id());
-            }
-            
-            // Include instruction into the transaction.
-            let compiled_jito_tip_ix = tx3.message.compile_instruction(&jito_tip_ix);
-            tx3.message.instructions.push(compiled_jito_tip_ix);
-
-            // Re-sign the transaction for integrity.
-            tx3.sign(&[&sender_keypair], client.get_latest_blockhash()?);
-
+    // We'll do a best attempt at retrying the bundle, tipping more on each attempt (per the tip policy).
+    let attempts = AtomicU32::new(0);
+    let submitted_execute_signatures = Mutex::new(vec![]);
+    let result = utils::run_with_retry(5, || async {
+        let attempt = attempts.fetch_add(1, Ordering::Relaxed);
+
+        let recent_blockhash = client.get_latest_blockhash()?;
+        let mut transactions = stages
+            .iter()
+            .map(|instructions| {
+                let mut transaction = Transaction::new_with_payer(instructions, Some(&sender_keypair.pubkey()));
+                transaction.try_sign(&required_signers(&transaction.message, &signers)[..], recent_blockhash)?;
+                Ok(transaction)
+            })
+            .collect::<Result<Vec<_>, Box<dyn Error>>>()?;
+
+        let jito_tip_ix = jito::create_jito_tip_instruction(jito_config, sender_keypair.pubkey(), attempt).await?;
+        let tip_index = jito::place_tip(
+            &mut transactions,
+            jito_tip_ix,
+            jito_config.tip_placement,
+            EXECUTE_TRANSFER_STAGE,
+            &sender_keypair.pubkey(),
+            &signers,
+            recent_blockhash,
+        ).map_err(PermanentError)?;
+
+        let mut labels = TRANSFER_STAGES.to_vec();
+        if let Some(tip_index) = tip_index {
+            labels.insert(tip_index, "Jito Tip");
         }
 
-        let serialized_tx1 = bs58::encode(bincode::serialize(&transactions[0])?).into_string();
-        let serialized_tx2 = bs58::encode(bincode::serialize(&transactions[1])?).into_string();
-        let serialized_tx3 = bs58::encode(bincode::serialize(&transactions[2])?).into_string();
-        let serialized_tx4 = bs58::encode(bincode::serialize(&transactions[3])?).into_string();
-        let serialized_tx5 = bs58::encode(bincode::serialize(&transactions[4])?).into_string();
-        
-        let tx_bundle = json!([
-            serialized_tx1, 
-            serialized_tx2, 
-            serialized_tx3, 
-            serialized_tx4, 
-            serialized_tx5
-        ]);
-
-        let bundled_signatures = jito::submit_and_confirm_bundle(tx_bundle).await?;
-        print_transaction_url("Transfer [Allocate Proof Accounts]", &bundled_signatures[0]);
-        print_transaction_url("Transfer [Encode Range Proof]", &bundled_signatures[1]);
-        print_transaction_url("Transfer [Encode Remaining Proofs]", &bundled_signatures[2]);
-        print_transaction_url("Transfer [Execute Transfer]", &bundled_signatures[3]);
-        print_transaction_url("Transfer [Close Proof Accounts]", &bundled_signatures[4]);
-        
-        record_value("last_confidential_transfer_signature", &bundled_signatures[3])?;
-    
-        Ok(())
-    }).await
-}
-
-/// Refactored version of spl_token_client::token::Token::confidential_transfer_create_context_state_account().
-/// Instead of sending transactions internally, this function now returns the instructions to be used externally.
-fn get_zk_proof_context_state_account_creation_instructions<
-    ZK: bytemuck::Pod + zk_elgamal_proof_program::proof_data::ZkProofData<U>,
-    U: bytemuck::Pod,
->(
-    fee_payer_pubkey: &Pubkey,
-    context_state_account_pubkey: &Pubkey,
-    context_state_authority_pubkey: &Pubkey,
-    proof_data: &ZK,
-) -> Result<(solana_sdk::instruction::Instruction, solana_sdk::instruction::Instruction), Box<dyn Error>> {
-    use std::mem::size_of;
-    use spl_token_confidential_transfer_proof_extraction::instruction::zk_proof_type_to_instruction;
-
-    let client = get_rpc_client()?;
-    let space = size_of::<zk_elgamal_proof_program::state::ProofContextState<U>>();
-    let rent = client.get_minimum_balance_for_rent_exemption(space)?;
-
-    let context_state_info = ContextStateInfo {
-        context_state_account: context_state_account_pubkey,
-        context_state_authority: context_state_authority_pubkey,
-    };
+        // Retries only change the blockhash and the tip, so the first simulation stands for all of them.
+        if attempt == 0 {
+            jito::simulate_or_fail(jito_config, &labels, &transactions, &signers).await?;
+        }
 
-    let instruction_type = zk_proof_type_to_instruction(ZK::PROOF_TYPE)?;
+        let execute_index = labels
+            .iter()
+            .position(|label| *label == TRANSFER_STAGES[EXECUTE_TRANSFER_STAGE])
+            .ok_or_else(|| PermanentError("Bundle has no transfer transaction".into()))?;
+        submitted_execute_signatures.lock().unwrap().push(transactions[execute_index].signatures[0]);
 
-    let create_account_ix = system_instruction::create_account(
-        fee_payer_pubkey,
-        context_state_account_pubkey,
-        rent,
-        space as u64,
-        &zk_elgamal_proof_program::id(),
-    );
+        let bundled_signatures = jito::submit_and_confirm_bundle(jito_config, &transactions).await?;
+        for (label, signature) in labels.iter().zip(&bundled_signatures) {
+            print_transaction_url(&format!("Transfer [{}]", label), signature);
+        }
 
-    let verify_proof_ix =
-        instruction_type.encode_verify_proof(Some(context_state_info), proof_data);
+        record_value("last_confidential_transfer_signature", &bundled_signatures[execute_index])?;
+    
+        Ok(())
+    }).await;
+
+    // An attempt reported as not landed may have landed anyway; the shared proof accounts make sure
+    // at most one of them did.
+    if let Err(e) = result {
+        let submitted_execute_signatures = submitted_execute_signatures.into_inner().unwrap();
+        let Some(signature) = jito::landed_signature(&client, &submitted_execute_signatures)? else {
+            return Err(e);
+        };
+        let signature = signature.to_string();
+        print_transaction_url(&format!("Transfer [{}] (earlier attempt)", TRANSFER_STAGES[EXECUTE_TRANSFER_STAGE]), &signature);
+        record_value("last_confidential_transfer_signature", &signature)?;
+    }
 
-    // Return a tuple containing the create account instruction and verify proof instruction.
-    Ok((create_account_ix, verify_proof_ix))
-}
\ No newline at end of file
+    Ok(())
+}
@@ -0,0 +1,441 @@
+use {
+    base64::{prelude::BASE64_STANDARD, Engine},
+    bytemuck::Pod,
+    serde::{Deserialize, Serialize},
+    solana_client::rpc_client::RpcClient,
+    solana_sdk::{
+        hash::{hash, Hash},
+        pubkey::Pubkey,
+        signature::{Keypair, Signature},
+        signer::Signer,
+        transaction::{self, Transaction},
+    },
+    spl_token_2022::solana_zk_sdk::{
+        encryption::pod::{auth_encryption::PodAeCiphertext, elgamal::PodElGamalCiphertext},
+        zk_elgamal_proof_program::proof_data::{
+            BatchedGroupedCiphertext3HandlesValidityProofData, BatchedRangeProofU128Data,
+            CiphertextCommitmentEqualityProofData,
+        },
+    },
+    std::{error::Error, mem::size_of, str::FromStr},
+    utils::{load_value, nonce, record_value, remove_value},
+};
+
+/// Stages of a split-proof transfer, in the order their transactions are sent.
+pub const TRANSFER_STAGES: [&str; 5] = [
+    "Allocate Proof Accounts",
+    "Encode Range Proof",
+    "Encode Remaining Proofs",
+    "Execute Transfer",
+    "Close Proof Accounts",
+];
+
+/// Index of the "Encode Range Proof" stage in `TRANSFER_STAGES`. Its transaction is too close to the
+/// packet limit to take a durable nonce advance.
+pub const ENCODE_RANGE_PROOF_STAGE: usize = 1;
+
+/// Index of the "Execute Transfer" stage in `TRANSFER_STAGES`.
+pub const EXECUTE_TRANSFER_STAGE: usize = 3;
+
+/// Public data of a split-proof transfer: the three proofs, the auditor ciphertexts of the amount,
+/// and the sender balances they were generated against and lead to.
+///
+/// None of it is secret, so it is persisted with the plan and lets an interrupted transfer finish
+/// encoding and execute with exactly the proofs its context accounts already hold.
+#[derive(Clone, Copy)]
+pub struct TransferProofs {
+    pub equality: CiphertextCommitmentEqualityProofData,
+    pub ciphertext_validity: BatchedGroupedCiphertext3HandlesValidityProofData,
+    pub range: BatchedRangeProofU128Data,
+    pub auditor_ciphertext_lo: PodElGamalCiphertext,
+    pub auditor_ciphertext_hi: PodElGamalCiphertext,
+    /// The sender's available balance the proofs were generated against.
+    pub source_available_balance: PodElGamalCiphertext,
+    /// The sender's decryptable available balance once the transfer executes.
+    pub new_decryptable_available_balance: PodAeCiphertext,
+}
+
+impl TransferProofs {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        [
+            bytemuck::bytes_of(&self.equality),
+            bytemuck::bytes_of(&self.ciphertext_validity),
+            bytemuck::bytes_of(&self.range),
+            bytemuck::bytes_of(&self.auditor_ciphertext_lo),
+            bytemuck::bytes_of(&self.auditor_ciphertext_hi),
+            bytemuck::bytes_of(&self.source_available_balance),
+            bytemuck::bytes_of(&self.new_decryptable_available_balance),
+        ]
+        .concat()
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Box<dyn Error>> {
+        let mut offset = 0;
+        let proofs = Self {
+            equality: read_pod(bytes, &mut offset)?,
+            ciphertext_validity: read_pod(bytes, &mut offset)?,
+            range: read_pod(bytes, &mut offset)?,
+            auditor_ciphertext_lo: read_pod(bytes, &mut offset)?,
+            auditor_ciphertext_hi: read_pod(bytes, &mut offset)?,
+            source_available_balance: read_pod(bytes, &mut offset)?,
+            new_decryptable_available_balance: read_pod(bytes, &mut offset)?,
+        };
+        if offset != bytes.len() {
+            return Err("Unexpected trailing bytes in transfer proof data".into());
+        }
+        Ok(proofs)
+    }
+
+    pub fn hash(&self) -> Hash {
+        hash(&self.to_bytes())
+    }
+}
+
+fn read_pod<T: Pod>(bytes: &[u8], offset: &mut usize) -> Result<T, Box<dyn Error>> {
+    let end = *offset + size_of::<T>();
+    let value = bytemuck::try_pod_read_unaligned(bytes.get(*offset..end).ok_or("Truncated transfer proof data")?)
+        .map_err(|e| format!("Invalid transfer proof data: {:?}", e))?;
+    *offset = end;
+    Ok(value)
+}
+
+/// Persisted progress of a split-proof transfer.
+///
+/// Only public data is stored: the context state account addresses and the proofs they hold (or
+/// are about to hold). The context accounts' keypairs are needed solely to allocate them, so an
+/// interrupted transfer can continue from any stage without them.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct TransferPlan {
+    pub plan_id: String,
+    pub sender: String,
+    pub recipient: String,
+    pub amount: u64,
+    /// Equality, ciphertext validity and range proof context state accounts, once chosen.
+    pub context_state_accounts: Vec<String>,
+    /// Base64-encoded `TransferProofs` for the context state accounts.
+    pub proof_data: Option<String>,
+    /// Hash of `proof_data`, checked whenever the proofs are loaded back.
+    pub proof_data_hash: Option<String>,
+    /// Signatures of the confirmed stages, in stage order.
+    pub completed_stages: Vec<String>,
+    /// Signature of a stage that was sent but not yet confirmed when the plan was last saved.
+    pub pending_signature: Option<String>,
+    /// Base64-encoded signed transaction of the pending stage, resent on resume while it can still land.
+    #[serde(default)]
+    pub pending_transaction: Option<String>,
+    /// Set once the plan has been abandoned; the transfer will not be executed.
+    pub aborted: bool,
+    /// Signature of the transaction that closed the proof accounts of an abandoned plan.
+    pub abort_signature: Option<String>,
+}
+
+impl TransferPlan {
+    pub fn new(sender: &Pubkey, recipient: &Pubkey, amount: u64) -> Self {
+        Self {
+            // A fresh random address is as good a unique id as any.
+            plan_id: Keypair::new().pubkey().to_string(),
+            sender: sender.to_string(),
+            recipient: recipient.to_string(),
+            amount,
+            context_state_accounts: vec![],
+            proof_data: None,
+            proof_data_hash: None,
+            completed_stages: vec![],
+            pending_signature: None,
+            pending_transaction: None,
+            aborted: false,
+            abort_signature: None,
+        }
+    }
+
+    pub fn load(plan_id: &str) -> Result<Self, Box<dyn Error>> {
+        load_value(&Self::variable_name(plan_id))
+            .map_err(|_| format!("Transfer plan {} not found; finished and aborted plans are removed", plan_id).into())
+    }
+
+    pub fn save(&self) -> Result<(), Box<dyn Error>> {
+        // Recorded as a JSON *string* so the .env parser keeps the inner quotes intact;
+        // `load_value` then deserializes the unquoted object directly.
+        record_value(&Self::variable_name(&self.plan_id), serde_json::to_string(self)?)?;
+        record_value("last_transfer_plan_id", &self.plan_id)?;
+
+        let sender = self.sender_pubkey()?;
+        let mut plan_ids = Self::plan_ids(&sender);
+        if !plan_ids.contains(&self.plan_id) {
+            plan_ids.push(self.plan_id.clone());
+            record_value(&Self::plan_ids_variable_name(&sender), serde_json::to_string(&plan_ids)?)?;
+        }
+        Ok(())
+    }
+
+    /// Every saved plan of `sender`. Plans that can no longer be read are skipped.
+    pub fn plans_of(sender: &Pubkey) -> Vec<Self> {
+        Self::plan_ids(sender)
+            .iter()
+            .filter_map(|plan_id| match Self::load(plan_id) {
+                Ok(plan) => Some(plan),
+                Err(e) => {
+                    println!("Skipping unreadable transfer plan {}: {}", plan_id, e);
+                    None
+                }
+            })
+            .collect()
+    }
+
+    /// Saves an unfinished plan. A finished or aborted one has nothing left to resume, so it is
+    /// removed instead, along with its entry in the sender's index.
+    fn save_or_prune(&self) -> Result<(), Box<dyn Error>> {
+        if self.is_unfinished() {
+            return self.save();
+        }
+
+        remove_value(&Self::variable_name(&self.plan_id))?;
+        let sender = self.sender_pubkey()?;
+        let mut plan_ids = Self::plan_ids(&sender);
+        plan_ids.retain(|plan_id| plan_id != &self.plan_id);
+        record_value(&Self::plan_ids_variable_name(&sender), serde_json::to_string(&plan_ids)?)?;
+        Ok(())
+    }
+
+    fn plan_ids(sender: &Pubkey) -> Vec<String> {
+        load_value(&Self::plan_ids_variable_name(sender)).unwrap_or_default()
+    }
+
+    /// Whether the plan may still use its context state accounts, i.e. it can be resumed or aborted.
+    pub fn is_unfinished(&self) -> bool {
+        !self.aborted && !self.is_complete()
+    }
+
+    pub fn sender_pubkey(&self) -> Result<Pubkey, Box<dyn Error>> {
+        Ok(Pubkey::from_str(&self.sender)?)
+    }
+
+    pub fn recipient_pubkey(&self) -> Result<Pubkey, Box<dyn Error>> {
+        Ok(Pubkey::from_str(&self.recipient)?)
+    }
+
+    /// Records the context state accounts and the proofs they will hold.
+    pub fn set_proofs(&mut self, context_state_accounts: &[Pubkey; 3], proofs: &TransferProofs) {
+        self.context_state_accounts = context_state_accounts.iter().map(Pubkey::to_string).collect();
+        self.proof_data = Some(BASE64_STANDARD.encode(proofs.to_bytes()));
+        self.proof_data_hash = Some(proofs.hash().to_string());
+    }
+
+    pub fn context_state_pubkeys(&self) -> Result<[Pubkey; 3], Box<dyn Error>> {
+        let pubkeys = self
+            .context_state_accounts
+            .iter()
+            .map(|account| Pubkey::from_str(account))
+            .collect::<Result<Vec<_>, _>>()?;
+        pubkeys
+            .try_into()
+            .map_err(|_| format!("Transfer plan {} has no proof context state accounts", self.plan_id).into())
+    }
+
+    /// The persisted proofs, verified against `proof_data_hash`.
+    pub fn proofs(&self) -> Result<TransferProofs, Box<dyn Error>> {
+        let proof_data = self.proof_data.as_ref().ok_or_else(|| format!("Transfer plan {} has no proof data", self.plan_id))?;
+        let proofs = TransferProofs::from_bytes(&BASE64_STANDARD.decode(proof_data)?)?;
+
+        let expected_hash = self.proof_data_hash.as_deref().map(Hash::from_str).transpose()?;
+        if expected_hash != Some(proofs.hash()) {
+            return Err(format!("Proof data of transfer plan {} does not match its recorded hash", self.plan_id).into());
+        }
+        Ok(proofs)
+    }
+
+    /// Index of the first stage not yet confirmed on-chain.
+    pub fn next_stage(&self) -> usize {
+        self.completed_stages.len()
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.next_stage() == TRANSFER_STAGES.len()
+    }
+
+    /// Records a stage's signed transaction before it is sent, so a crash between sending and
+    /// confirming can be reconciled against the chain on resume.
+    pub fn mark_pending(&mut self, transaction: &Transaction) -> Result<(), Box<dyn Error>> {
+        self.pending_signature = Some(transaction.signatures[0].to_string());
+        self.pending_transaction = Some(BASE64_STANDARD.encode(bincode::serialize(transaction)?));
+        self.save()
+    }
+
+    pub fn mark_confirmed(&mut self, signature: &Signature) -> Result<(), Box<dyn Error>> {
+        self.pending_signature = None;
+        self.pending_transaction = None;
+        self.completed_stages.push(signature.to_string());
+        self.save_or_prune()
+    }
+
+    pub fn mark_aborted(&mut self, close_signature: Option<&Signature>) -> Result<(), Box<dyn Error>> {
+        self.pending_signature = None;
+        self.pending_transaction = None;
+        self.aborted = true;
+        self.abort_signature = close_signature.map(|signature| signature.to_string());
+        self.save_or_prune()
+    }
+
+    /// Resolves a pending stage signature left behind by an interrupted run.
+    ///
+    /// A stage that hasn't landed but still can (its blockhash hasn't expired, or its durable nonce
+    /// hasn't advanced) is resent as signed rather than treated as unsent. Otherwise a rebuilt
+    /// allocation would choose new context state accounts, and the original landing later would
+    /// fund accounts the plan no longer records.
+    pub fn reconcile(&mut self, client: &RpcClient) -> Result<(), Box<dyn Error>> {
+        let Some(pending_signature) = &self.pending_signature else {
+            return Ok(());
+        };
+        let signature = Signature::from_str(pending_signature)?;
+
+        let mut status = signature_status(client, &signature)?;
+        if let (None, Some(transaction)) = (&status, self.pending_transaction()?) {
+            if nonce::can_still_land(client, &transaction)? {
+                println!("Pending stage can still land; resending it: {}", signature);
+                status = match client.send_and_confirm_transaction(&transaction) {
+                    Ok(_) => Some(Ok(())),
+                    Err(e) => match signature_status(client, &signature)? {
+                        Some(status) => Some(status),
+                        None => return Err(format!("Pending stage {} can still land but was not confirmed: {}", signature, e).into()),
+                    },
+                };
+            } else {
+                // Expired, but it may have landed just before expiring.
+                status = signature_status(client, &signature)?;
+            }
+        }
+
+        self.resolve_pending(status);
+        self.save_or_prune()
+    }
+
+    fn pending_transaction(&self) -> Result<Option<Transaction>, Box<dyn Error>> {
+        let Some(transaction) = &self.pending_transaction else {
+            return Ok(None);
+        };
+        Ok(Some(bincode::deserialize(&BASE64_STANDARD.decode(transaction)?)?))
+    }
+
+    /// Applies the on-chain status of the pending stage signature.
+    fn resolve_pending(&mut self, status: Option<transaction::Result<()>>) {
+        self.pending_transaction = None;
+        let Some(pending_signature) = self.pending_signature.take() else {
+            return;
+        };
+        match status {
+            Some(Ok(())) => {
+                println!("Pending stage landed before interruption: {}", pending_signature);
+                self.completed_stages.push(pending_signature);
+            }
+            Some(Err(e)) => println!("Pending stage failed before interruption: {}", e),
+            None => println!("Pending stage never landed: {}", pending_signature),
+        }
+    }
+
+    fn variable_name(plan_id: &str) -> String {
+        format!("transfer_plan_{}", plan_id)
+    }
+
+    fn plan_ids_variable_name(sender: &Pubkey) -> String {
+        format!("transfer_plans_{}", sender)
+    }
+}
+
+/// Status of `signature`, searching the ledger history for transactions too old for the status cache.
+fn signature_status(client: &RpcClient, signature: &Signature) -> Result<Option<transaction::Result<()>>, Box<dyn Error>> {
+    Ok(client.get_signature_status_with_commitment_and_history(signature, client.commitment(), true)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        solana_sdk::transaction::TransactionError,
+        spl_token_2022::solana_zk_sdk::encryption::{auth_encryption::AeKey, elgamal::ElGamalKeypair},
+        spl_token_confidential_transfer_proof_generation::transfer::transfer_split_proof_data,
+    };
+
+    fn test_proofs() -> TransferProofs {
+        let source_elgamal_keypair = ElGamalKeypair::new_rand();
+        let aes_key = AeKey::new_rand();
+        let available_balance = source_elgamal_keypair.pubkey().encrypt(100_u64);
+
+        let proof_data = transfer_split_proof_data(
+            &available_balance,
+            &aes_key.encrypt(100),
+            40,
+            &source_elgamal_keypair,
+            &aes_key,
+            ElGamalKeypair::new_rand().pubkey(),
+            Some(ElGamalKeypair::new_rand().pubkey()),
+        )
+        .unwrap();
+
+        TransferProofs {
+            equality: proof_data.equality_proof_data,
+            ciphertext_validity: proof_data.ciphertext_validity_proof_data_with_ciphertext.proof_data,
+            range: proof_data.range_proof_data,
+            auditor_ciphertext_lo: proof_data.ciphertext_validity_proof_data_with_ciphertext.ciphertext_lo,
+            auditor_ciphertext_hi: proof_data.ciphertext_validity_proof_data_with_ciphertext.ciphertext_hi,
+            source_available_balance: available_balance.into(),
+            new_decryptable_available_balance: aes_key.encrypt(60).into(),
+        }
+    }
+
+    #[test]
+    fn test_plan_roundtrip_preserves_proofs() -> Result<(), Box<dyn Error>> {
+        let proofs = test_proofs();
+        let context_state_accounts = [Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique()];
+
+        let mut plan = TransferPlan::new(&Pubkey::new_unique(), &Pubkey::new_unique(), 40);
+        plan.set_proofs(&context_state_accounts, &proofs);
+
+        let serialized = serde_json::to_string(&plan)?;
+        assert!(!serialized.contains("keypair"), "no context account secrets are persisted");
+        let restored: TransferPlan = serde_json::from_str(&serialized)?;
+
+        assert_eq!(restored.context_state_pubkeys()?, context_state_accounts);
+        assert_eq!(restored.proofs()?.to_bytes(), proofs.to_bytes());
+        assert_eq!(restored.proofs()?.hash(), proofs.hash());
+        Ok(())
+    }
+
+    #[test]
+    fn test_tampered_proofs_are_rejected() {
+        let mut plan = TransferPlan::new(&Pubkey::new_unique(), &Pubkey::new_unique(), 40);
+        plan.set_proofs(&[Pubkey::new_unique(), Pubkey::new_unique(), Pubkey::new_unique()], &test_proofs());
+
+        plan.proof_data_hash = Some(Hash::new_unique().to_string());
+        assert!(plan.proofs().is_err());
+
+        plan.proof_data = Some(BASE64_STANDARD.encode([0u8; 16]));
+        assert!(plan.proofs().is_err());
+    }
+
+    #[test]
+    fn test_reconcile_pending_stage() -> Result<(), Box<dyn Error>> {
+        let mut plan = TransferPlan::new(&Pubkey::new_unique(), &Pubkey::new_unique(), 40);
+        plan.completed_stages.push(Signature::new_unique().to_string());
+
+        // A stage that never landed (and no longer can) is retried from the same index.
+        plan.pending_signature = Some(Signature::new_unique().to_string());
+        plan.pending_transaction = Some(BASE64_STANDARD.encode([0u8; 16]));
+        plan.resolve_pending(None);
+        assert_eq!(plan.next_stage(), 1);
+        assert!(plan.pending_signature.is_none());
+        assert!(plan.pending_transaction.is_none());
+
+        plan.pending_signature = Some(Signature::new_unique().to_string());
+        plan.resolve_pending(Some(Err(TransactionError::AccountNotFound)));
+        assert_eq!(plan.next_stage(), 1);
+
+        // A stage that landed counts as confirmed, and survives a save/load roundtrip.
+        let landed = Signature::new_unique().to_string();
+        plan.pending_signature = Some(landed.clone());
+        plan.resolve_pending(Some(Ok(())));
+        let restored: TransferPlan = serde_json::from_str(&serde_json::to_string(&plan)?)?;
+        assert_eq!(restored.next_stage(), 2);
+        assert_eq!(restored.completed_stages[1], landed);
+        Ok(())
+    }
+}
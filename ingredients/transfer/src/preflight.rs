@@ -0,0 +1,304 @@
+use {
+    spl_token_2022::{
+        extension::{
+            confidential_transfer::{
+                account_info::TransferAccountInfo, ConfidentialTransferAccount, ConfidentialTransferMint,
+                MAXIMUM_DEPOSIT_TRANSFER_AMOUNT,
+            },
+            BaseStateWithExtensions, StateWithExtensionsOwned,
+        },
+        solana_zk_sdk::encryption::{
+            auth_encryption::{AeCiphertext, AeKey},
+            elgamal::ElGamalPubkey,
+            pod::elgamal::PodElGamalPubkey,
+        },
+        state::{Account, Mint},
+    },
+    std::{error::Error, fmt},
+};
+
+/// Reasons a confidential transfer is rejected before any proof is generated or rent is spent.
+#[derive(Debug, PartialEq)]
+pub enum PreflightError {
+    /// Confidential transfer amounts are encrypted as a 16-bit and a 32-bit part.
+    AmountTooLarge { amount: u64 },
+    SenderBalanceUndecryptable,
+    InsufficientBalance { available: u64, requested: u64 },
+    RecipientNotConfidential,
+    RecipientNotApproved,
+    RecipientCreditsDisabled,
+    /// The recipient must apply its pending balance before it can receive more confidential credits.
+    RecipientCreditLimitReached { pending_balance_credit_counter: u64, maximum: u64 },
+    RecipientFrozen,
+    MissingAuditor,
+}
+
+impl fmt::Display for PreflightError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::AmountTooLarge { amount } => write!(
+                f,
+                "transfer amount {} exceeds the 48-bit maximum of {}",
+                amount, MAXIMUM_DEPOSIT_TRANSFER_AMOUNT
+            ),
+            Self::SenderBalanceUndecryptable => write!(f, "sender's available balance could not be decrypted"),
+            Self::InsufficientBalance { available, requested } => write!(
+                f,
+                "insufficient available balance: {} available, {} requested",
+                available, requested
+            ),
+            Self::RecipientNotConfidential => write!(f, "recipient account is not configured for confidential transfers"),
+            Self::RecipientNotApproved => write!(f, "recipient account has not been approved by the mint authority"),
+            Self::RecipientCreditsDisabled => write!(f, "recipient account does not accept confidential credits"),
+            Self::RecipientCreditLimitReached { pending_balance_credit_counter, maximum } => write!(
+                f,
+                "recipient account has {} pending credits, the maximum of {}; it must apply its pending balance first",
+                pending_balance_credit_counter, maximum
+            ),
+            Self::RecipientFrozen => write!(f, "recipient account is frozen"),
+            Self::MissingAuditor => write!(f, "mint has no auditor ElGamal pubkey"),
+        }
+    }
+}
+
+impl Error for PreflightError {}
+
+/// Keys checked during pre-flight and needed afterwards to generate the transfer proofs.
+pub struct PreflightKeys {
+    pub recipient_elgamal_pubkey: ElGamalPubkey,
+    pub auditor_elgamal_pubkey: ElGamalPubkey,
+}
+
+/// Checks that a confidential transfer can succeed with the given account states.
+pub fn check_transfer(
+    sender_transfer_account_info: &TransferAccountInfo,
+    sender_aes_key: &AeKey,
+    recipient_account: &StateWithExtensionsOwned<Account>,
+    mint_account: &StateWithExtensionsOwned<Mint>,
+    amount: u64,
+) -> Result<PreflightKeys, PreflightError> {
+    if amount > MAXIMUM_DEPOSIT_TRANSFER_AMOUNT {
+        return Err(PreflightError::AmountTooLarge { amount });
+    }
+
+    // The decryptable balance tracks the available balance, and is far cheaper to decrypt than ElGamal
+    let decryptable_available_balance: AeCiphertext = sender_transfer_account_info
+        .decryptable_available_balance
+        .try_into()
+        .map_err(|_| PreflightError::SenderBalanceUndecryptable)?;
+    let available = sender_aes_key
+        .decrypt(&decryptable_available_balance)
+        .ok_or(PreflightError::SenderBalanceUndecryptable)?;
+    if available < amount {
+        return Err(PreflightError::InsufficientBalance { available, requested: amount });
+    }
+
+    let recipient_extension = recipient_account
+        .get_extension::<ConfidentialTransferAccount>()
+        .map_err(|_| PreflightError::RecipientNotConfidential)?;
+    if !bool::from(recipient_extension.approved) {
+        return Err(PreflightError::RecipientNotApproved);
+    }
+    if !bool::from(recipient_extension.allow_confidential_credits) {
+        return Err(PreflightError::RecipientCreditsDisabled);
+    }
+    let pending_balance_credit_counter = u64::from(recipient_extension.pending_balance_credit_counter);
+    let maximum = u64::from(recipient_extension.maximum_pending_balance_credit_counter);
+    if pending_balance_credit_counter >= maximum {
+        return Err(PreflightError::RecipientCreditLimitReached { pending_balance_credit_counter, maximum });
+    }
+    if recipient_account.base.is_frozen() {
+        return Err(PreflightError::RecipientFrozen);
+    }
+    let recipient_elgamal_pubkey: ElGamalPubkey = recipient_extension
+        .elgamal_pubkey
+        .try_into()
+        .map_err(|_| PreflightError::RecipientNotConfidential)?;
+
+    let auditor_elgamal_pubkey: ElGamalPubkey = mint_account
+        .get_extension::<ConfidentialTransferMint>()
+        .ok()
+        .and_then(|extension| Option::<PodElGamalPubkey>::from(extension.auditor_elgamal_pubkey))
+        .and_then(|pubkey| pubkey.try_into().ok())
+        .ok_or(PreflightError::MissingAuditor)?;
+
+    Ok(PreflightKeys {
+        recipient_elgamal_pubkey,
+        auditor_elgamal_pubkey,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        bytemuck::Zeroable,
+        spl_token_2022::{
+            extension::{ExtensionType, StateWithExtensionsMut},
+            solana_zk_sdk::encryption::elgamal::ElGamalKeypair,
+            state::AccountState,
+        },
+    };
+
+    const AVAILABLE_BALANCE: u64 = 100;
+    const MAXIMUM_PENDING_BALANCE_CREDIT_COUNTER: u64 = 65536;
+
+    struct Recipient {
+        /// Whether the account has the `ConfidentialTransferAccount` extension at all.
+        confidential: bool,
+        approved: bool,
+        allow_confidential_credits: bool,
+        pending_balance_credit_counter: u64,
+        frozen: bool,
+    }
+
+    impl Default for Recipient {
+        fn default() -> Self {
+            Self {
+                confidential: true,
+                approved: true,
+                allow_confidential_credits: true,
+                pending_balance_credit_counter: 0,
+                frozen: false,
+            }
+        }
+    }
+
+    fn sender_transfer_account_info(aes_key: &AeKey) -> TransferAccountInfo {
+        let mut extension = ConfidentialTransferAccount::zeroed();
+        extension.decryptable_available_balance = aes_key.encrypt(AVAILABLE_BALANCE).into();
+        TransferAccountInfo::new(&extension)
+    }
+
+    fn recipient_account(recipient: &Recipient) -> StateWithExtensionsOwned<Account> {
+        let extension_types = if recipient.confidential { vec![ExtensionType::ConfidentialTransferAccount] } else { vec![] };
+        let space = ExtensionType::try_calculate_account_len::<Account>(&extension_types).unwrap();
+        let mut data = vec![0; space];
+
+        let mut state = StateWithExtensionsMut::<Account>::unpack_uninitialized(&mut data).unwrap();
+        let account_state = if recipient.frozen { AccountState::Frozen } else { AccountState::Initialized };
+        state.base = Account { state: account_state, ..Account::default() };
+        state.pack_base();
+        if !recipient.confidential {
+            return StateWithExtensionsOwned::unpack(data).unwrap();
+        }
+        state.init_account_type().unwrap();
+
+        let extension = state.init_extension::<ConfidentialTransferAccount>(true).unwrap();
+        extension.elgamal_pubkey = (*ElGamalKeypair::new_rand().pubkey()).into();
+        extension.approved = recipient.approved.into();
+        extension.allow_confidential_credits = recipient.allow_confidential_credits.into();
+        extension.pending_balance_credit_counter = recipient.pending_balance_credit_counter.into();
+        extension.maximum_pending_balance_credit_counter = MAXIMUM_PENDING_BALANCE_CREDIT_COUNTER.into();
+
+        StateWithExtensionsOwned::unpack(data).unwrap()
+    }
+
+    fn mint_account(with_auditor: bool) -> StateWithExtensionsOwned<Mint> {
+        let space = ExtensionType::try_calculate_account_len::<Mint>(&[ExtensionType::ConfidentialTransferMint]).unwrap();
+        let mut data = vec![0; space];
+
+        let mut state = StateWithExtensionsMut::<Mint>::unpack_uninitialized(&mut data).unwrap();
+        state.base = Mint { is_initialized: true, ..Mint::default() };
+        state.pack_base();
+        state.init_account_type().unwrap();
+
+        let extension = state.init_extension::<ConfidentialTransferMint>(true).unwrap();
+        if with_auditor {
+            extension.auditor_elgamal_pubkey =
+                Some(PodElGamalPubkey::from(*ElGamalKeypair::new_rand().pubkey())).try_into().unwrap();
+        }
+
+        StateWithExtensionsOwned::unpack(data).unwrap()
+    }
+
+    #[test]
+    fn test_check_transfer() {
+        let sender_aes_key = AeKey::new_rand();
+        let sender_transfer_account_info = sender_transfer_account_info(&sender_aes_key);
+        let mint = mint_account(true);
+        let mint_without_auditor = mint_account(false);
+
+        let cases = [
+            ("whole balance", AVAILABLE_BALANCE, Recipient::default(), &mint, Ok(())),
+            (
+                "insufficient balance",
+                AVAILABLE_BALANCE + 1,
+                Recipient::default(),
+                &mint,
+                Err(PreflightError::InsufficientBalance { available: AVAILABLE_BALANCE, requested: AVAILABLE_BALANCE + 1 }),
+            ),
+            (
+                "amount over 48 bits",
+                MAXIMUM_DEPOSIT_TRANSFER_AMOUNT + 1,
+                Recipient::default(),
+                &mint,
+                Err(PreflightError::AmountTooLarge { amount: MAXIMUM_DEPOSIT_TRANSFER_AMOUNT + 1 }),
+            ),
+            (
+                "last credit before the limit",
+                AVAILABLE_BALANCE,
+                Recipient { pending_balance_credit_counter: MAXIMUM_PENDING_BALANCE_CREDIT_COUNTER - 1, ..Recipient::default() },
+                &mint,
+                Ok(()),
+            ),
+            (
+                "credit limit reached",
+                AVAILABLE_BALANCE,
+                Recipient { pending_balance_credit_counter: MAXIMUM_PENDING_BALANCE_CREDIT_COUNTER, ..Recipient::default() },
+                &mint,
+                Err(PreflightError::RecipientCreditLimitReached {
+                    pending_balance_credit_counter: MAXIMUM_PENDING_BALANCE_CREDIT_COUNTER,
+                    maximum: MAXIMUM_PENDING_BALANCE_CREDIT_COUNTER,
+                }),
+            ),
+            (
+                "recipient not approved",
+                AVAILABLE_BALANCE,
+                Recipient { approved: false, ..Recipient::default() },
+                &mint,
+                Err(PreflightError::RecipientNotApproved),
+            ),
+            (
+                "recipient credits disabled",
+                AVAILABLE_BALANCE,
+                Recipient { allow_confidential_credits: false, ..Recipient::default() },
+                &mint,
+                Err(PreflightError::RecipientCreditsDisabled),
+            ),
+            (
+                "recipient frozen",
+                AVAILABLE_BALANCE,
+                Recipient { frozen: true, ..Recipient::default() },
+                &mint,
+                Err(PreflightError::RecipientFrozen),
+            ),
+            (
+                "recipient not confidential",
+                AVAILABLE_BALANCE,
+                Recipient { confidential: false, ..Recipient::default() },
+                &mint,
+                Err(PreflightError::RecipientNotConfidential),
+            ),
+            (
+                "mint without auditor",
+                AVAILABLE_BALANCE,
+                Recipient::default(),
+                &mint_without_auditor,
+                Err(PreflightError::MissingAuditor),
+            ),
+        ];
+
+        for (name, amount, recipient, mint, expected) in cases {
+            let result = check_transfer(
+                &sender_transfer_account_info,
+                &sender_aes_key,
+                &recipient_account(&recipient),
+                mint,
+                amount,
+            )
+            .map(|_| ());
+            assert_eq!(result, expected, "{}", name);
+        }
+    }
+}
@@ -0,0 +1,85 @@
+use {
+    crate::{
+        abort_plan, continue_plan, generate_transfer_proofs,
+        plan::{TransferPlan, EXECUTE_TRANSFER_STAGE},
+        ProofContextKeypairs,
+    },
+    solana_sdk::{
+        pubkey::Pubkey,
+        signature::{Signature, Signer},
+    },
+    std::{error::Error, str::FromStr, sync::Arc},
+    utils::get_rpc_client,
+};
+
+/// Shields public tokens and sends them on confidentially: deposits `deposit_amount` from the
+/// sender's public balance and confidentially transfers `confidential_transfer_amount` to
+/// `recipient_pubkey`, in the five transactions of a split-proof transfer.
+///
+/// The deposit and its apply ride along in the proof account allocation transaction. The transfer
+/// proofs are generated against the balance the sender will have once that transaction lands,
+/// computed locally, so nothing is re-fetched between the deposit and the transfer. The transfer is
+/// pre-flight checked against that projected balance before anything is sent.
+///
+/// The transfer runs as a `TransferPlan`: if a stage fails before the transfer executes, the plan is
+/// aborted and the proof accounts closed (the deposit itself stays applied). If only the final close
+/// fails, the plan can be finished with `resume_transfer`.
+///
+/// With `use_durable_nonces`, the stages are signed against the sender's durable nonce as each one is sent.
+///
+/// Returns the signature of the transfer itself.
+pub async fn deposit_and_transfer(
+    sender_keypair: Arc<dyn Signer>,
+    recipient_pubkey: &Pubkey,
+    deposit_amount: u64,
+    confidential_transfer_amount: u64,
+    use_durable_nonces: bool,
+) -> Result<Signature, Box<dyn Error>> {
+    let client = get_rpc_client()?;
+
+    let deposit_and_apply =
+        deposit_tokens::deposit_and_apply_instructions(&client, deposit_amount, sender_keypair.as_ref())?;
+
+    let (proofs, _) = generate_transfer_proofs(
+        &client,
+        &sender_keypair,
+        recipient_pubkey,
+        confidential_transfer_amount,
+        Some(deposit_and_apply.new_transfer_account_info),
+    ).await?;
+
+    // Persist the plan before anything lands on-chain, so a failed stage can be aborted or resumed.
+    let context_keypairs = ProofContextKeypairs::generate();
+    let mut plan = TransferPlan::new(&sender_keypair.pubkey(), recipient_pubkey, confidential_transfer_amount);
+    plan.set_proofs(&context_keypairs.pubkeys(), &proofs);
+    plan.save()?;
+    println!("Transfer plan id: {}", plan.plan_id);
+    let plan_id = plan.plan_id.clone();
+
+    let e = match continue_plan(
+        &client,
+        plan,
+        &sender_keypair,
+        Some(&context_keypairs),
+        &deposit_and_apply.instructions,
+        use_durable_nonces,
+    ).await {
+        Ok(signature) => return Ok(signature),
+        Err(e) => e,
+    };
+
+    // A plan `continue_plan` aborted itself is already removed, and has nothing left to close.
+    let Ok(mut plan) = TransferPlan::load(&plan_id) else {
+        return Err(e);
+    };
+    plan.reconcile(&client)?;
+    if plan.is_complete() {
+        return Ok(Signature::from_str(&plan.completed_stages[EXECUTE_TRANSFER_STAGE])?);
+    }
+    if plan.next_stage() > EXECUTE_TRANSFER_STAGE {
+        return Err(format!("Closing the proof accounts of transfer plan {} failed; resume it with resume_transfer: {}", plan_id, e).into());
+    }
+
+    abort_plan(plan, sender_keypair, use_durable_nonces).await?;
+    Err(format!("Shield and send failed; transfer plan {} was aborted: {}", plan_id, e).into())
+}
@@ -0,0 +1,72 @@
+use std::error::Error;
+
+use apply_pending_balance::{decrypt_available_balance, decrypt_pending_balance};
+use serde::Serialize;
+use utils::get_rpc_client;
+use solana_sdk::{pubkey::Pubkey, signer::Signer};
+use spl_associated_token_account::get_associated_token_address_with_program_id;
+use spl_token_2022::{
+    extension::{
+        confidential_transfer::ConfidentialTransferAccount,
+        BaseStateWithExtensions, StateWithExtensionsOwned,
+    },
+    solana_zk_sdk::encryption::{auth_encryption::AeKey, elgamal::ElGamalKeypair},
+    state::Account,
+};
+
+/// Public and decrypted confidential balances of a token account, at the time it was read.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct BalanceSnapshot {
+    pub owner: String,
+    pub mint: String,
+    pub token_account: String,
+    pub public_balance: u64,
+    /// Decrypted from `decryptable_available_balance` with the owner's AES key.
+    pub available_balance: u64,
+    /// Decrypted from `pending_balance_lo` and `pending_balance_hi` with the owner's ElGamal secret key.
+    pub pending_balance: u64,
+    /// Deposits and incoming transfers since the pending balance was last applied.
+    pub pending_balance_credit_counter: u64,
+    pub maximum_pending_balance_credit_counter: u64,
+    /// Credit counters recorded by the last `ApplyPendingBalance`; they differ if credits arrived while it was in flight.
+    pub expected_pending_balance_credit_counter: u64,
+    pub actual_pending_balance_credit_counter: u64,
+    pub allow_confidential_credits: bool,
+    pub allow_non_confidential_credits: bool,
+}
+
+/// Reads the balances of `owner`'s associated token account for `mint`, decrypting the confidential
+/// ones with keys derived from the owner's signer.
+pub async fn view_balance(owner: &dyn Signer, mint: &Pubkey) -> Result<BalanceSnapshot, Box<dyn Error>> {
+    let client = get_rpc_client()?;
+
+    let token_account_pubkey = get_associated_token_address_with_program_id(
+        &owner.pubkey(),
+        mint,
+        &spl_token_2022::id(),
+    );
+
+    let elgamal_keypair = ElGamalKeypair::new_from_signer(owner, &token_account_pubkey.to_bytes())?;
+    let aes_key = AeKey::new_from_signer(owner, &token_account_pubkey.to_bytes())?;
+
+    let token_account = StateWithExtensionsOwned::<Account>::unpack(client.get_account(&token_account_pubkey)?.data)?;
+    let confidential_transfer_account = token_account.get_extension::<ConfidentialTransferAccount>()?;
+
+    let snapshot = BalanceSnapshot {
+        owner: owner.pubkey().to_string(),
+        mint: mint.to_string(),
+        token_account: token_account_pubkey.to_string(),
+        public_balance: token_account.base.amount,
+        available_balance: decrypt_available_balance(confidential_transfer_account, &aes_key)?,
+        pending_balance: decrypt_pending_balance(confidential_transfer_account, elgamal_keypair.secret())?,
+        pending_balance_credit_counter: confidential_transfer_account.pending_balance_credit_counter.into(),
+        maximum_pending_balance_credit_counter: confidential_transfer_account.maximum_pending_balance_credit_counter.into(),
+        expected_pending_balance_credit_counter: confidential_transfer_account.expected_pending_balance_credit_counter.into(),
+        actual_pending_balance_credit_counter: confidential_transfer_account.actual_pending_balance_credit_counter.into(),
+        allow_confidential_credits: confidential_transfer_account.allow_confidential_credits.into(),
+        allow_non_confidential_credits: confidential_transfer_account.allow_non_confidential_credits.into(),
+    };
+
+    println!("{}", serde_json::to_string_pretty(&snapshot)?);
+    Ok(snapshot)
+}
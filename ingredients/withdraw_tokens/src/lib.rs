@@ -1,210 +1,539 @@
 use {
-    utils::{get_non_blocking_rpc_client, get_or_create_keypair, load_value, print_transaction_url},
-    solana_sdk::
-        signature::{Keypair, Signer}
-    ,
+    utils::{
+        current_mint, get_rpc_client, jito, load_value, nonce, print_transaction_url,
+        required_signers, PermanentError,
+        jito::JitoConfig,
+        nonce::BlockhashSource,
+        proof_context::context_state_instructions,
+        proof_generation::withdraw_proof_data_parallel,
+    },
+    solana_client::rpc_client::RpcClient,
+    solana_sdk::{
+        hash::Hash,
+        instruction::Instruction,
+        pubkey::Pubkey,
+        signature::{Keypair, Signature, Signer},
+        transaction::Transaction,
+    },
     spl_associated_token_account::
         get_associated_token_address_with_program_id
     ,
     spl_token_2022::{
+        error::TokenError,
         extension::{
             confidential_transfer::{
                 account_info::
                     WithdrawAccountInfo
                 ,
+                instruction::withdraw,
                 ConfidentialTransferAccount,
             },
-            BaseStateWithExtensions,
+            BaseStateWithExtensions, StateWithExtensionsOwned,
         },
-        solana_zk_sdk::
+        solana_zk_sdk::{
             encryption::{
-                auth_encryption::AeKey,
-                elgamal::ElGamalKeypair,
-            }
-        ,
-    },
-    spl_token_client::{
-        client::{ProgramRpcClient, ProgramRpcClientSendTransaction, RpcClientResponse},
-        token::{ProofAccount, Token},
+                auth_encryption::{AeCiphertext, AeKey},
+                elgamal::{ElGamalCiphertext, ElGamalKeypair},
+            },
+            zk_elgamal_proof_program::instruction::{close_context_state, ContextStateInfo},
+        },
+        state::Account,
     },
-    spl_token_confidential_transfer_proof_generation::
-        withdraw::WithdrawProofData
-    ,
-    std::{error::Error, sync::Arc},
+    spl_token_confidential_transfer_proof_extraction::instruction::ProofLocation,
+    spl_token_confidential_transfer_proof_generation::withdraw::WithdrawProofData,
+    std::{error::Error, fmt, sync::{atomic::{AtomicU32, Ordering}, Arc, Mutex}},
 };
 
-pub async fn withdraw_tokens(withdraw_amount: u64, recipient_signer: Arc<dyn Signer>) -> Result<(), Box<dyn Error>> {
-    
-    let mint = get_or_create_keypair("mint")?;
+#[cfg(feature = "dry-run")]
+use utils::dry_run::{DryRun, DryRunReport};
+
+/// Outcome of a completed `withdraw_tokens`.
+#[derive(Clone, Debug)]
+pub struct WithdrawReceipt {
+    /// Signatures of the proof transactions: equality proof, range proof allocation, range proof.
+    pub proof_signatures: Vec<Signature>,
+    pub withdraw_signature: Signature,
+    /// Signature of the transaction closing both proof context state accounts.
+    pub close_signature: Signature,
+    /// Lamports the proof context state accounts held when they were closed.
+    pub rent_reclaimed: u64,
+    pub rent_destination: Pubkey,
+    /// The account's decryptable available balance after the withdrawal, and its plaintext value.
+    pub new_decryptable_available_balance: AeCiphertext,
+    pub new_available_balance: u64,
+}
+
+#[derive(Debug)]
+pub enum WithdrawError {
+    BalanceUndecryptable,
+    InsufficientBalance { available: u64, requested: u64 },
+    /// A transaction failed. The proof context state accounts opened by the completed stages have
+    /// been closed where possible; `open_proof_accounts` lists those still holding rent. Those
+    /// holding a verified proof can still be closed with `sweep_proof_context_accounts`.
+    StageFailed {
+        stage: &'static str,
+        completed_signatures: Vec<Signature>,
+        open_proof_accounts: Vec<Pubkey>,
+        error: String,
+    },
+}
+
+impl fmt::Display for WithdrawError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::BalanceUndecryptable => write!(f, "available balance could not be decrypted"),
+            Self::InsufficientBalance { available, requested } => write!(
+                f,
+                "insufficient available balance: {} available, {} requested",
+                available, requested
+            ),
+            Self::StageFailed { stage, completed_signatures, open_proof_accounts, error } => write!(
+                f,
+                "withdrawal failed at [{}] after {} confirmed transaction(s), leaving {} proof account(s) open: {}",
+                stage,
+                completed_signatures.len(),
+                open_proof_accounts.len(),
+                error
+            ),
+        }
+    }
+}
+
+impl Error for WithdrawError {}
+
+/// Withdraws `withdraw_amount` from the confidential available balance into the public balance.
+///
+/// Rent from the proof context state accounts goes to `rent_destination`, defaulting to the
+/// owner's wallet. With `use_durable_nonces`, transactions use the owner's durable nonces if it has
+/// enough of them; the range proof transaction always uses a recent blockhash.
+///
+/// If a transaction fails, the proof context state accounts opened so far are closed before the
+/// `WithdrawError::StageFailed` is returned, encoding the range proof first if its account was
+/// allocated but not yet verified.
+pub async fn withdraw_tokens(
+    withdraw_amount: u64,
+    recipient_signer: Arc<dyn Signer>,
+    rent_destination: Option<&Pubkey>,
+    use_durable_nonces: bool,
+) -> Result<WithdrawReceipt, Box<dyn Error>> {
+    let client = get_rpc_client()?;
+    let rent_destination = rent_destination.copied().unwrap_or_else(|| recipient_signer.pubkey());
+
+    let prepared = prepare_withdraw(&client, withdraw_amount, recipient_signer.as_ref(), &rent_destination).await?;
+    let blockhash_sources = nonce::blockhash_sources(
+        &recipient_signer.pubkey(),
+        prepared.stages.len(),
+        use_durable_nonces,
+        &[ENCODE_RANGE_PROOF_STAGE],
+    );
+
+    let mut signatures = Vec::with_capacity(prepared.stages.len());
+    let mut rent_reclaimed = 0;
+    for (index, ((stage, instructions), blockhash_source)) in prepared.stages.iter().zip(&blockhash_sources).enumerate() {
+        // The rent is measured right before the close, so it is exactly what closing the accounts returns.
+        let result = match index {
+            CLOSE_STAGE => prepared.proof_account_lamports(&client).map(|lamports| rent_reclaimed = lamports),
+            _ => Ok(()),
+        }
+        .and_then(|()| prepared.send(&client, instructions, recipient_signer.as_ref(), blockhash_source));
+
+        match result {
+            Ok(signature) => {
+                print_transaction_url(stage, &signature.to_string());
+                signatures.push(signature);
+            }
+            Err(error) => {
+                let open_proof_accounts = prepared.abort(&client, recipient_signer.as_ref(), index);
+                return Err(WithdrawError::StageFailed {
+                    stage: *stage,
+                    completed_signatures: signatures,
+                    open_proof_accounts,
+                    error: error.to_string(),
+                }.into());
+            }
+        }
+    }
+
+    let [equality_proof_signature, range_allocation_signature, range_proof_signature, withdraw_signature, close_signature] = signatures[..] else {
+        return Err(format!("Expected {} withdrawal signatures, got {}", WITHDRAW_STAGE_COUNT, signatures.len()).into());
+    };
+
+    Ok(WithdrawReceipt {
+        proof_signatures: vec![equality_proof_signature, range_allocation_signature, range_proof_signature],
+        withdraw_signature,
+        close_signature,
+        rent_reclaimed,
+        rent_destination,
+        new_decryptable_available_balance: prepared.new_decryptable_available_balance,
+        new_available_balance: prepared.new_available_balance,
+    })
+}
+
+/// Unsigned transactions of a full withdrawal (proof accounts, withdraw, close), in order.
+struct PreparedWithdraw {
+    /// Label and instructions of each transaction.
+    stages: Vec<(&'static str, Vec<Instruction>)>,
+    equality_proof_context_state_keypair: Keypair,
+    range_proof_context_state_keypair: Keypair,
+    /// Used by the dry run to decrypt the balance it ends with.
+    #[cfg(feature = "dry-run")]
+    recipient_associated_token_address: Pubkey,
+    #[cfg(feature = "dry-run")]
+    receiver_aes_key: AeKey,
+    new_decryptable_available_balance: AeCiphertext,
+    new_available_balance: u64,
+}
+
+/// Index of the "Encode Range Proof" stage in `PreparedWithdraw::stages`. Its transaction is too
+/// close to the packet limit to take a durable nonce advance.
+const ENCODE_RANGE_PROOF_STAGE: usize = 2;
+/// Index of the "Withdraw Transaction" stage in `PreparedWithdraw::stages`.
+const WITHDRAW_STAGE: usize = 3;
+/// Index of the "Close Proof Context State Accounts" stage in `PreparedWithdraw::stages`.
+const CLOSE_STAGE: usize = 4;
+/// Number of transactions in `PreparedWithdraw::stages`.
+const WITHDRAW_STAGE_COUNT: usize = 5;
+
+impl PreparedWithdraw {
+    /// Signs each stage with the signers it requires, from the recipient and the proof context state keypairs.
+    fn sign(&self, recipient_signer: &dyn Signer, recent_blockhash: Hash) -> Result<Vec<Transaction>, Box<dyn Error>> {
+        let signers = self.signers(recipient_signer);
+        self.stages
+            .iter()
+            .map(|(_, instructions)| {
+                let mut transaction = Transaction::new_with_payer(instructions, Some(&recipient_signer.pubkey()));
+                transaction.try_sign(&required_signers(&transaction.message, &signers)[..], recent_blockhash)?;
+                Ok(transaction)
+            })
+            .collect()
+    }
+
+    fn signers<'a>(&'a self, recipient_signer: &'a dyn Signer) -> [&'a dyn Signer; 3] {
+        [
+            recipient_signer,
+            &self.equality_proof_context_state_keypair,
+            &self.range_proof_context_state_keypair,
+        ]
+    }
+
+    fn labels(&self) -> Vec<&'static str> {
+        self.stages.iter().map(|(label, _)| *label).collect()
+    }
+
+    /// Signs `instructions` with the signers they require and sends them.
+    fn send(
+        &self,
+        client: &RpcClient,
+        instructions: &[Instruction],
+        recipient_signer: &dyn Signer,
+        blockhash_source: &BlockhashSource,
+    ) -> Result<Signature, Box<dyn Error>> {
+        let signers = self.signers(recipient_signer);
+        let transaction = Transaction::new_with_payer(instructions, Some(&recipient_signer.pubkey()));
+        let transaction_signers = required_signers(&transaction.message, &signers);
+        let transaction = nonce::signed_transaction(
+            client,
+            instructions,
+            &recipient_signer.pubkey(),
+            &transaction_signers[..],
+            blockhash_source,
+        )?;
+        Ok(client.send_and_confirm_transaction(&transaction)?)
+    }
+
+    /// Lamports currently held by both proof context state accounts.
+    fn proof_account_lamports(&self, client: &RpcClient) -> Result<u64, Box<dyn Error>> {
+        let proof_accounts = [
+            self.equality_proof_context_state_keypair.pubkey(),
+            self.range_proof_context_state_keypair.pubkey(),
+        ];
+        Ok(client
+            .get_multiple_accounts(&proof_accounts)?
+            .iter()
+            .flatten()
+            .map(|account| account.lamports)
+            .sum())
+    }
+
+    /// Best-effort cleanup after stage `failed_stage` failed: encodes the range proof if its account
+    /// was allocated but not yet verified (an empty context state account can't be closed), then
+    /// closes each proof context state account the completed stages opened. Returns those still open.
+    fn abort(&self, client: &RpcClient, recipient_signer: &dyn Signer, failed_stage: usize) -> Vec<Pubkey> {
+        let equality_pubkey = self.equality_proof_context_state_keypair.pubkey();
+        let range_pubkey = self.range_proof_context_state_keypair.pubkey();
+        let opened_accounts = match failed_stage {
+            0 => vec![],
+            1 => vec![equality_pubkey],
+            _ => vec![equality_pubkey, range_pubkey],
+        };
+
+        if failed_stage == ENCODE_RANGE_PROOF_STAGE {
+            let (stage, instructions) = &self.stages[ENCODE_RANGE_PROOF_STAGE];
+            match self.send(client, instructions, recipient_signer, &BlockhashSource::Recent) {
+                Ok(signature) => print_transaction_url(&format!("Abort: {}", stage), &signature.to_string()),
+                Err(e) => println!("Failed to encode the range proof: {}", e),
+            }
+        }
+
+        // One transaction per account, so an account that can't be closed doesn't keep the other open.
+        let (_, close_instructions) = &self.stages[CLOSE_STAGE];
+        opened_accounts
+            .into_iter()
+            .filter(|proof_account| {
+                let Some(close_instruction) = close_instructions.iter().find(|instruction| instruction.accounts[0].pubkey == *proof_account) else {
+                    return true;
+                };
+                match self.send(client, std::slice::from_ref(close_instruction), recipient_signer, &BlockhashSource::Recent) {
+                    Ok(signature) => {
+                        print_transaction_url("Abort: Close Proof Context State Account", &signature.to_string());
+                        false
+                    }
+                    Err(e) => {
+                        println!("Failed to close proof account {} (it still holds rent): {}", proof_account, e);
+                        true
+                    }
+                }
+            })
+            .collect()
+    }
+}
+
+/// Builds every withdrawal transaction up front, generating the proofs against the current on-chain balance.
+async fn prepare_withdraw(
+    client: &RpcClient,
+    withdraw_amount: u64,
+    recipient_signer: &dyn Signer,
+    rent_destination: &Pubkey,
+) -> Result<PreparedWithdraw, Box<dyn Error>> {
+    let mint = current_mint()?;
     let decimals = load_value("mint_decimals")?;
     let recipient_associated_token_address = get_associated_token_address_with_program_id(
         &recipient_signer.pubkey(),
-        &mint.pubkey(),
+        &mint,
         &spl_token_2022::id(),
     );
 
-    // A "non-blocking" RPC client (for async calls)
-    let token = {
-        let rpc_client = get_non_blocking_rpc_client()?;
-
-        let program_client =
-            ProgramRpcClient::new(Arc::new(rpc_client), ProgramRpcClientSendTransaction);
-
-        // Create a "token" client, to use various helper functions for Token Extensions
-        Token::new(
-            Arc::new(program_client),
-            &spl_token_2022::id(),
-            &mint.pubkey(),
-            Some(decimals),
-            recipient_signer.clone(),
-            // ^^^ HACK: Unsafe clone of keypair due to Rust lifetime issues.
-        )
-    };
-
     let receiver_elgamal_keypair =
-        ElGamalKeypair::new_from_signer(&recipient_signer, &recipient_associated_token_address.to_bytes())
-            .unwrap();
+        ElGamalKeypair::new_from_signer(recipient_signer, &recipient_associated_token_address.to_bytes())?;
     let receiver_aes_key =
-        AeKey::new_from_signer(&recipient_signer, &recipient_associated_token_address.to_bytes()).unwrap();
-
-    // Get recipient token account data
-    let token_account = token
-        .get_account_info(&recipient_associated_token_address)
-        .await?;
+        AeKey::new_from_signer(recipient_signer, &recipient_associated_token_address.to_bytes())?;
 
-    // Unpack the ConfidentialTransferAccount extension portion of the token account data
-    let extension_data = token_account.get_extension::<ConfidentialTransferAccount>()?;
+    let withdraw_account_info = WithdrawAccountInfo::new(
+        StateWithExtensionsOwned::<Account>::unpack(client.get_account(&recipient_associated_token_address)?.data)?
+            .get_extension::<ConfidentialTransferAccount>()?,
+    );
 
-    // Confidential Transfer extension information needed to construct a `Withdraw` instruction.
-    let withdraw_account_info = WithdrawAccountInfo::new(extension_data);
+    let decryptable_available_balance: AeCiphertext = withdraw_account_info
+        .decryptable_available_balance
+        .try_into()
+        .map_err(|_| WithdrawError::BalanceUndecryptable)?;
+    let available = receiver_aes_key
+        .decrypt(&decryptable_available_balance)
+        .ok_or(WithdrawError::BalanceUndecryptable)?;
+    if available < withdraw_amount {
+        return Err(WithdrawError::InsufficientBalance { available, requested: withdraw_amount }.into());
+    }
 
-    // Authority for the withdraw proof account (to close the account)
-    let context_state_authority = &recipient_signer;
+    let WithdrawProofData {
+        equality_proof_data,
+        range_proof_data,
+    } = generate_withdraw_proof_data(&withdraw_account_info, &receiver_elgamal_keypair, &receiver_aes_key, withdraw_amount).await?;
 
     let equality_proof_context_state_keypair = Keypair::new();
     let equality_proof_context_state_pubkey = equality_proof_context_state_keypair.pubkey();
     let range_proof_context_state_keypair = Keypair::new();
     let range_proof_context_state_pubkey = range_proof_context_state_keypair.pubkey();
+    let payer_pubkey = recipient_signer.pubkey();
 
-    // Create a withdraw proof data
-    let WithdrawProofData {
-        equality_proof_data,
-        range_proof_data,
-    } = withdraw_account_info.generate_proof_data(
+    let (equality_create_ix, equality_verify_ix, _) = context_state_instructions(
+        client,
+        &payer_pubkey,
+        &equality_proof_context_state_pubkey,
+        &payer_pubkey,
+        &equality_proof_data,
+    )?;
+    let (range_create_ix, range_verify_ix, _) = context_state_instructions(
+        client,
+        &payer_pubkey,
+        &range_proof_context_state_pubkey,
+        &payer_pubkey,
+        &range_proof_data,
+    )?;
+
+    let new_decryptable_available_balance =
+        withdraw_account_info.new_decryptable_available_balance(withdraw_amount, &receiver_aes_key)?;
+    let withdraw_instructions = withdraw(
+        &spl_token_2022::id(),
+        &recipient_associated_token_address,
+        &mint,
         withdraw_amount,
-        &receiver_elgamal_keypair,
-        &receiver_aes_key,
+        decimals,
+        &new_decryptable_available_balance.clone().into(),
+        &payer_pubkey,
+        &[],
+        ProofLocation::ContextStateAccount(&equality_proof_context_state_pubkey),
+        ProofLocation::ContextStateAccount(&range_proof_context_state_pubkey),
     )?;
 
-    // Generate withdrawal proof accounts
-    let context_state_authority_pubkey = context_state_authority.pubkey();
-    let create_equality_proof_signer = &[&equality_proof_context_state_keypair];
-    let create_range_proof_signer = &[&range_proof_context_state_keypair];
-
-    match token
-        .confidential_transfer_create_context_state_account(
-            &equality_proof_context_state_pubkey,
-            &context_state_authority_pubkey,
-            &equality_proof_data,
-            false,
-            create_equality_proof_signer,
-        )
-        .await
-    {
-        Ok(RpcClientResponse::Signature(signature)) => {
-            print_transaction_url("Equality Proof Context State Account", &signature.to_string());
-        }
-        _ => {
-            panic!("Unexpected result from create equality proof context state account");
-        }
-    }
-    match token
-        .confidential_transfer_create_context_state_account(
-            &range_proof_context_state_pubkey,
-            &context_state_authority_pubkey,
-            &range_proof_data,
-            true,
-            create_range_proof_signer,
-        )
-        .await
-    {
-        Ok(RpcClientResponse::Signature(signature)) => {
-            print_transaction_url("Range Proof Context State Account", &signature.to_string());
-        }
-        _ => {
-            panic!("Unexpected result from create range proof context state account");
-        }
-    }
+    let close_instructions: Vec<Instruction> = [equality_proof_context_state_pubkey, range_proof_context_state_pubkey]
+        .iter()
+        .map(|context_state_account| {
+            close_context_state(
+                ContextStateInfo {
+                    context_state_account,
+                    context_state_authority: &payer_pubkey,
+                },
+                rent_destination,
+            )
+        })
+        .collect();
 
-    // do the withdrawal
-    match token
-        .confidential_transfer_withdraw(
-            &recipient_associated_token_address,
+    // The range proof is too large to verify in the same transaction as its allocation.
+    Ok(PreparedWithdraw {
+        stages: vec![
+            ("Equality Proof Context State Account", vec![equality_create_ix, equality_verify_ix]),
+            ("Allocate Range Proof Context State Account", vec![range_create_ix]),
+            ("Encode Range Proof", vec![range_verify_ix]),
+            ("Withdraw Transaction", withdraw_instructions),
+            ("Close Proof Context State Accounts", close_instructions),
+        ],
+        equality_proof_context_state_keypair,
+        range_proof_context_state_keypair,
+        #[cfg(feature = "dry-run")]
+        recipient_associated_token_address,
+        #[cfg(feature = "dry-run")]
+        receiver_aes_key,
+        new_decryptable_available_balance,
+        new_available_balance: available - withdraw_amount,
+    })
+}
+
+/// Withdraws as a single Jito bundle: either every transaction lands, or none does and no proof
+/// context state accounts are left behind. The withdrawal is prepared once; bundles that don't land
+/// are re-signed against a fresh blockhash and resubmitted with an escalating tip, like
+/// `transfer::with_split_proofs_atomic`. Before a failure is reported, the withdraw signature of
+/// every attempt is looked up in case one landed anyway.
+///
+/// The bundle holds all five transactions, so only `TipPlacement::Execute` is supported; `First`
+/// and `Last` are rejected before anything is prepared.
+pub async fn withdraw_tokens_atomic(withdraw_amount: u64, recipient_signer: Arc<dyn Signer>, jito_config: &JitoConfig) -> Result<(), Box<dyn Error>> {
+    jito_config.check_tip_placement(WITHDRAW_STAGE_COUNT)?;
+    let client = get_rpc_client()?;
+    let prepared = prepare_withdraw(&client, withdraw_amount, recipient_signer.as_ref(), &recipient_signer.pubkey()).await?;
+    let signers = prepared.signers(recipient_signer.as_ref());
+
+    let attempts = AtomicU32::new(0);
+    let submitted_withdraw_signatures = Mutex::new(vec![]);
+    let result = utils::run_with_retry(5, || async {
+        let attempt = attempts.fetch_add(1, Ordering::Relaxed);
+
+        let recent_blockhash = client.get_latest_blockhash()?;
+        let mut transactions = prepared.sign(recipient_signer.as_ref(), recent_blockhash)?;
+
+        let jito_tip_ix = jito::create_jito_tip_instruction(jito_config, recipient_signer.pubkey(), attempt).await?;
+        let tip_index = jito::place_tip(
+            &mut transactions,
+            jito_tip_ix,
+            jito_config.tip_placement,
+            WITHDRAW_STAGE,
             &recipient_signer.pubkey(),
-            Some(&ProofAccount::ContextAccount(
-                equality_proof_context_state_pubkey,
-            )),
-            Some(&ProofAccount::ContextAccount(
-                range_proof_context_state_pubkey,
-            )),
-            withdraw_amount,
-            decimals,
-            Some(withdraw_account_info),
-            &receiver_elgamal_keypair,
-            &receiver_aes_key,
-            &[&recipient_signer],
-        )
-        .await
-    {
-        Ok(RpcClientResponse::Signature(signature)) => {
-            print_transaction_url("Withdraw Transaction", &signature.to_string());
-        }
-        Ok(RpcClientResponse::Transaction(_)) => {
-            panic!("Unexpected result from withdraw: transaction");
+            &signers,
+            recent_blockhash,
+        ).map_err(PermanentError)?;
+
+        let mut labels = prepared.labels();
+        let withdraw_label = labels[WITHDRAW_STAGE];
+        if let Some(tip_index) = tip_index {
+            labels.insert(tip_index, "Jito Tip");
         }
-        Ok(RpcClientResponse::Simulation(_)) => {
-            panic!("Unexpected result from withdraw: simulation");
+
+        // Retries only change the blockhash and the tip, so the first simulation stands for all of them.
+        if attempt == 0 {
+            jito::simulate_or_fail(jito_config, &labels, &transactions, &signers).await?;
         }
-        Err(e) => {
-            panic!("Unexpected result from withdraw: {:?}", e);
+
+        let withdraw_index = labels
+            .iter()
+            .position(|label| *label == withdraw_label)
+            .ok_or_else(|| PermanentError("Bundle has no withdraw transaction".into()))?;
+        submitted_withdraw_signatures.lock().unwrap().push(transactions[withdraw_index].signatures[0]);
+
+        let bundled_signatures = jito::submit_and_confirm_bundle(jito_config, &transactions).await?;
+        for (label, signature) in labels.iter().zip(&bundled_signatures) {
+            print_transaction_url(label, signature);
         }
+
+        Ok(())
+    }).await;
+
+    // An attempt reported as not landed may have landed anyway; the shared proof accounts make sure
+    // at most one of them did.
+    if let Err(e) = result {
+        let submitted_withdraw_signatures = submitted_withdraw_signatures.into_inner().unwrap();
+        let Some(signature) = jito::landed_signature(&client, &submitted_withdraw_signatures)? else {
+            return Err(e);
+        };
+        print_transaction_url("Withdraw Transaction (earlier attempt)", &signature.to_string());
     }
 
-    // close context state account
-    let close_context_state_signer = &[&context_state_authority];
-
-    match token
-        .confidential_transfer_close_context_state_account(
-            &equality_proof_context_state_pubkey,
-            &recipient_associated_token_address,
-            &context_state_authority_pubkey,
-            close_context_state_signer,
-        )
-        .await
-    {
-        Ok(RpcClientResponse::Signature(signature)) => {
-            print_transaction_url("Close Equality Proof Context State Account", &signature.to_string());
-        }
-        _ => {
-            panic!("Unexpected result from close equality proof context state account");
+    Ok(())
+}
+
+/// Executes the full withdrawal (proof accounts, withdraw, close) in a local fork of the current
+/// on-chain state, without submitting anything.
+#[cfg(feature = "dry-run")]
+pub async fn withdraw_tokens_dry_run(withdraw_amount: u64, recipient_signer: Arc<dyn Signer>) -> Result<DryRunReport, Box<dyn Error>> {
+    let client = get_rpc_client()?;
+    let mint = current_mint()?;
+    let prepared = prepare_withdraw(&client, withdraw_amount, recipient_signer.as_ref(), &recipient_signer.pubkey()).await?;
+    let recipient_associated_token_address = prepared.recipient_associated_token_address;
+
+    let mut dry_run = DryRun::fork(
+        &[recipient_signer.pubkey(), recipient_associated_token_address, mint],
+        &[spl_token_2022::id()],
+    ).await?;
+
+    let signers = prepared.signers(recipient_signer.as_ref());
+    for (label, instructions) in &prepared.stages {
+        let transaction = Transaction::new_with_payer(instructions, Some(&recipient_signer.pubkey()));
+        let transaction_signers = required_signers(&transaction.message, &signers);
+        if !dry_run.process(label, transaction, &transaction_signers[..]).await? {
+            break;
         }
     }
-    match token
-        .confidential_transfer_close_context_state_account(
-            &range_proof_context_state_pubkey,
-            &recipient_associated_token_address,
-            &context_state_authority_pubkey,
-            close_context_state_signer,
-        )
-        .await
-    {
-        Ok(RpcClientResponse::Signature(signature)) => {
-            print_transaction_url("Close Range Proof Context State Account", &signature.to_string());
-        }
-        _ => {
-            panic!("Unexpected result from close range proof context state account");
+
+    let mut report = dry_run.finish().await?;
+    for balance in report.final_balances.iter_mut().filter(|balance| balance.address == recipient_associated_token_address) {
+        if let Some(account) = &balance.account {
+            let decryptable_available_balance: AeCiphertext = StateWithExtensionsOwned::<Account>::unpack(account.data.clone())?
+                .get_extension::<ConfidentialTransferAccount>()?
+                .decryptable_available_balance
+                .try_into()?;
+            balance.confidential_available_balance = prepared.receiver_aes_key.decrypt(&decryptable_available_balance);
         }
     }
-    
-    Ok(())
-}
\ No newline at end of file
+
+    report.print();
+    Ok(report)
+}
+
+async fn generate_withdraw_proof_data(
+    withdraw_account_info: &WithdrawAccountInfo,
+    receiver_elgamal_keypair: &ElGamalKeypair,
+    receiver_aes_key: &AeKey,
+    withdraw_amount: u64,
+) -> Result<WithdrawProofData, Box<dyn Error>> {
+    let current_available_balance: ElGamalCiphertext = withdraw_account_info.available_balance.try_into()?;
+    let current_decryptable_available_balance: AeCiphertext = withdraw_account_info.decryptable_available_balance.try_into()?;
+    let current_balance = receiver_aes_key
+        .decrypt(&current_decryptable_available_balance)
+        .ok_or(TokenError::AccountDecryption)?;
+
+    // The equality and range proofs are generated in parallel on the dedicated proof pool
+    withdraw_proof_data_parallel(
+        &current_available_balance,
+        current_balance,
+        withdraw_amount,
+        receiver_elgamal_keypair,
+    ).await
+}
@@ -4,15 +4,22 @@ mod recipe {
     use std::sync::Arc;
 
     use apply_pending_balance;
+    use approve_account;
+    use close_account;
+    use collect_fees;
+    use configure_credits;
     use deposit_tokens;
-    use utils::{get_or_create_keypair, get_or_create_keypair_elgamal};
+    use migrate_account;
+    use utils::{current_mint, get_or_create_keypair, get_or_create_keypair_elgamal, jito::JitoConfig};
     use mint_tokens;
     use setup_mint;
     use setup_mint_confidential;
     use setup_participants;
     use setup_token_account;
     use solana_sdk::{native_token::LAMPORTS_PER_SOL, signer::Signer};
+    use spl_associated_token_account::get_associated_token_address_with_program_id;
     use transfer;
+    use view_balance;
     use withdraw_tokens;
 
     #[tokio::test(flavor = "multi_thread", worker_threads = 8)]
@@ -24,15 +31,15 @@ mod recipe {
         let absolute_mint_authority = get_or_create_keypair("absolute_mint_authority")?;
 
         // Step 1. Setup participants
-        setup_participants::setup_basic_participant(&fee_payer_keypair.pubkey(), None, 2 * LAMPORTS_PER_SOL).await?;
-        setup_participants::setup_basic_participant(&sender_keypair.pubkey(), Some(&fee_payer_keypair), LAMPORTS_PER_SOL).await?;
-        setup_participants::setup_basic_participant(&recipient_keypair.pubkey(), Some(&fee_payer_keypair), LAMPORTS_PER_SOL/5).await?;
+        setup_participants::setup_basic_participant(&fee_payer_keypair.pubkey(), None, 2 * LAMPORTS_PER_SOL, false).await?;
+        setup_participants::setup_basic_participant(&sender_keypair.pubkey(), Some(&fee_payer_keypair), LAMPORTS_PER_SOL, false).await?;
+        setup_participants::setup_basic_participant(&recipient_keypair.pubkey(), Some(&fee_payer_keypair), LAMPORTS_PER_SOL/5, false).await?;
 
         // Step 2. Create mint
-        setup_mint_confidential::create_mint(&absolute_mint_authority, &auditor_elgamal_keypair).await?;
+        setup_mint_confidential::create_mint(&absolute_mint_authority, &auditor_elgamal_keypair, true, false).await?;
 
         // Step 3. Setup token account for sender
-        setup_token_account::setup_token_account(&sender_keypair).await?;
+        setup_token_account::setup_token_account(&sender_keypair, false).await?;
 
         // Step 4. Confidentially mint tokens
         mint_tokens::go_with_confidential_mintburn(&absolute_mint_authority, &sender_keypair.pubkey(), 100_00, &auditor_elgamal_keypair).await?;
@@ -49,36 +56,36 @@ mod recipe {
         let absolute_mint_authority = get_or_create_keypair("absolute_mint_authority")?;
 
         // Step 1. Setup participants
-        setup_participants::setup_basic_participant(&fee_payer_keypair.pubkey(), None, 2 * LAMPORTS_PER_SOL).await?;
-        setup_participants::setup_basic_participant(&sender_keypair.pubkey(), Some(&fee_payer_keypair), LAMPORTS_PER_SOL/2).await?;
-        setup_participants::setup_basic_participant(&recipient_keypair.pubkey(), Some(&fee_payer_keypair), LAMPORTS_PER_SOL/5).await?;
+        setup_participants::setup_basic_participant(&fee_payer_keypair.pubkey(), None, 2 * LAMPORTS_PER_SOL, false).await?;
+        setup_participants::setup_basic_participant(&sender_keypair.pubkey(), Some(&fee_payer_keypair), LAMPORTS_PER_SOL/2, false).await?;
+        setup_participants::setup_basic_participant(&recipient_keypair.pubkey(), Some(&fee_payer_keypair), LAMPORTS_PER_SOL/5, false).await?;
 
         // Step 2. Create mint
-        setup_mint::create_mint(&absolute_mint_authority, &auditor_elgamal_keypair).await?;
+        setup_mint::create_mint(&absolute_mint_authority, &auditor_elgamal_keypair, true, None, false).await?;
 
         // Step 3. Setup token account for sender
-        setup_token_account::setup_token_account(&sender_keypair).await?;
+        setup_token_account::setup_token_account(&sender_keypair, false).await?;
 
         // Step 4. Mint tokens
-        mint_tokens::go(&absolute_mint_authority, &sender_keypair.pubkey(), 100_00).await?;
+        mint_tokens::go(&absolute_mint_authority, &sender_keypair.pubkey(), 100_00, false).await?;
 
         // Step 5. Deposit tokens
-        deposit_tokens::deposit_tokens(50_00, &sender_keypair).await?;
+        deposit_tokens::deposit_tokens(50_00, &sender_keypair, false).await?;
 
         // Step 6. Apply pending balance
-        apply_pending_balance::apply_pending_balance(&sender_keypair).await?;
+        apply_pending_balance::apply_pending_balance(&sender_keypair, false).await?;
 
         // Step 7. Create recipient token account
-        setup_token_account::setup_token_account(&recipient_keypair).await?;
+        setup_token_account::setup_token_account(&recipient_keypair, false).await?;
 
         // Step 8. Transfer tokens with split proofs
-        transfer::with_split_proofs(sender_keypair.clone(), recipient_keypair.clone(), 50_00).await?;
+        transfer::with_split_proofs(sender_keypair.clone(), recipient_keypair.clone(), 50_00, false).await?;
 
         // Step 9. Apply recipient's pending balance
-        apply_pending_balance::apply_pending_balance(&recipient_keypair).await?;
+        apply_pending_balance::apply_pending_balance(&recipient_keypair, false).await?;
 
         // Step 10. Withdraw tokens
-        withdraw_tokens::withdraw_tokens(20_00, recipient_keypair.clone()).await?;
+        withdraw_tokens::withdraw_tokens(20_00, recipient_keypair.clone(), None, false).await?;
 
         // Step 11. Auditor asserts last transfer amount
         global_auditor_assert::last_transfer_amount(50_00, &auditor_elgamal_keypair).await?;
@@ -86,6 +93,217 @@ mod recipe {
         Ok(())
     }
 
+    #[tokio::test(flavor = "multi_thread", worker_threads = 8)]
+    async fn manual_approval_recipe() -> Result<(), Box<dyn Error>> {
+        let sender_keypair = Arc::new(get_or_create_keypair("sender_keypair")?);
+        let fee_payer_keypair = get_or_create_keypair("fee_payer_keypair")?;
+        let auditor_elgamal_keypair = get_or_create_keypair_elgamal("auditor_elgamal")?;
+        let absolute_mint_authority = get_or_create_keypair("absolute_mint_authority")?;
+        let mint = current_mint()?;
+
+        // Step 1. Setup participants
+        setup_participants::setup_basic_participant(&fee_payer_keypair.pubkey(), None, 2 * LAMPORTS_PER_SOL, false).await?;
+        setup_participants::setup_basic_participant(&absolute_mint_authority.pubkey(), Some(&fee_payer_keypair), LAMPORTS_PER_SOL/10, false).await?;
+        setup_participants::setup_basic_participant(&sender_keypair.pubkey(), Some(&fee_payer_keypair), LAMPORTS_PER_SOL/2, false).await?;
+
+        // Step 2. Create mint requiring approval of new accounts
+        setup_mint::create_mint(&absolute_mint_authority, &auditor_elgamal_keypair, false, None, false).await?;
+
+        // Step 3. Setup token account for sender; it waits in the approval queue
+        setup_token_account::setup_token_account(&sender_keypair, false).await?;
+        let sender_token_account = get_associated_token_address_with_program_id(
+            &sender_keypair.pubkey(),
+            &mint,
+            &spl_token_2022::id(),
+        );
+        assert_eq!(approve_account::pending_approvals(&mint)?, vec![sender_token_account]);
+
+        // Step 4. Confidential transfer authority approves the queue
+        approve_account::approve_pending_accounts(&absolute_mint_authority, &mint, false, |_| true).await?;
+        assert!(approve_account::pending_approvals(&mint)?.is_empty());
+
+        // Step 5. Approved account can now deposit
+        mint_tokens::go(&absolute_mint_authority, &sender_keypair.pubkey(), 100_00, false).await?;
+        deposit_tokens::deposit_and_apply(50_00, &sender_keypair, false).await?;
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 8)]
+    async fn shield_and_send_recipe() -> Result<(), Box<dyn Error>> {
+        let sender_keypair = Arc::new(get_or_create_keypair("sender_keypair")?);
+        let recipient_keypair = Arc::new(get_or_create_keypair("recipient_keypair")?);
+        let fee_payer_keypair = get_or_create_keypair("fee_payer_keypair")?;
+        let auditor_elgamal_keypair = get_or_create_keypair_elgamal("auditor_elgamal")?;
+        let absolute_mint_authority = get_or_create_keypair("absolute_mint_authority")?;
+
+        // Step 1. Setup participants
+        setup_participants::setup_basic_participant(&fee_payer_keypair.pubkey(), None, 2 * LAMPORTS_PER_SOL, false).await?;
+        setup_participants::setup_basic_participant(&sender_keypair.pubkey(), Some(&fee_payer_keypair), LAMPORTS_PER_SOL/2, false).await?;
+        setup_participants::setup_basic_participant(&recipient_keypair.pubkey(), Some(&fee_payer_keypair), LAMPORTS_PER_SOL/5, false).await?;
+
+        // Step 2. Create mint
+        setup_mint::create_mint(&absolute_mint_authority, &auditor_elgamal_keypair, true, None, false).await?;
+
+        // Step 3. Setup token accounts for sender and recipient
+        setup_token_account::setup_token_account(&sender_keypair, false).await?;
+        setup_token_account::setup_token_account(&recipient_keypair, false).await?;
+
+        // Recipient only accepts confidential transfers
+        configure_credits::configure_credits(recipient_keypair.as_ref(), configure_credits::CreditPolicy::CONFIDENTIAL_ONLY, false).await?;
+
+        // Step 4. Mint tokens
+        mint_tokens::go(&absolute_mint_authority, &sender_keypair.pubkey(), 100_00, false).await?;
+
+        // Step 5. Deposit and apply in one transaction
+        let available_balance = deposit_tokens::deposit_and_apply(20_00, &sender_keypair, false).await?;
+        assert_eq!(available_balance, 20_00);
+
+        // Step 6. Shield more public tokens and send them on confidentially
+        transfer::shield::deposit_and_transfer(sender_keypair.clone(), &recipient_keypair.pubkey(), 30_00, 50_00, false).await?;
+
+        // Step 7. Auditor asserts last transfer amount
+        global_auditor_assert::last_transfer_amount(50_00, &auditor_elgamal_keypair).await?;
+
+        // Step 8. Sender and recipient view their balances
+        let mint = current_mint()?;
+        let sender_balance = view_balance::view_balance(sender_keypair.as_ref(), &mint).await?;
+        assert_eq!((sender_balance.public_balance, sender_balance.available_balance), (50_00, 0));
+        let recipient_balance = view_balance::view_balance(recipient_keypair.as_ref(), &mint).await?;
+        assert_eq!((recipient_balance.pending_balance, recipient_balance.pending_balance_credit_counter), (50_00, 1));
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 8)]
+    async fn close_out_recipe() -> Result<(), Box<dyn Error>> {
+        let sender_keypair = Arc::new(get_or_create_keypair("sender_keypair")?);
+        let recipient_keypair = Arc::new(get_or_create_keypair("recipient_keypair")?);
+        let fee_payer_keypair = get_or_create_keypair("fee_payer_keypair")?;
+        let auditor_elgamal_keypair = get_or_create_keypair_elgamal("auditor_elgamal")?;
+        let absolute_mint_authority = get_or_create_keypair("absolute_mint_authority")?;
+        let mint = current_mint()?;
+
+        // Step 1. Setup participants
+        setup_participants::setup_basic_participant(&fee_payer_keypair.pubkey(), None, 2 * LAMPORTS_PER_SOL, false).await?;
+        setup_participants::setup_basic_participant(&sender_keypair.pubkey(), Some(&fee_payer_keypair), LAMPORTS_PER_SOL/2, false).await?;
+        setup_participants::setup_basic_participant(&recipient_keypair.pubkey(), Some(&fee_payer_keypair), LAMPORTS_PER_SOL/5, false).await?;
+
+        // Step 2. Create mint
+        setup_mint::create_mint(&absolute_mint_authority, &auditor_elgamal_keypair, true, None, false).await?;
+
+        // Step 3. Setup token accounts for sender and recipient
+        setup_token_account::setup_token_account(&sender_keypair, false).await?;
+        setup_token_account::setup_token_account(&recipient_keypair, false).await?;
+
+        // Step 4. Mint tokens
+        mint_tokens::go(&absolute_mint_authority, &sender_keypair.pubkey(), 100_00, false).await?;
+
+        // Step 5. Deposit tokens, leaving them pending
+        deposit_tokens::deposit_tokens(50_00, &sender_keypair, false).await?;
+
+        // Step 6. Close out the sender's account, sending its public tokens to the recipient
+        let recipient_token_account = get_associated_token_address_with_program_id(
+            &recipient_keypair.pubkey(),
+            &mint,
+            &spl_token_2022::id(),
+        );
+        let receipt = close_account::close_account(sender_keypair.clone(), &recipient_token_account, true, false).await?;
+        assert_eq!(receipt.public_balance_transferred, 100_00);
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 8)]
+    async fn transfer_fee_recipe() -> Result<(), Box<dyn Error>> {
+        let sender_keypair = Arc::new(get_or_create_keypair("sender_keypair")?);
+        let recipient_keypair = Arc::new(get_or_create_keypair("recipient_keypair")?);
+        let fee_payer_keypair = get_or_create_keypair("fee_payer_keypair")?;
+        let auditor_elgamal_keypair = get_or_create_keypair_elgamal("auditor_elgamal")?;
+        let withdraw_withheld_authority_elgamal_keypair = get_or_create_keypair_elgamal("withdraw_withheld_authority_elgamal")?;
+        let absolute_mint_authority = Arc::new(get_or_create_keypair("absolute_mint_authority")?);
+
+        // Step 1. Setup participants (the mint authority also withdraws withheld fees, paying for the proof account)
+        setup_participants::setup_basic_participant(&fee_payer_keypair.pubkey(), None, 2 * LAMPORTS_PER_SOL, false).await?;
+        setup_participants::setup_basic_participant(&absolute_mint_authority.pubkey(), Some(&fee_payer_keypair), LAMPORTS_PER_SOL/10, false).await?;
+        setup_participants::setup_basic_participant(&sender_keypair.pubkey(), Some(&fee_payer_keypair), LAMPORTS_PER_SOL/2, false).await?;
+        setup_participants::setup_basic_participant(&recipient_keypair.pubkey(), Some(&fee_payer_keypair), LAMPORTS_PER_SOL/5, false).await?;
+
+        // Step 2. Create mint charging 1% on transfers, capped at 10.00
+        let transfer_fee = setup_mint::ConfidentialTransferFeeParams {
+            transfer_fee_basis_points: 100,
+            maximum_fee: 10_00,
+            withdraw_withheld_authority_elgamal_keypair: &withdraw_withheld_authority_elgamal_keypair,
+        };
+        setup_mint::create_mint(&absolute_mint_authority, &auditor_elgamal_keypair, true, Some(&transfer_fee), false).await?;
+
+        // Step 3. Setup token accounts for sender and recipient
+        setup_token_account::setup_token_account(&sender_keypair, false).await?;
+        setup_token_account::setup_token_account(&recipient_keypair, false).await?;
+
+        // Step 4. Mint, deposit and apply
+        mint_tokens::go(&absolute_mint_authority, &sender_keypair.pubkey(), 100_00, false).await?;
+        deposit_tokens::deposit_and_apply(50_00, &sender_keypair, false).await?;
+
+        // Step 5. Transfer with fee; 0.50 is withheld in the recipient's account
+        transfer::fee::transfer_with_fee(sender_keypair.clone(), recipient_keypair.clone(), 50_00, false).await?;
+
+        let mint = current_mint()?;
+        let recipient_token_account = get_associated_token_address_with_program_id(
+            &recipient_keypair.pubkey(),
+            &mint,
+            &spl_token_2022::id(),
+        );
+        assert_eq!(collect_fees::withheld_amount_in_accounts(&[recipient_token_account], &withdraw_withheld_authority_elgamal_keypair)?, 50);
+
+        // Step 6. Harvest the withheld fee into the mint
+        collect_fees::harvest_withheld_tokens_to_mint(&[recipient_token_account], false).await?;
+        assert_eq!(collect_fees::withheld_amount_in_mint(&withdraw_withheld_authority_elgamal_keypair)?, 50);
+
+        // Step 7. Withdraw the harvested fee to the sender's confidential balance
+        let withdrawn_amount = collect_fees::withdraw_withheld_tokens_from_mint(
+            absolute_mint_authority.clone(),
+            &withdraw_withheld_authority_elgamal_keypair,
+            sender_keypair.as_ref(),
+            false,
+        )
+        .await?;
+        assert_eq!(withdrawn_amount, 50);
+        assert_eq!(collect_fees::withheld_amount_in_mint(&withdraw_withheld_authority_elgamal_keypair)?, 0);
+
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 8)]
+    async fn migrate_account_recipe() -> Result<(), Box<dyn Error>> {
+        let sender_keypair = Arc::new(get_or_create_keypair("sender_keypair")?);
+        let recipient_keypair = Arc::new(get_or_create_keypair("recipient_keypair")?);
+        let fee_payer_keypair = get_or_create_keypair("fee_payer_keypair")?;
+        let auditor_elgamal_keypair = get_or_create_keypair_elgamal("auditor_elgamal")?;
+        let absolute_mint_authority = get_or_create_keypair("absolute_mint_authority")?;
+
+        // Step 1. Setup participants
+        setup_participants::setup_basic_participant(&fee_payer_keypair.pubkey(), None, 2 * LAMPORTS_PER_SOL, false).await?;
+        setup_participants::setup_basic_participant(&sender_keypair.pubkey(), Some(&fee_payer_keypair), LAMPORTS_PER_SOL/2, false).await?;
+        setup_participants::setup_basic_participant(&recipient_keypair.pubkey(), Some(&fee_payer_keypair), LAMPORTS_PER_SOL/5, false).await?;
+
+        // Step 2. Create mint
+        setup_mint::create_mint(&absolute_mint_authority, &auditor_elgamal_keypair, true, None, false).await?;
+
+        // Step 3. Setup token account for the old signer and fund it
+        setup_token_account::setup_token_account(&sender_keypair, false).await?;
+        mint_tokens::go(&absolute_mint_authority, &sender_keypair.pubkey(), 100_00, false).await?;
+        deposit_tokens::deposit_and_apply(40_00, &sender_keypair, false).await?;
+        deposit_tokens::deposit_tokens(10_00, &sender_keypair, false).await?;
+
+        // Step 4. Migrate everything to an account owned by the new signer
+        let receipt = migrate_account::migrate_account(sender_keypair.clone(), recipient_keypair.clone(), false).await?;
+        assert_eq!(receipt.migrated_amount, 50_00);
+        assert_eq!(receipt.close.public_balance_transferred, 50_00);
+
+        Ok(())
+    }
+
     #[tokio::test(flavor = "multi_thread", worker_threads = 8)]
     async fn basic_transfer_recipe_atomic() -> Result<(), Box<dyn Error>> {
         let sender_keypair = Arc::new(get_or_create_keypair("sender_keypair")?);
@@ -95,36 +313,37 @@ mod recipe {
         let absolute_mint_authority = get_or_create_keypair("absolute_mint_authority")?;
 
         // Step 1. Setup participants
-        setup_participants::setup_basic_participant(&fee_payer_keypair.pubkey(), None, 2 * LAMPORTS_PER_SOL).await?;
-        setup_participants::setup_basic_participant(&sender_keypair.pubkey(), Some(&fee_payer_keypair), LAMPORTS_PER_SOL/2).await?;
-        setup_participants::setup_basic_participant(&recipient_keypair.pubkey(), Some(&fee_payer_keypair), LAMPORTS_PER_SOL/5).await?;
+        setup_participants::setup_basic_participant(&fee_payer_keypair.pubkey(), None, 2 * LAMPORTS_PER_SOL, false).await?;
+        setup_participants::setup_basic_participant(&sender_keypair.pubkey(), Some(&fee_payer_keypair), LAMPORTS_PER_SOL/2, false).await?;
+        setup_participants::setup_basic_participant(&recipient_keypair.pubkey(), Some(&fee_payer_keypair), LAMPORTS_PER_SOL/5, false).await?;
 
         // Step 2. Create mint
-        setup_mint::create_mint(&absolute_mint_authority, &auditor_elgamal_keypair).await?;
+        setup_mint::create_mint(&absolute_mint_authority, &auditor_elgamal_keypair, true, None, false).await?;
 
         // Step 3. Setup token account for sender
-        setup_token_account::setup_token_account(&sender_keypair).await?;
+        setup_token_account::setup_token_account(&sender_keypair, false).await?;
 
         // Step 4. Mint tokens
-        mint_tokens::go(&absolute_mint_authority, &sender_keypair.pubkey(), 100_00).await?;
+        mint_tokens::go(&absolute_mint_authority, &sender_keypair.pubkey(), 100_00, false).await?;
 
         // Step 5. Deposit tokens
-        deposit_tokens::deposit_tokens(50_00, &sender_keypair).await?;
+        deposit_tokens::deposit_tokens(50_00, &sender_keypair, false).await?;
 
         // Step 6. Apply pending balance
-        apply_pending_balance::apply_pending_balance(&sender_keypair).await?;
+        apply_pending_balance::apply_pending_balance(&sender_keypair, false).await?;
 
         // Step 7. Create recipient token account
-        setup_token_account::setup_token_account(&recipient_keypair).await?;
+        setup_token_account::setup_token_account(&recipient_keypair, false).await?;
 
         // Step 8. Transfer tokens with split proofs
-        transfer::with_split_proofs_atomic(sender_keypair.clone(), recipient_keypair.clone(), 50_00).await?;
+        let jito_config = JitoConfig::from_env()?;
+        transfer::with_split_proofs_atomic(sender_keypair.clone(), recipient_keypair.clone(), 50_00, &jito_config).await?;
 
         // Step 9. Apply recipient's pending balance
-        apply_pending_balance::apply_pending_balance(&recipient_keypair).await?;
+        apply_pending_balance::apply_pending_balance(&recipient_keypair, false).await?;
 
         // Step 10. Withdraw tokens
-        withdraw_tokens::withdraw_tokens(20_00, recipient_keypair.clone()).await?;
+        withdraw_tokens::withdraw_tokens_atomic(20_00, recipient_keypair.clone(), &jito_config).await?;
 
         // Step 11. Auditor asserts last transfer amount
         global_auditor_assert::last_transfer_amount(50_00, &auditor_elgamal_keypair).await?;
@@ -153,36 +372,36 @@ mod recipe {
         let absolute_mint_authority = get_or_create_keypair("absolute_mint_authority")?;
 
         // Step 1. Setup participants
-        setup_participants::setup_basic_participant(&fee_payer_keypair.pubkey(), None, 2 * LAMPORTS_PER_SOL).await?;
-        setup_participants::setup_basic_participant(&sender_signer.pubkey(), Some(&fee_payer_keypair), LAMPORTS_PER_SOL/2).await?;
-        setup_participants::setup_basic_participant(&recipient_signer.pubkey(), Some(&fee_payer_keypair), LAMPORTS_PER_SOL/5).await?;
+        setup_participants::setup_basic_participant(&fee_payer_keypair.pubkey(), None, 2 * LAMPORTS_PER_SOL, true).await?;
+        setup_participants::setup_basic_participant(&sender_signer.pubkey(), Some(&fee_payer_keypair), LAMPORTS_PER_SOL/2, true).await?;
+        setup_participants::setup_basic_participant(&recipient_signer.pubkey(), Some(&fee_payer_keypair), LAMPORTS_PER_SOL/5, true).await?;
 
         // Step 2. Create mint
-        setup_mint::create_mint(&absolute_mint_authority, &auditor_elgamal_keypair).await?;
+        setup_mint::create_mint(&absolute_mint_authority, &auditor_elgamal_keypair, true, None, true).await?;
 
         // Step 3. Setup token account for sender
-        setup_token_account::setup_token_account(&sender_signer).await?;
+        setup_token_account::setup_token_account(&sender_signer, true).await?;
 
         // Step 4. Mint tokens
-        mint_tokens::go(&absolute_mint_authority, &sender_signer.pubkey(), 100_00).await?;
+        mint_tokens::go(&absolute_mint_authority, &sender_signer.pubkey(), 100_00, true).await?;
 
         // Step 5. Deposit tokens
-        deposit_tokens::deposit_tokens(50_00, &sender_signer).await?;
+        deposit_tokens::deposit_tokens(50_00, &sender_signer, true).await?;
 
         // Step 6. Apply pending balance
-        apply_pending_balance::apply_pending_balance(&sender_signer).await?;
+        apply_pending_balance::apply_pending_balance(&sender_signer, true).await?;
 
         // Step 7. Create recipient token account
-        setup_token_account::setup_token_account(&recipient_signer).await?;
+        setup_token_account::setup_token_account(&recipient_signer, true).await?;
 
         // Step 8. Transfer tokens with split proofs
-        transfer::with_split_proofs(sender_signer.clone(), recipient_signer.clone(), 50_00).await?;
+        transfer::with_split_proofs(sender_signer.clone(), recipient_signer.clone(), 50_00, true).await?;
 
         // Step 9. Apply recipient's pending balance
-        apply_pending_balance::apply_pending_balance(&recipient_signer).await?;
+        apply_pending_balance::apply_pending_balance(&recipient_signer, true).await?;
 
         // Step 10. Withdraw tokens
-        withdraw_tokens::withdraw_tokens(20_00, recipient_signer.clone()).await?;
+        withdraw_tokens::withdraw_tokens(20_00, recipient_signer.clone(), None, true).await?;
 
         // Step 11. Auditor asserts last transfer amount
         global_auditor_assert::last_transfer_amount(50_00, &auditor_elgamal_keypair).await?;
@@ -204,36 +423,36 @@ mod recipe {
         let absolute_mint_authority = get_or_create_keypair("absolute_mint_authority")?;
 
         // Step 1. Setup participants
-        setup_participants::setup_basic_participant(&fee_payer_keypair.pubkey(), None, 2 * LAMPORTS_PER_SOL).await?;
-        setup_participants::setup_basic_participant(&sender_signer.pubkey(), Some(&fee_payer_keypair), LAMPORTS_PER_SOL/2).await?;
-        setup_participants::setup_basic_participant(&recipient_signer.pubkey(), Some(&fee_payer_keypair), LAMPORTS_PER_SOL/5).await?;
+        setup_participants::setup_basic_participant(&fee_payer_keypair.pubkey(), None, 2 * LAMPORTS_PER_SOL, true).await?;
+        setup_participants::setup_basic_participant(&sender_signer.pubkey(), Some(&fee_payer_keypair), LAMPORTS_PER_SOL/2, true).await?;
+        setup_participants::setup_basic_participant(&recipient_signer.pubkey(), Some(&fee_payer_keypair), LAMPORTS_PER_SOL/5, true).await?;
 
         // Step 2. Create mint
-        setup_mint::create_mint(&absolute_mint_authority, &auditor_elgamal_keypair).await?;
+        setup_mint::create_mint(&absolute_mint_authority, &auditor_elgamal_keypair, true, None, true).await?;
 
         // Step 3. Setup token account for sender
-        setup_token_account::setup_token_account(&sender_signer).await?;
+        setup_token_account::setup_token_account(&sender_signer, true).await?;
 
         // Step 4. Mint tokens
-        mint_tokens::go(&absolute_mint_authority, &sender_signer.pubkey(), 100_00).await?;
+        mint_tokens::go(&absolute_mint_authority, &sender_signer.pubkey(), 100_00, true).await?;
 
         // Step 5. Deposit tokens
-        deposit_tokens::deposit_tokens(50_00, &sender_signer).await?;
+        deposit_tokens::deposit_tokens(50_00, &sender_signer, true).await?;
 
         // Step 6. Apply pending balance
-        apply_pending_balance::apply_pending_balance(&sender_signer).await?;
+        apply_pending_balance::apply_pending_balance(&sender_signer, true).await?;
 
         // Step 7. Create recipient token account
-        setup_token_account::setup_token_account(&recipient_signer).await?;
+        setup_token_account::setup_token_account(&recipient_signer, true).await?;
 
         // Step 8. Transfer tokens with split proofs
-        transfer::with_split_proofs(sender_signer.clone(), recipient_signer.clone(), 50_00).await?;
+        transfer::with_split_proofs(sender_signer.clone(), recipient_signer.clone(), 50_00, true).await?;
 
         // Step 9. Apply recipient's pending balance
-        apply_pending_balance::apply_pending_balance(&recipient_signer).await?;
+        apply_pending_balance::apply_pending_balance(&recipient_signer, true).await?;
 
         // Step 10. Withdraw tokens
-        withdraw_tokens::withdraw_tokens(20_00, recipient_signer.clone()).await?;
+        withdraw_tokens::withdraw_tokens(20_00, recipient_signer.clone(), None, true).await?;
 
         // Step 11. Auditor asserts last transfer amount
         global_auditor_assert::last_transfer_amount(50_00, &auditor_elgamal_keypair).await?;
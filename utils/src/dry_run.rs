@@ -0,0 +1,223 @@
+use {
+    crate::get_rpc_client,
+    solana_program_test::{ProgramTest, ProgramTestContext},
+    solana_sdk::{
+        account::Account,
+        account_utils::StateMut,
+        bpf_loader_upgradeable::{self, UpgradeableLoaderState},
+        native_loader,
+        pubkey::Pubkey,
+        signers::Signers,
+        sysvar,
+        transaction::Transaction,
+    },
+    std::{collections::HashSet, error::Error},
+};
+
+/// Result of executing one transaction of a dry run.
+#[derive(Debug)]
+pub struct DryRunTransaction {
+    pub label: String,
+    /// Serialized size in bytes (the packet limit is 1232).
+    pub size: usize,
+    pub compute_units_consumed: u64,
+    pub logs: Vec<String>,
+    pub error: Option<String>,
+}
+
+/// State of a tracked account after the dry run.
+#[derive(Debug)]
+pub struct DryRunBalance {
+    pub address: Pubkey,
+    pub lamports: u64,
+    /// `None` if the account does not exist (e.g. it was closed) at the end of the dry run.
+    pub account: Option<Account>,
+    /// Decrypted confidential available balance, filled in by ingredients that can decrypt it.
+    pub confidential_available_balance: Option<u64>,
+}
+
+#[derive(Debug)]
+pub struct DryRunReport {
+    pub transactions: Vec<DryRunTransaction>,
+    pub final_balances: Vec<DryRunBalance>,
+}
+
+impl DryRunReport {
+    /// Whether every transaction of the sequence executed successfully.
+    pub fn succeeded(&self) -> bool {
+        self.transactions.iter().all(|transaction| transaction.error.is_none())
+    }
+
+    /// Index and result of the first transaction that failed, if any.
+    pub fn first_failure(&self) -> Option<(usize, &DryRunTransaction)> {
+        self.transactions.iter().enumerate().find(|(_, transaction)| transaction.error.is_some())
+    }
+
+    pub fn print(&self) {
+        println!("\nDry run: {} transaction(s)", self.transactions.len());
+        for transaction in &self.transactions {
+            println!(
+                "  [{}] {} bytes, {} CU, {}",
+                transaction.label,
+                transaction.size,
+                transaction.compute_units_consumed,
+                transaction.error.as_deref().unwrap_or("ok"),
+            );
+            for log in &transaction.logs {
+                println!("      {}", log);
+            }
+        }
+        println!("Final balances:");
+        for balance in &self.final_balances {
+            match balance.confidential_available_balance {
+                Some(available) => println!(
+                    "  {}: {} lamports, {} confidential available",
+                    balance.address, balance.lamports, available
+                ),
+                None => println!("  {}: {} lamports", balance.address, balance.lamports),
+            }
+        }
+    }
+}
+
+/// A local bank forked from the current on-chain state of a set of accounts.
+///
+/// Multi-transaction flows (e.g. the split-proof transfer) can't be simulated one transaction at a
+/// time against the cluster, since later transactions depend on the state left by earlier ones.
+/// Executing the whole sequence in a fork gives accurate results without submitting anything.
+pub struct DryRun {
+    context: ProgramTestContext,
+    tracked_accounts: Vec<Pubkey>,
+    /// Accounts already loaded from the cluster (or found missing there).
+    loaded_accounts: HashSet<Pubkey>,
+    transactions: Vec<DryRunTransaction>,
+}
+
+impl DryRun {
+    /// Seeds a local bank with `accounts` and `programs` as they currently exist on-chain.
+    /// Balances are reported for `accounts` only. Accounts that don't exist yet are left empty.
+    pub async fn fork(accounts: &[Pubkey], programs: &[Pubkey]) -> Result<Self, Box<dyn Error>> {
+        let client = get_rpc_client()?;
+        let mut program_test = ProgramTest::default();
+
+        let addresses: Vec<Pubkey> = accounts.iter().chain(programs).copied().collect();
+        for (address, account) in addresses.iter().zip(client.get_multiple_accounts(&addresses)?) {
+            let Some(account) = account else {
+                continue;
+            };
+
+            // Builtin programs and sysvars are provided by the fork itself
+            if account.owner == native_loader::id() || account.owner == sysvar::id() {
+                continue;
+            }
+
+            // Upgradeable programs keep their bytecode in a separate program data account
+            if account.owner == bpf_loader_upgradeable::id() {
+                if let Ok(UpgradeableLoaderState::Program { programdata_address }) = account.state() {
+                    program_test.add_account(programdata_address, client.get_account(&programdata_address)?);
+                }
+            }
+
+            program_test.add_account(*address, account);
+        }
+
+        Ok(Self {
+            context: program_test.start_with_context().await,
+            tracked_accounts: accounts.to_vec(),
+            loaded_accounts: addresses.into_iter().collect(),
+            transactions: vec![],
+        })
+    }
+
+    /// Executes a transaction in the fork and records its outcome.
+    /// Returns whether the transaction succeeded; later transactions usually depend on it.
+    pub async fn process<T: Signers + ?Sized>(
+        &mut self,
+        label: &str,
+        mut transaction: Transaction,
+        signers: &T,
+    ) -> Result<bool, Box<dyn Error>> {
+        self.load_missing_accounts(&transaction)?;
+
+        // The cluster's blockhash is unknown to the fork, so re-sign against the fork's own.
+        let recent_blockhash = self.context.banks_client.get_latest_blockhash().await?;
+        transaction.try_sign(signers, recent_blockhash)?;
+
+        let size = bincode::serialized_size(&transaction)? as usize;
+        let result = self
+            .context
+            .banks_client
+            .process_transaction_with_metadata(transaction)
+            .await?;
+
+        let (logs, compute_units_consumed) = result
+            .metadata
+            .map(|metadata| (metadata.log_messages, metadata.compute_units_consumed))
+            .unwrap_or_default();
+        let error = result.result.err().map(|e| e.to_string());
+        let succeeded = error.is_none();
+
+        println!(
+            "Dry run [{}]: {} bytes, {} CU, {}",
+            label,
+            size,
+            compute_units_consumed,
+            error.as_deref().unwrap_or("ok")
+        );
+
+        self.transactions.push(DryRunTransaction {
+            label: label.to_string(),
+            size,
+            compute_units_consumed,
+            logs,
+            error,
+        });
+
+        Ok(succeeded)
+    }
+
+    /// Copies accounts the transaction uses that weren't forked up front (e.g. durable nonce or tip
+    /// accounts) from the cluster. Each account is loaded once, so state changed by the dry run is kept.
+    fn load_missing_accounts(&mut self, transaction: &Transaction) -> Result<(), Box<dyn Error>> {
+        let missing: Vec<Pubkey> = transaction
+            .message
+            .account_keys
+            .iter()
+            .filter(|address| self.loaded_accounts.insert(**address))
+            .copied()
+            .collect();
+        if missing.is_empty() {
+            return Ok(());
+        }
+
+        let client = get_rpc_client()?;
+        for (address, account) in missing.iter().zip(client.get_multiple_accounts(&missing)?) {
+            match account {
+                Some(account) if account.owner != native_loader::id() && account.owner != sysvar::id() && !account.executable => {
+                    self.context.set_account(address, &account.into());
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Ends the dry run, reporting the executed transactions and the final state of the tracked accounts.
+    pub async fn finish(mut self) -> Result<DryRunReport, Box<dyn Error>> {
+        let mut final_balances = Vec::with_capacity(self.tracked_accounts.len());
+        for address in &self.tracked_accounts {
+            let account = self.context.banks_client.get_account(*address).await?;
+            final_balances.push(DryRunBalance {
+                address: *address,
+                lamports: account.as_ref().map_or(0, |account| account.lamports),
+                account,
+                confidential_available_balance: None,
+            });
+        }
+
+        Ok(DryRunReport {
+            transactions: self.transactions,
+            final_balances,
+        })
+    }
+}
@@ -1,30 +1,467 @@
 use {
-    std::time::Duration,
-    solana_sdk::{instruction::Instruction, native_token::LAMPORTS_PER_SOL, pubkey::Pubkey, system_instruction},
-    jito_sdk_rust::JitoJsonRpcSDK,
-    std::str::FromStr,
-    reqwest,
-    serde_json::Value
+    crate::{required_signers, PermanentError, ENV_FILE_PATH},
+    rand::Rng,
+    serde::{de::DeserializeOwned, Deserialize, Serialize},
+    serde_json::json,
+    solana_client::rpc_client::RpcClient,
+    solana_sdk::{
+        hash::Hash,
+        instruction::{AccountMeta, Instruction},
+        message::Message,
+        native_token::LAMPORTS_PER_SOL,
+        packet::PACKET_DATA_SIZE,
+        pubkey::Pubkey,
+        signature::Signature,
+        signer::Signer,
+        system_instruction,
+        transaction::Transaction,
+    },
+    std::{collections::HashMap, env, error::Error, str::FromStr, time::Duration},
+    tokio::time::{sleep, Instant},
+    tokio_util::sync::CancellationToken,
 };
 
+#[cfg(any(test, feature = "test-utils"))]
+pub mod mock_engine;
 
-#[derive(Debug)]
+pub const MAX_RETRIES: u32 = 40;
+pub const RETRY_DELAY: Duration = Duration::from_secs(3);
+pub const DEFAULT_CONFIRMATION_TIMEOUT: Duration = Duration::from_secs(RETRY_DELAY.as_secs() * MAX_RETRIES as u64);
+/// Consecutive "Invalid" in-flight statuses after which a bundle is considered dropped.
+const DROPPED_AFTER_INVALID_POLLS: u32 = 2;
+pub const DEFAULT_JITO_ENGINE_URL: &str = "https://dallas.testnet.block-engine.jito.wtf/api/v1";
+pub const DEFAULT_TIP_FLOOR_URL: &str = "https://bundles.jito.wtf/api/v1/bundles/tip_floor";
+
+/// Most transactions the block engine accepts in one bundle.
+pub const MAX_BUNDLE_TRANSACTIONS: usize = 5;
+/// Most bundle ids the block engine accepts in one status request.
+pub const MAX_BUNDLE_STATUS_IDS: usize = 5;
+
+/// Jito's minimum accepted tip.
+pub const MIN_TIP_LAMPORTS: u64 = 1_000;
+
+/// Landed-tip statistic reported by the tip floor API.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TipPercentile {
+    P25,
+    P50,
+    P75,
+    P95,
+    P99,
+    /// Exponential moving average of the 50th percentile.
+    Ema50,
+}
+
+impl TipPercentile {
+    /// Field name of this statistic in the tip floor response.
+    pub fn field_name(&self) -> &'static str {
+        match self {
+            TipPercentile::P25 => "landed_tips_25th_percentile",
+            TipPercentile::P50 => "landed_tips_50th_percentile",
+            TipPercentile::P75 => "landed_tips_75th_percentile",
+            TipPercentile::P95 => "landed_tips_95th_percentile",
+            TipPercentile::P99 => "landed_tips_99th_percentile",
+            TipPercentile::Ema50 => "ema_landed_tips_50th_percentile",
+        }
+    }
+}
+
+impl FromStr for TipPercentile {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "25" | "p25" => Ok(TipPercentile::P25),
+            "50" | "p50" => Ok(TipPercentile::P50),
+            "75" | "p75" => Ok(TipPercentile::P75),
+            "95" | "p95" => Ok(TipPercentile::P95),
+            "99" | "p99" => Ok(TipPercentile::P99),
+            "ema50" => Ok(TipPercentile::Ema50),
+            _ => Err(format!("Unknown tip percentile: {}", s)),
+        }
+    }
+}
+
+/// Where a bundle's tip is paid.
+///
+/// A dedicated tip transaction takes one of the bundle's five slots, so `First` and `Last` only
+/// suit bundles of up to four transactions; full bundles (the split-proof transfer and the
+/// withdrawal) must tip with `Execute`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TipPlacement {
+    /// A dedicated tip transaction ahead of the bundle.
+    First,
+    /// A dedicated tip transaction at the end of the bundle.
+    Last,
+    /// An extra instruction in the bundle's execute transaction, which is rebuilt and re-signed.
+    Execute,
+}
+
+impl FromStr for TipPlacement {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "first" => Ok(TipPlacement::First),
+            "last" => Ok(TipPlacement::Last),
+            "execute" => Ok(TipPlacement::Execute),
+            _ => Err(format!("Unknown tip placement: {}", s)),
+        }
+    }
+}
+
+/// How much to tip for a bundle.
+#[derive(Clone, Debug)]
+pub struct TipPolicy {
+    pub percentile: TipPercentile,
+    pub min_lamports: u64,
+    pub max_lamports: u64,
+    /// Multiplier applied to the tip on each retry (1.0 keeps the tip constant).
+    pub escalation_factor: f64,
+}
+
+impl Default for TipPolicy {
+    fn default() -> Self {
+        Self {
+            percentile: TipPercentile::P99,
+            min_lamports: MIN_TIP_LAMPORTS,
+            max_lamports: LAMPORTS_PER_SOL / 1_000,
+            escalation_factor: 1.5,
+        }
+    }
+}
+
+impl TipPolicy {
+    /// Tip for the given (zero-based) attempt, derived from the landed tip reported by the tip floor.
+    pub fn tip_lamports(&self, landed_tip_lamports: u64, attempt: u32) -> u64 {
+        let escalated = landed_tip_lamports as f64 * self.escalation_factor.powi(attempt as i32);
+        // Saturating float-to-int conversion, then clamped to the policy bounds.
+        (escalated as u64).clamp(self.min_lamports, self.max_lamports.max(self.min_lamports))
+    }
+}
+
+/// Block engine connection and tipping configuration.
+#[derive(Clone, Debug)]
+pub struct JitoConfig {
+    pub engine_url: String,
+    pub tip_floor_url: String,
+    /// UUID for authenticated (rate-limit exempt) block engine access.
+    pub uuid: Option<String>,
+    pub tip_policy: TipPolicy,
+    pub tip_placement: TipPlacement,
+    /// Interval between bundle status polls.
+    pub poll_interval: Duration,
+    /// Overall time to wait for submitted bundles to resolve.
+    pub confirmation_timeout: Duration,
+    /// Whether to execute bundles in a local fork before submitting (and tipping for) them.
+    /// Needs the `dry-run` feature, and defaults to whether it is enabled.
+    pub simulate_before_submit: bool,
+}
+
+impl Default for JitoConfig {
+    fn default() -> Self {
+        Self {
+            engine_url: DEFAULT_JITO_ENGINE_URL.to_string(),
+            tip_floor_url: DEFAULT_TIP_FLOOR_URL.to_string(),
+            uuid: None,
+            tip_policy: TipPolicy::default(),
+            tip_placement: TipPlacement::Execute,
+            poll_interval: RETRY_DELAY,
+            confirmation_timeout: DEFAULT_CONFIRMATION_TIMEOUT,
+            simulate_before_submit: cfg!(feature = "dry-run"),
+        }
+    }
+}
+
+impl JitoConfig {
+    /// Block engine for a region, e.g. `"ny"`, `"amsterdam"` or `"tokyo"`, on testnet
+    /// (`{region}.testnet.block-engine.jito.wtf`) or mainnet (`{region}.mainnet.block-engine.jito.wtf`).
+    pub fn for_region(region: &str, testnet: bool) -> Self {
+        let network = if testnet { "testnet" } else { "mainnet" };
+        Self {
+            engine_url: format!("https://{}.{}.block-engine.jito.wtf/api/v1", region, network),
+            ..Self::default()
+        }
+    }
+
+    /// Fails if the tip can't be placed in a bundle of `bundle_len` transactions, i.e. a dedicated
+    /// tip transaction is configured but the bundle has no slot left for it. Lets flows reject the
+    /// configuration before generating proofs or tipping.
+    pub fn check_tip_placement(&self, bundle_len: usize) -> Result<(), PermanentError> {
+        if self.tip_placement != TipPlacement::Execute && bundle_len >= MAX_BUNDLE_TRANSACTIONS {
+            return Err(PermanentError(format!(
+                "TipPlacement::{:?} needs a free bundle slot, but this bundle already holds {} transactions; only TipPlacement::Execute is supported",
+                self.tip_placement, bundle_len
+            ).into()));
+        }
+        Ok(())
+    }
+
+    /// Reads the configuration from the environment, falling back to the defaults.
+    ///
+    /// `JITO_ENGINE_URL` takes precedence over `JITO_REGION`. A region resolves to its testnet block
+    /// engine unless `JITO_TESTNET=false`, matching the testnet default engine. Tipping is set
+    /// with `JITO_TIP_PERCENTILE`, `JITO_TIP_MIN_LAMPORTS`, `JITO_TIP_MAX_LAMPORTS` and
+    /// `JITO_TIP_ESCALATION_FACTOR`, and placed per `JITO_TIP_PLACEMENT` (`first`, `last` or
+    /// `execute`). `JITO_SIMULATE=false` skips bundle simulation; `JITO_SIMULATE=true` is an error
+    /// without the `dry-run` feature.
+    pub fn from_env() -> Result<Self, Box<dyn Error>> {
+        dotenvy::from_filename_override(ENV_FILE_PATH).ok();
+
+        let mut config = match (env::var("JITO_ENGINE_URL"), env::var("JITO_REGION")) {
+            (Ok(engine_url), _) => Self { engine_url, ..Self::default() },
+            (Err(_), Ok(region)) => {
+                let testnet = env::var("JITO_TESTNET").map(|value| value != "false").unwrap_or(true);
+                Self::for_region(&region, testnet)
+            }
+            _ => Self::default(),
+        };
+
+        if let Ok(tip_floor_url) = env::var("JITO_TIP_FLOOR_URL") {
+            config.tip_floor_url = tip_floor_url;
+        }
+        config.uuid = env::var("JITO_UUID").ok();
+
+        if let Ok(percentile) = env::var("JITO_TIP_PERCENTILE") {
+            config.tip_policy.percentile = percentile.parse()?;
+        }
+        if let Ok(min_lamports) = env::var("JITO_TIP_MIN_LAMPORTS") {
+            config.tip_policy.min_lamports = min_lamports.parse()?;
+        }
+        if let Ok(max_lamports) = env::var("JITO_TIP_MAX_LAMPORTS") {
+            config.tip_policy.max_lamports = max_lamports.parse()?;
+        }
+        if let Ok(escalation_factor) = env::var("JITO_TIP_ESCALATION_FACTOR") {
+            config.tip_policy.escalation_factor = escalation_factor.parse()?;
+        }
+        if let Ok(tip_placement) = env::var("JITO_TIP_PLACEMENT") {
+            config.tip_placement = tip_placement.parse()?;
+        }
+        if let Ok(simulate) = env::var("JITO_SIMULATE") {
+            config.simulate_before_submit = simulate.parse()?;
+            if config.simulate_before_submit && !cfg!(feature = "dry-run") {
+                return Err("JITO_SIMULATE=true needs the `dry-run` feature".into());
+            }
+        }
+
+        Ok(config)
+    }
+}
+
+// ---- Block engine JSON-RPC types ----
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct JsonRpcRequest<P> {
+    pub jsonrpc: String,
+    pub id: u64,
+    pub method: String,
+    pub params: P,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct JsonRpcResponse<R> {
+    pub jsonrpc: String,
+    pub id: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub result: Option<R>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<JsonRpcError>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct JsonRpcError {
+    pub code: i64,
+    pub message: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct RpcContext {
+    pub slot: u64,
+}
+
+/// A result wrapped with the slot it was observed at.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RpcResponseWithContext<T> {
+    pub context: RpcContext,
+    pub value: T,
+}
+
+/// Options accepted by `sendBundle`.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SendBundleOptions {
+    pub encoding: String,
+}
+
+/// Status reported by `getInflightBundleStatuses` (covers the last five minutes).
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum InflightBundleState {
+    /// Not found in the block engine.
+    Invalid,
+    /// Not yet failed nor landed.
+    Pending,
+    /// All regions marked the bundle as failed and it wasn't forwarded.
+    Failed,
+    Landed,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct InflightBundleStatus {
+    pub bundle_id: String,
+    pub status: InflightBundleState,
+    pub landed_slot: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ConfirmationStatus {
+    Processed,
+    Confirmed,
+    Finalized,
+}
+
+/// Status reported by `getBundleStatuses` for a landed bundle.
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct BundleStatus {
-    confirmation_status: Option<String>,
-    err: Option<serde_json::Value>,
-    transactions: Option<Vec<String>>,
+    pub bundle_id: String,
+    /// Signatures of the bundled transactions, in order.
+    pub transactions: Vec<String>,
+    pub slot: u64,
+    pub confirmation_status: Option<ConfirmationStatus>,
+    /// `{"Ok": null}` when every transaction succeeded.
+    pub err: Result<(), serde_json::Value>,
 }
 
-pub const MAX_RETRIES: u32 = 40;
-pub const RETRY_DELAY: Duration = Duration::from_secs(3);
-pub const JITO_ENGINE_URL: &str = "https://dallas.testnet.block-engine.jito.wtf/api/v1";
+/// Landed tip statistics (in SOL) reported by the tip floor API.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TipFloor {
+    pub time: String,
+    pub landed_tips_25th_percentile: f64,
+    pub landed_tips_50th_percentile: f64,
+    pub landed_tips_75th_percentile: f64,
+    pub landed_tips_95th_percentile: f64,
+    pub landed_tips_99th_percentile: f64,
+    pub ema_landed_tips_50th_percentile: f64,
+}
+
+impl TipFloor {
+    /// The landed tip for a percentile, in lamports.
+    pub fn lamports(&self, percentile: TipPercentile) -> u64 {
+        let sol = match percentile {
+            TipPercentile::P25 => self.landed_tips_25th_percentile,
+            TipPercentile::P50 => self.landed_tips_50th_percentile,
+            TipPercentile::P75 => self.landed_tips_75th_percentile,
+            TipPercentile::P95 => self.landed_tips_95th_percentile,
+            TipPercentile::P99 => self.landed_tips_99th_percentile,
+            TipPercentile::Ema50 => self.ema_landed_tips_50th_percentile,
+        };
+        // Convert SOL to Lamports
+        (sol * LAMPORTS_PER_SOL as f64) as u64
+    }
+}
+
+// ---- Block engine client ----
+
+/// Typed JSON-RPC client for the Jito block engine.
+pub struct JitoClient {
+    http: reqwest::Client,
+    engine_url: String,
+    tip_floor_url: String,
+    uuid: Option<String>,
+}
+
+impl JitoClient {
+    pub fn new(config: &JitoConfig) -> Self {
+        Self {
+            http: reqwest::Client::new(),
+            engine_url: config.engine_url.trim_end_matches('/').to_string(),
+            tip_floor_url: config.tip_floor_url.clone(),
+            uuid: config.uuid.clone(),
+        }
+    }
+
+    /// Submits base58-encoded transactions as a bundle, returning the bundle id.
+    pub async fn send_bundle(&self, encoded_transactions: &[String]) -> Result<String, Box<dyn Error>> {
+        self.call(
+            "sendBundle",
+            json!([encoded_transactions, SendBundleOptions { encoding: "base58".to_string() }]),
+        )
+        .await
+    }
 
-pub async fn create_jito_tip_instruction(sender_pubkey: Pubkey) -> Result<Instruction, Box<dyn std::error::Error>> {
-    let jito_sdk = JitoJsonRpcSDK::new(JITO_ENGINE_URL, None);
+    /// In-flight statuses, requested [`MAX_BUNDLE_STATUS_IDS`] bundles at a time.
+    pub async fn get_inflight_bundle_statuses(&self, bundle_ids: &[String]) -> Result<Vec<InflightBundleStatus>, Box<dyn Error>> {
+        let mut statuses = vec![];
+        for chunk in bundle_ids.chunks(MAX_BUNDLE_STATUS_IDS) {
+            let response: RpcResponseWithContext<Vec<InflightBundleStatus>> =
+                self.call("getInflightBundleStatuses", json!([chunk])).await?;
+            statuses.extend(response.value);
+        }
+        Ok(statuses)
+    }
 
-    let random_tip_account = jito_sdk.get_random_tip_account().await?;
-    let jito_tip_account = Pubkey::from_str(&random_tip_account)?;
-    let jito_tip_amount:u64 = get_max_tip_amount().await?;
+    /// Statuses of landed bundles, requested [`MAX_BUNDLE_STATUS_IDS`] at a time; bundles the
+    /// cluster doesn't know about are omitted.
+    pub async fn get_bundle_statuses(&self, bundle_ids: &[String]) -> Result<Vec<BundleStatus>, Box<dyn Error>> {
+        let mut statuses = vec![];
+        for chunk in bundle_ids.chunks(MAX_BUNDLE_STATUS_IDS) {
+            let response: RpcResponseWithContext<Vec<Option<BundleStatus>>> =
+                self.call("getBundleStatuses", json!([chunk])).await?;
+            statuses.extend(response.value.into_iter().flatten());
+        }
+        Ok(statuses)
+    }
+
+    pub async fn get_tip_accounts(&self) -> Result<Vec<Pubkey>, Box<dyn Error>> {
+        let tip_accounts: Vec<String> = self.call("getTipAccounts", json!([])).await?;
+        Ok(tip_accounts
+            .iter()
+            .map(|tip_account| Pubkey::from_str(tip_account))
+            .collect::<Result<_, _>>()?)
+    }
+
+    pub async fn get_random_tip_account(&self) -> Result<Pubkey, Box<dyn Error>> {
+        let tip_accounts = self.get_tip_accounts().await?;
+        if tip_accounts.is_empty() {
+            return Err("Block engine returned no tip accounts".into());
+        }
+        // Spread tips across accounts to reduce write-lock contention.
+        let index = rand::thread_rng().gen_range(0..tip_accounts.len());
+        Ok(tip_accounts[index])
+    }
+
+    pub async fn get_tip_floor(&self) -> Result<TipFloor, Box<dyn Error>> {
+        let tip_floors: Vec<TipFloor> = self.http.get(&self.tip_floor_url).send().await?.error_for_status()?.json().await?;
+        tip_floors.into_iter().next().ok_or_else(|| "Tip floor response is empty".into())
+    }
+
+    async fn call<R: DeserializeOwned>(&self, method: &str, params: serde_json::Value) -> Result<R, Box<dyn Error>> {
+        let request = JsonRpcRequest {
+            jsonrpc: "2.0".to_string(),
+            id: 1,
+            method: method.to_string(),
+            params,
+        };
+
+        let mut http_request = self.http.post(format!("{}/bundles", self.engine_url)).json(&request);
+        if let Some(uuid) = &self.uuid {
+            http_request = http_request.header("x-jito-auth", uuid);
+        }
+
+        let response: JsonRpcResponse<R> = http_request.send().await?.error_for_status()?.json().await?;
+        match (response.result, response.error) {
+            (Some(result), _) => Ok(result),
+            (None, Some(error)) => Err(format!("{} failed ({}): {}", method, error.code, error.message).into()),
+            (None, None) => Err(format!("{} returned neither a result nor an error", method).into()),
+        }
+    }
+}
+
+/// Tip instruction for the given (zero-based) submission attempt; later attempts tip more, per the tip policy.
+pub async fn create_jito_tip_instruction(config: &JitoConfig, sender_pubkey: Pubkey, attempt: u32) -> Result<Instruction, Box<dyn Error>> {
+    let jito_client = JitoClient::new(config);
+
+    let jito_tip_account = jito_client.get_random_tip_account().await?;
+    let jito_tip_amount: u64 = get_tip_amount(config, attempt).await?;
     println!("Jito tip lamports: {}", jito_tip_amount);
 
     Ok(system_instruction::transfer(
@@ -33,168 +470,427 @@ pub async fn create_jito_tip_instruction(sender_pubkey: Pubkey) -> Result<Instru
         jito_tip_amount,
     ))
 }
-pub async fn submit_and_confirm_bundle(bundle: serde_json::Value) -> Result<Vec<String>, Box<dyn std::error::Error>> {
-
-    let jito_sdk = JitoJsonRpcSDK::new(JITO_ENGINE_URL, None);
-
-    // UUID for the bundle
-    let uuid = None;
-
-    // Send bundle using Jito SDK
-    println!("Sending bundle with {} transactions...", bundle.as_array().unwrap().len());
-    let response = jito_sdk.send_bundle(Some(bundle), uuid).await?;
-
-    // Extract bundle UUID from response
-    let bundle_uuid = response["result"]
-        .as_str()
-        .ok_or("Failed to get bundle UUID from response")?;
-    println!("Bundle sent with UUID: {}", bundle_uuid);
-
-    confirm_bundle_status(&jito_sdk, &bundle_uuid).await
-    
-}
-pub async fn confirm_bundle_status(jito_sdk: &JitoJsonRpcSDK, bundle_uuid: &str) -> Result<Vec<String>, Box<dyn std::error::Error>> {
-
-    for attempt in 1..=MAX_RETRIES {
-        println!("Checking bundle status (attempt {}/{})", attempt, MAX_RETRIES);
-
-        let status_response = jito_sdk.get_in_flight_bundle_statuses(vec![bundle_uuid.to_string()]).await?;
-
-        if let Some(result) = status_response.get("result") {
-            if let Some(value) = result.get("value") {
-                if let Some(statuses) = value.as_array() {
-                    if let Some(bundle_status) = statuses.get(0) {
-                        if let Some(status) = bundle_status.get("status") {
-                            match status.as_str() {
-                                Some("Landed") => {
-                                    println!("Bundle landed on-chain. Checking final status...");
-                                    return Ok(check_final_bundle_status(&jito_sdk, bundle_uuid).await?);
-                                },
-                                Some("Pending") => {
-                                    println!("Bundle is pending. Waiting...");
-                                },
-                                Some(status) => {
-                                    if status == "Failed" {
-                                        return Err(format!("Bundle failed to land on-chain").into());
-                                    }
-                                    println!("Unexpected bundle status: {}. Waiting...", status);
-                                },
-                                None => {
-                                    println!("Unable to parse bundle status. Waiting...");
-                                }
-                            }
-                        } else {
-                            println!("Status field not found in bundle status. Waiting...");
-                        }
-                    } else {
-                        println!("Bundle status not found. Waiting...");
+
+/// Adds `tip_instruction` to a bundle as configured by `placement`, returning the index of the
+/// dedicated tip transaction if one was added.
+///
+/// `execute_index` is the bundle's main transaction, where `TipPlacement::Execute` tips. That
+/// transaction is recompiled from its instructions (so the message header accounts for the new
+/// accounts) and re-signed by its required `signers`, with `payer` paying for it.
+pub fn place_tip(
+    transactions: &mut Vec<Transaction>,
+    tip_instruction: Instruction,
+    placement: TipPlacement,
+    execute_index: usize,
+    payer: &Pubkey,
+    signers: &[&dyn Signer],
+    recent_blockhash: Hash,
+) -> Result<Option<usize>, Box<dyn Error>> {
+    let (index, transaction) = match placement {
+        TipPlacement::First | TipPlacement::Last => {
+            if transactions.len() >= MAX_BUNDLE_TRANSACTIONS {
+                return Err(format!(
+                    "Bundle already holds {} transactions; no room for a dedicated tip transaction (use TipPlacement::Execute)",
+                    transactions.len()
+                ).into());
+            }
+
+            let message = Message::new(&[tip_instruction], Some(payer));
+            let mut tip_transaction = Transaction::new_unsigned(message);
+            tip_transaction.try_sign(&required_signers(&tip_transaction.message, signers)[..], recent_blockhash)?;
+
+            let index = if placement == TipPlacement::First { 0 } else { transactions.len() };
+            transactions.insert(index, tip_transaction);
+            (Some(index), &transactions[index])
+        }
+        TipPlacement::Execute => {
+            let execute_transaction = transactions
+                .get_mut(execute_index)
+                .ok_or_else(|| format!("Bundle has no transaction at index {}", execute_index))?;
+
+            let mut instructions = decompile_instructions(&execute_transaction.message);
+            instructions.push(tip_instruction);
+
+            let message = Message::new(&instructions, Some(payer));
+            let mut tipped_transaction = Transaction::new_unsigned(message);
+            tipped_transaction.try_sign(&required_signers(&tipped_transaction.message, signers)[..], recent_blockhash)?;
+
+            *execute_transaction = tipped_transaction;
+            (None, &*execute_transaction)
+        }
+    };
+
+    let size = bincode::serialized_size(transaction)? as usize;
+    if size > PACKET_DATA_SIZE {
+        return Err(format!("Tipped transaction is {} bytes, over the {} byte limit", size, PACKET_DATA_SIZE).into());
+    }
+
+    Ok(index)
+}
+
+/// Recovers a compiled message's instructions, with signer and writable flags taken from its header.
+fn decompile_instructions(message: &Message) -> Vec<Instruction> {
+    message
+        .instructions
+        .iter()
+        .map(|instruction| Instruction {
+            program_id: message.account_keys[instruction.program_id_index as usize],
+            accounts: instruction
+                .accounts
+                .iter()
+                .map(|&index| {
+                    let index = index as usize;
+                    AccountMeta {
+                        pubkey: message.account_keys[index],
+                        is_signer: message.is_signer(index),
+                        is_writable: message.is_maybe_writable(index, None),
                     }
-                } else {
-                    println!("Unexpected value format. Waiting...");
-                }
-            } else {
-                println!("Value field not found in result. Waiting...");
+                })
+                .collect(),
+            data: instruction.data.clone(),
+        })
+        .collect()
+}
+
+/// Tip for the given (zero-based) attempt: the configured landed-tip percentile, escalated and clamped per the tip policy.
+pub async fn get_tip_amount(config: &JitoConfig, attempt: u32) -> Result<u64, Box<dyn Error>> {
+    let tip_floor = JitoClient::new(config).get_tip_floor().await?;
+    let landed_tip_lamports = tip_floor.lamports(config.tip_policy.percentile);
+
+    println!("Jito landed tip ({:?}): {} lamports", config.tip_policy.percentile, landed_tip_lamports);
 
+    Ok(config.tip_policy.tip_lamports(landed_tip_lamports, attempt))
+}
+
+/// Final state of a submitted bundle.
+#[derive(Clone, Debug, PartialEq)]
+pub enum BundleOutcome {
+    /// Confirmed on-chain; signatures of the bundled transactions, in order.
+    Landed { transactions: Vec<String> },
+    /// Rejected by the block engine, or landed with a transaction error.
+    Failed { reason: String },
+    /// Unknown to the block engine (expired or never forwarded to a leader).
+    Dropped,
+    /// Still unresolved when the deadline passed.
+    TimedOut,
+    /// Confirmation was cancelled before the bundle resolved.
+    Cancelled,
+}
+
+/// Executes a bundle's transactions in order in a local fork of the current on-chain state.
+///
+/// The fork can't know the cluster's blockhash, so each transaction is re-signed there by the
+/// `signers` it requires; every signer of the bundle must be provided. Execution stops at the first
+/// failing transaction, reported by `DryRunReport::first_failure`. `labels` name the transactions
+/// in the report.
+#[cfg(feature = "dry-run")]
+pub async fn simulate_bundle(
+    labels: &[&str],
+    transactions: &[Transaction],
+    signers: &[&dyn Signer],
+) -> Result<crate::dry_run::DryRunReport, Box<dyn Error>> {
+    let mut accounts = vec![];
+    let mut programs = vec![];
+    for transaction in transactions {
+        let message = &transaction.message;
+        for (index, key) in message.account_keys.iter().enumerate() {
+            let keys = if message.is_key_called_as_program(index) { &mut programs } else { &mut accounts };
+            if !keys.contains(key) {
+                keys.push(*key);
             }
-        } else if let Some(error) = status_response.get("error") {
-            println!("Error checking bundle status: {:?}", error);
-        } else {
-            println!("Unexpected response format. Waiting...");
         }
+    }
+
+    let mut dry_run = crate::dry_run::DryRun::fork(&accounts, &programs).await?;
+    for (index, transaction) in transactions.iter().enumerate() {
+        let transaction_signers = required_signers(&transaction.message, signers);
+        let label = labels.get(index).copied().unwrap_or("Bundled transaction");
+        if !dry_run.process(label, transaction.clone(), &transaction_signers[..]).await? {
+            break;
+        }
+    }
+
+    dry_run.finish().await
+}
+
+/// Executes the bundle in a local fork if `config.simulate_before_submit`, printing the logs of the
+/// first failing transaction. A bundle that fails against current state would fail on-chain too, so
+/// the failure is a `PermanentError`: it isn't retried or tipped for.
+#[cfg(feature = "dry-run")]
+pub async fn simulate_or_fail(
+    config: &JitoConfig,
+    labels: &[&str],
+    transactions: &[Transaction],
+    signers: &[&dyn Signer],
+) -> Result<(), Box<dyn Error>> {
+    if !config.simulate_before_submit {
+        return Ok(());
+    }
 
-        if attempt < MAX_RETRIES {
-            std::thread::sleep(RETRY_DELAY);
+    let report = simulate_bundle(labels, transactions, signers).await?;
+    if let Some((index, failed)) = report.first_failure() {
+        for log in &failed.logs {
+            println!("      {}", log);
         }
+        return Err(PermanentError(format!(
+            "Bundle simulation failed at transaction {} [{}]: {}",
+            index + 1,
+            failed.label,
+            failed.error.as_deref().unwrap_or_default(),
+        ).into()).into());
     }
 
-    Err(format!("Failed to confirm bundle status after {} attempts", MAX_RETRIES).into())
+    Ok(())
 }
 
-pub async fn get_max_tip_amount() -> Result<u64, Box<dyn std::error::Error>> {
-    // Query the API
-    let response = reqwest::get("https://bundles.jito.wtf/api/v1/bundles/tip_floor").await?;
-    let data: Value = response.json().await?;
+/// Without the `dry-run` feature bundles can't be simulated, so requesting it is a `PermanentError`.
+#[cfg(not(feature = "dry-run"))]
+pub async fn simulate_or_fail(
+    config: &JitoConfig,
+    _labels: &[&str],
+    _transactions: &[Transaction],
+    _signers: &[&dyn Signer],
+) -> Result<(), Box<dyn Error>> {
+    if config.simulate_before_submit {
+        return Err(PermanentError("Bundle simulation needs the `dry-run` feature".into()).into());
+    }
+    Ok(())
+}
+
+/// Submits the transactions as one bundle and waits for it to land, returning their signatures.
+pub async fn submit_and_confirm_bundle(config: &JitoConfig, transactions: &[Transaction]) -> Result<Vec<String>, Box<dyn Error>> {
+    match submit_and_watch_bundle(config, transactions, &CancellationToken::new()).await? {
+        BundleOutcome::Landed { transactions } => Ok(transactions),
+        outcome => Err(format!("Bundle did not land: {:?}", outcome).into()),
+    }
+}
+
+/// The first of `signatures` that landed successfully, searching the ledger history too.
+///
+/// A bundle reported as not landed (timed out, dropped) can still have landed, so retried bundles
+/// check the signatures of every earlier attempt before reporting a failure.
+pub fn landed_signature(client: &RpcClient, signatures: &[Signature]) -> Result<Option<Signature>, Box<dyn Error>> {
+    for signature in signatures {
+        if let Some(Ok(())) = client.get_signature_status_with_commitment_and_history(signature, client.commitment(), true)? {
+            return Ok(Some(*signature));
+        }
+    }
+    Ok(None)
+}
 
-    // Parse the JSON to get the 99th percentile tip
-    let landed_tips_99th_percentile = data[0]["landed_tips_99th_percentile"]
-        .as_f64()
-        .ok_or("Failed to parse landed_tips_99th_percentile")?;
+/// Submits a bundle and waits for its outcome, until `config.confirmation_timeout` passes or `cancel` fires.
+pub async fn submit_and_watch_bundle(
+    config: &JitoConfig,
+    transactions: &[Transaction],
+    cancel: &CancellationToken,
+) -> Result<BundleOutcome, Box<dyn Error>> {
+    let jito_client = JitoClient::new(config);
 
-    println!("Jito landed_tips_99th_percentile: {}", landed_tips_99th_percentile);
+    let encoded_transactions = transactions
+        .iter()
+        .map(|transaction| Ok(bs58::encode(bincode::serialize(transaction)?).into_string()))
+        .collect::<Result<Vec<_>, Box<dyn Error>>>()?;
 
-    // Convert SOL to Lamports
-    let jito_tip_amount = (landed_tips_99th_percentile * LAMPORTS_PER_SOL as f64) as u64;
+    println!("Sending bundle with {} transactions...", encoded_transactions.len());
+    let bundle_id = jito_client.send_bundle(&encoded_transactions).await?;
+    println!("Bundle sent with UUID: {}", bundle_id);
 
-    Ok(jito_tip_amount)
+    let mut outcomes = watch_bundles(config, &[bundle_id.clone()], cancel).await?;
+    Ok(outcomes.remove(&bundle_id).unwrap_or(BundleOutcome::TimedOut))
 }
 
-async fn check_final_bundle_status(jito_sdk: &JitoJsonRpcSDK, bundle_uuid: &str) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+/// Waits for several bundles at once, polling their statuses every `config.poll_interval`.
+///
+/// Returns an outcome for every bundle: bundles still unresolved at `config.confirmation_timeout`
+/// are `TimedOut`, and those unresolved when `cancel` fires are `Cancelled`.
+pub async fn watch_bundles(
+    config: &JitoConfig,
+    bundle_ids: &[String],
+    cancel: &CancellationToken,
+) -> Result<HashMap<String, BundleOutcome>, Box<dyn Error>> {
+    let jito_client = JitoClient::new(config);
+    let deadline = Instant::now() + config.confirmation_timeout;
+
+    let mut outcomes = HashMap::new();
+    let mut pending: Vec<String> = bundle_ids.to_vec();
+    let mut invalid_poll_counts: HashMap<String, u32> = HashMap::new();
+
+    // Every wait, including the status requests, gives way to `cancel`; whatever is still pending
+    // when the loop breaks early was cancelled.
+    while !pending.is_empty() {
+        let inflight_statuses: HashMap<String, InflightBundleStatus> = tokio::select! {
+            _ = cancel.cancelled() => break,
+            statuses = jito_client.get_inflight_bundle_statuses(&pending) => statuses?,
+        }
+        .into_iter()
+        .map(|status| (status.bundle_id.clone(), status))
+        .collect();
+
+        let mut landed = vec![];
+        for bundle_id in &pending {
+            let state = inflight_statuses.get(bundle_id).map(|status| status.status);
+            if state != Some(InflightBundleState::Invalid) {
+                invalid_poll_counts.remove(bundle_id);
+            }
+
+            match state {
+                Some(InflightBundleState::Landed) => landed.push(bundle_id.clone()),
+                Some(InflightBundleState::Failed) => {
+                    outcomes.insert(bundle_id.clone(), BundleOutcome::Failed { reason: "Bundle failed to land on-chain".to_string() });
+                }
+                Some(InflightBundleState::Invalid) => {
+                    // A freshly submitted bundle can briefly be unknown to the status endpoint.
+                    let invalid_polls = invalid_poll_counts.entry(bundle_id.clone()).or_insert(0);
+                    *invalid_polls += 1;
+                    if *invalid_polls >= DROPPED_AFTER_INVALID_POLLS {
+                        outcomes.insert(bundle_id.clone(), BundleOutcome::Dropped);
+                    }
+                }
+                Some(InflightBundleState::Pending) => println!("Bundle {} is pending. Waiting...", bundle_id),
+                None => println!("Status of bundle {} not found. Waiting...", bundle_id),
+            }
+        }
 
-    for attempt in 1..=MAX_RETRIES {
-        println!("Checking final bundle status (attempt {}/{})", attempt, MAX_RETRIES);
+        // Landed bundles are resolved once the cluster reports them confirmed.
+        if !landed.is_empty() {
+            let bundle_statuses = tokio::select! {
+                _ = cancel.cancelled() => break,
+                statuses = jito_client.get_bundle_statuses(&landed) => statuses?,
+            };
+            for bundle_status in bundle_statuses {
+                if let Some(outcome) = final_outcome(&bundle_status) {
+                    outcomes.insert(bundle_status.bundle_id.clone(), outcome);
+                }
+            }
+        }
 
-        let status_response = jito_sdk.get_bundle_statuses(vec![bundle_uuid.to_string()]).await?;
-        let bundle_status = get_bundle_status(&status_response)?;
+        pending.retain(|bundle_id| !outcomes.contains_key(bundle_id));
+        if pending.is_empty() {
+            break;
+        }
 
-        match bundle_status.confirmation_status.as_deref() {
-            Some("confirmed") => {
-                println!("Bundle confirmed on-chain. Waiting for finalization...");
-                check_transaction_error(&bundle_status)?;
-                return match bundle_status.transactions {
-                    Some(transactions) => Ok(transactions),
-                    None => Err("Error retrieving transactions from finalized bundle status".into()),
-                };
-            },
-            Some("finalized") => {
-                println!("Bundle finalized on-chain successfully!");
-                check_transaction_error(&bundle_status)?;
-                return match bundle_status.transactions {
-                    Some(transactions) => Ok(transactions),
-                    None => Err("Error retrieving transactions from finalized bundle status".into()),
-                };
-            },
-            Some(status) => {
-                println!("Unexpected final bundle status: {}. Continuing to poll...", status);
-            },
-            None => {
-                println!("Unable to parse final bundle status. Continuing to poll...");
+        if Instant::now() + config.poll_interval > deadline {
+            for bundle_id in pending.drain(..) {
+                outcomes.insert(bundle_id, BundleOutcome::TimedOut);
             }
+            break;
         }
 
-        if attempt < MAX_RETRIES {
-            std::thread::sleep(RETRY_DELAY);
+        tokio::select! {
+            _ = cancel.cancelled() => break,
+            _ = sleep(config.poll_interval) => {}
         }
     }
 
-    Err(format!("Failed to get finalized status after {} attempts", MAX_RETRIES).into())
+    for bundle_id in pending {
+        outcomes.insert(bundle_id, BundleOutcome::Cancelled);
+    }
+    Ok(outcomes)
 }
 
-fn get_bundle_status(status_response: &serde_json::Value) -> Result<BundleStatus, Box<dyn std::error::Error>> {
-    status_response
-        .get("result")
-        .and_then(|result| result.get("value"))
-        .and_then(|value| value.as_array())
-        .and_then(|statuses| statuses.get(0))
-        .ok_or_else(|| format!("Failed to parse bundle status").into())
-        .map(|bundle_status| BundleStatus {
-            confirmation_status: bundle_status.get("confirmation_status").and_then(|s| s.as_str()).map(String::from),
-            err: bundle_status.get("err").cloned(),
-            transactions: bundle_status.get("transactions").and_then(|t| t.as_array()).map(|arr| {
-                arr.iter().filter_map(|v| v.as_str().map(String::from)).collect()
-            }),
-        })
+/// Outcome of a landed bundle, or `None` while it is not yet confirmed.
+fn final_outcome(bundle_status: &BundleStatus) -> Option<BundleOutcome> {
+    match bundle_status.confirmation_status {
+        Some(ConfirmationStatus::Confirmed) | Some(ConfirmationStatus::Finalized) => match &bundle_status.err {
+            Ok(()) => {
+                println!("Bundle {} confirmed on-chain.", bundle_status.bundle_id);
+                Some(BundleOutcome::Landed { transactions: bundle_status.transactions.clone() })
+            }
+            Err(err) => {
+                println!("Transaction encountered an error: {:?}", err);
+                Some(BundleOutcome::Failed { reason: format!("Transaction encountered an error: {}", err) })
+            }
+        },
+        _ => {
+            println!("Bundle {} is not yet confirmed. Continuing to poll...", bundle_status.bundle_id);
+            None
+        }
+    }
 }
 
-fn check_transaction_error(bundle_status: &BundleStatus) -> Result<(), Box<dyn std::error::Error>> {
-    if let Some(err) = &bundle_status.err {
-        if err["Ok"].is_null() {
-            println!("Transaction executed without errors.");
-            Ok(())
-        } else {
-            println!("Transaction encountered an error: {:?}", err);
-            Err(format!("Transaction encountered an error").into())
-        }
-    } else {
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        solana_sdk::{signature::Keypair, system_program},
+    };
+
+    fn test_bundle(payer: &Keypair, len: usize) -> Vec<Transaction> {
+        (0..len)
+            .map(|_| {
+                Transaction::new_signed_with_payer(
+                    &[system_instruction::transfer(&payer.pubkey(), &Pubkey::new_unique(), 1)],
+                    Some(&payer.pubkey()),
+                    &[payer],
+                    Hash::default(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_place_tip_in_execute_transaction() -> Result<(), Box<dyn Error>> {
+        let payer = Keypair::new();
+        let tip_account = Pubkey::new_unique();
+        let mut transactions = test_bundle(&payer, 5);
+        let original = transactions[3].message.clone();
+
+        let tip_index = place_tip(
+            &mut transactions,
+            system_instruction::transfer(&payer.pubkey(), &tip_account, 1_000),
+            TipPlacement::Execute,
+            3,
+            &payer.pubkey(),
+            &[&payer],
+            Hash::default(),
+        )?;
+        assert_eq!(tip_index, None);
+        assert_eq!(transactions.len(), 5);
+
+        let message = &transactions[3].message;
+        assert_eq!(decompile_instructions(message)[..1], decompile_instructions(&original)[..]);
+        assert_eq!(message.instructions.len(), 2);
+        // The tip account is writable and the system program read-only, per the recompiled header.
+        let tip_account_index = message.account_keys.iter().position(|key| *key == tip_account).unwrap();
+        let system_program_index = message.account_keys.iter().position(|key| *key == system_program::id()).unwrap();
+        assert!(message.is_maybe_writable(tip_account_index, None));
+        assert!(!message.is_maybe_writable(system_program_index, None));
+        transactions[3].verify()?;
         Ok(())
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_place_tip_in_dedicated_transaction() -> Result<(), Box<dyn Error>> {
+        let payer = Keypair::new();
+        let tip_instruction = system_instruction::transfer(&payer.pubkey(), &Pubkey::new_unique(), 1_000);
+
+        let mut transactions = test_bundle(&payer, 3);
+        let tip_index = place_tip(&mut transactions, tip_instruction.clone(), TipPlacement::First, 1, &payer.pubkey(), &[&payer], Hash::default())?;
+        assert_eq!(tip_index, Some(0));
+        assert_eq!(decompile_instructions(&transactions[0].message), vec![tip_instruction.clone()]);
+
+        let tip_index = place_tip(&mut transactions, tip_instruction.clone(), TipPlacement::Last, 1, &payer.pubkey(), &[&payer], Hash::default())?;
+        assert_eq!(tip_index, Some(4));
+
+        // A full bundle has no room for another transaction.
+        assert!(place_tip(&mut transactions, tip_instruction, TipPlacement::Last, 1, &payer.pubkey(), &[&payer], Hash::default()).is_err());
+        assert_eq!(transactions.len(), MAX_BUNDLE_TRANSACTIONS);
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_tip_placement() {
+        let config = |tip_placement| JitoConfig { tip_placement, ..JitoConfig::default() };
+
+        assert!(config(TipPlacement::Execute).check_tip_placement(MAX_BUNDLE_TRANSACTIONS).is_ok());
+        assert!(config(TipPlacement::First).check_tip_placement(MAX_BUNDLE_TRANSACTIONS - 1).is_ok());
+        assert!(config(TipPlacement::First).check_tip_placement(MAX_BUNDLE_TRANSACTIONS).is_err());
+        assert!(config(TipPlacement::Last).check_tip_placement(MAX_BUNDLE_TRANSACTIONS).is_err());
+    }
+
+    #[test]
+    fn test_for_region_engine_urls() {
+        assert_eq!(JitoConfig::for_region("ny", true).engine_url, "https://ny.testnet.block-engine.jito.wtf/api/v1");
+        assert_eq!(JitoConfig::for_region("ny", false).engine_url, "https://ny.mainnet.block-engine.jito.wtf/api/v1");
+    }
+}
@@ -0,0 +1,453 @@
+//! A local, scriptable stand-in for the Jito block engine, for tests.
+//!
+//! Serves `sendBundle`, `getInflightBundleStatuses`, `getBundleStatuses`, `getTipAccounts` and the
+//! tip floor over plain HTTP on localhost. Each submitted bundle consumes the next scripted
+//! behavior (falling back to the default one) to decide whether it lands, fails or is dropped.
+
+use {
+    super::{
+        BundleStatus, ConfirmationStatus, InflightBundleState, InflightBundleStatus, JitoConfig, JsonRpcError,
+        JsonRpcRequest, JsonRpcResponse, RpcContext, RpcResponseWithContext, TipFloor, MAX_BUNDLE_STATUS_IDS,
+        MAX_BUNDLE_TRANSACTIONS,
+    },
+    serde::Serialize,
+    serde_json::{json, Value},
+    solana_sdk::{pubkey::Pubkey, transaction::Transaction},
+    std::{
+        collections::{HashMap, VecDeque},
+        io,
+        sync::{Arc, Mutex},
+        time::Duration,
+    },
+    tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::{TcpListener, TcpStream},
+        task::JoinHandle,
+    },
+};
+
+/// How the mock engine resolves a submitted bundle.
+#[derive(Clone, Debug, PartialEq)]
+pub enum MockBundleBehavior {
+    /// Lands and confirms without errors.
+    Land,
+    /// Lands, but the cluster reports a transaction error.
+    LandWithError,
+    /// Reported as failed by the block engine.
+    Fail,
+    /// Becomes unknown to the block engine.
+    Drop,
+    /// Stays pending forever.
+    Hang,
+    /// Alternates between unknown and pending while in flight, then lands.
+    Flicker,
+}
+
+struct MockBundle {
+    behavior: MockBundleBehavior,
+    signatures: Vec<String>,
+    polls: u32,
+}
+
+struct MockState {
+    script: VecDeque<MockBundleBehavior>,
+    default_behavior: MockBundleBehavior,
+    /// In-flight polls answered with "Pending" before a bundle resolves.
+    pending_polls: u32,
+    bundles: HashMap<String, MockBundle>,
+    submitted: Vec<Vec<String>>,
+    tip_accounts: Vec<Pubkey>,
+    tip_floor: TipFloor,
+}
+
+pub struct MockBlockEngine {
+    address: String,
+    state: Arc<Mutex<MockState>>,
+    server: JoinHandle<()>,
+}
+
+impl MockBlockEngine {
+    /// Starts the mock engine on an ephemeral localhost port. Bundles land by default.
+    pub async fn start() -> io::Result<Self> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let address = format!("http://{}", listener.local_addr()?);
+
+        let state = Arc::new(Mutex::new(MockState {
+            script: VecDeque::new(),
+            default_behavior: MockBundleBehavior::Land,
+            pending_polls: 1,
+            bundles: HashMap::new(),
+            submitted: vec![],
+            tip_accounts: (0..8).map(|_| Pubkey::new_unique()).collect(),
+            tip_floor: TipFloor {
+                time: "1970-01-01T00:00:00Z".to_string(),
+                landed_tips_25th_percentile: 0.000_001,
+                landed_tips_50th_percentile: 0.000_01,
+                landed_tips_75th_percentile: 0.000_05,
+                landed_tips_95th_percentile: 0.000_1,
+                landed_tips_99th_percentile: 0.001,
+                ema_landed_tips_50th_percentile: 0.000_01,
+            },
+        }));
+
+        let server_state = state.clone();
+        let server = tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let state = server_state.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = serve_connection(stream, state).await {
+                        println!("Mock block engine connection error: {}", e);
+                    }
+                });
+            }
+        });
+
+        Ok(Self { address, state, server })
+    }
+
+    /// A configuration pointing at this engine, with fast polling suited to tests.
+    pub fn config(&self) -> JitoConfig {
+        JitoConfig {
+            engine_url: format!("{}/api/v1", self.address),
+            tip_floor_url: format!("{}/api/v1/bundles/tip_floor", self.address),
+            poll_interval: Duration::from_millis(10),
+            confirmation_timeout: Duration::from_secs(2),
+            ..JitoConfig::default()
+        }
+    }
+
+    /// Behaviors for the next submitted bundles, in order.
+    pub fn script(&self, behaviors: impl IntoIterator<Item = MockBundleBehavior>) {
+        self.state.lock().unwrap().script.extend(behaviors);
+    }
+
+    /// Behavior for bundles submitted once the script is exhausted.
+    pub fn set_default_behavior(&self, behavior: MockBundleBehavior) {
+        self.state.lock().unwrap().default_behavior = behavior;
+    }
+
+    pub fn set_pending_polls(&self, pending_polls: u32) {
+        self.state.lock().unwrap().pending_polls = pending_polls;
+    }
+
+    pub fn set_tip_floor(&self, tip_floor: TipFloor) {
+        self.state.lock().unwrap().tip_floor = tip_floor;
+    }
+
+    pub fn tip_accounts(&self) -> Vec<Pubkey> {
+        self.state.lock().unwrap().tip_accounts.clone()
+    }
+
+    /// The encoded transactions of every bundle submitted so far.
+    pub fn submitted_bundles(&self) -> Vec<Vec<String>> {
+        self.state.lock().unwrap().submitted.clone()
+    }
+}
+
+impl Drop for MockBlockEngine {
+    fn drop(&mut self) {
+        self.server.abort();
+    }
+}
+
+/// Serves a single HTTP/1.1 request and closes the connection.
+async fn serve_connection(mut stream: TcpStream, state: Arc<Mutex<MockState>>) -> io::Result<()> {
+    let mut buffer = Vec::new();
+    let header_end = loop {
+        let mut chunk = [0u8; 4096];
+        let read = stream.read(&mut chunk).await?;
+        if read == 0 {
+            return Ok(());
+        }
+        buffer.extend_from_slice(&chunk[..read]);
+        if let Some(position) = buffer.windows(4).position(|window| window == b"\r\n\r\n") {
+            break position + 4;
+        }
+    };
+
+    let headers = String::from_utf8_lossy(&buffer[..header_end]).to_string();
+    let request_line = headers.lines().next().unwrap_or_default().to_string();
+    let content_length = headers
+        .lines()
+        .filter_map(|line| line.split_once(':'))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("content-length"))
+        .and_then(|(_, value)| value.trim().parse::<usize>().ok())
+        .unwrap_or(0);
+
+    while buffer.len() < header_end + content_length {
+        let mut chunk = [0u8; 4096];
+        let read = stream.read(&mut chunk).await?;
+        if read == 0 {
+            break;
+        }
+        buffer.extend_from_slice(&chunk[..read]);
+    }
+    let body = &buffer[header_end..];
+
+    let (status, response_body) = if request_line.starts_with("GET") && request_line.contains("/bundles/tip_floor") {
+        let tip_floor = state.lock().unwrap().tip_floor.clone();
+        ("200 OK", json!([tip_floor]).to_string())
+    } else if request_line.starts_with("POST") {
+        match serde_json::from_slice::<JsonRpcRequest<Value>>(body) {
+            Ok(request) => ("200 OK", handle_rpc(&state, request)),
+            Err(e) => ("400 Bad Request", json!({ "error": e.to_string() }).to_string()),
+        }
+    } else {
+        ("404 Not Found", String::new())
+    };
+
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        response_body.len(),
+        response_body
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+fn handle_rpc(state: &Mutex<MockState>, request: JsonRpcRequest<Value>) -> String {
+    let mut state = state.lock().unwrap();
+    let context = RpcContext { slot: 1 };
+
+    match request.method.as_str() {
+        "sendBundle" => {
+            let encoded_transactions: Vec<String> =
+                serde_json::from_value(request.params[0].clone()).unwrap_or_default();
+            if encoded_transactions.is_empty() || encoded_transactions.len() > MAX_BUNDLE_TRANSACTIONS {
+                return rpc_error(request.id, -32602, "bundles must contain between 1 and 5 transactions");
+            }
+
+            let behavior = state.script.pop_front().unwrap_or_else(|| state.default_behavior.clone());
+            let bundle_id = format!("mock-bundle-{}", state.submitted.len());
+            let signatures = encoded_transactions.iter().enumerate().map(|(index, encoded)| first_signature(encoded, index)).collect();
+
+            state.submitted.push(encoded_transactions);
+            state.bundles.insert(bundle_id.clone(), MockBundle { behavior, signatures, polls: 0 });
+            rpc_result(request.id, bundle_id)
+        }
+        "getInflightBundleStatuses" => {
+            let bundle_ids: Vec<String> = serde_json::from_value(request.params[0].clone()).unwrap_or_default();
+            if bundle_ids.len() > MAX_BUNDLE_STATUS_IDS {
+                return rpc_error(request.id, -32602, "at most 5 bundle ids per request");
+            }
+            let pending_polls = state.pending_polls;
+
+            let statuses: Vec<InflightBundleStatus> = bundle_ids
+                .into_iter()
+                .map(|bundle_id| {
+                    let status = match state.bundles.get_mut(&bundle_id) {
+                        None => InflightBundleState::Invalid,
+                        Some(bundle) => {
+                            bundle.polls += 1;
+                            if bundle.polls <= pending_polls {
+                                if bundle.behavior == MockBundleBehavior::Flicker && bundle.polls % 2 == 1 {
+                                    InflightBundleState::Invalid
+                                } else {
+                                    InflightBundleState::Pending
+                                }
+                            } else {
+                                match bundle.behavior {
+                                    MockBundleBehavior::Land | MockBundleBehavior::LandWithError | MockBundleBehavior::Flicker => {
+                                        InflightBundleState::Landed
+                                    }
+                                    MockBundleBehavior::Fail => InflightBundleState::Failed,
+                                    MockBundleBehavior::Drop => InflightBundleState::Invalid,
+                                    MockBundleBehavior::Hang => InflightBundleState::Pending,
+                                }
+                            }
+                        }
+                    };
+                    let landed_slot = (status == InflightBundleState::Landed).then_some(context.slot);
+                    InflightBundleStatus { bundle_id, status, landed_slot }
+                })
+                .collect();
+
+            rpc_result(request.id, RpcResponseWithContext { context, value: statuses })
+        }
+        "getBundleStatuses" => {
+            let bundle_ids: Vec<String> = serde_json::from_value(request.params[0].clone()).unwrap_or_default();
+            if bundle_ids.len() > MAX_BUNDLE_STATUS_IDS {
+                return rpc_error(request.id, -32602, "at most 5 bundle ids per request");
+            }
+
+            let statuses: Vec<Option<BundleStatus>> = bundle_ids
+                .into_iter()
+                .map(|bundle_id| {
+                    let bundle = state.bundles.get(&bundle_id)?;
+                    let err = match bundle.behavior {
+                        MockBundleBehavior::Land | MockBundleBehavior::Flicker => Ok(()),
+                        MockBundleBehavior::LandWithError => Err(json!({ "InstructionError": [0, "InvalidAccountData"] })),
+                        _ => return None,
+                    };
+                    Some(BundleStatus {
+                        bundle_id,
+                        transactions: bundle.signatures.clone(),
+                        slot: context.slot,
+                        confirmation_status: Some(ConfirmationStatus::Confirmed),
+                        err,
+                    })
+                })
+                .collect();
+
+            rpc_result(request.id, RpcResponseWithContext { context, value: statuses })
+        }
+        "getTipAccounts" => {
+            let tip_accounts: Vec<String> = state.tip_accounts.iter().map(Pubkey::to_string).collect();
+            rpc_result(request.id, tip_accounts)
+        }
+        method => rpc_error(request.id, -32601, &format!("Method not found: {}", method)),
+    }
+}
+
+/// The transaction's first signature, or a placeholder if it can't be decoded.
+fn first_signature(encoded_transaction: &str, index: usize) -> String {
+    bs58::decode(encoded_transaction)
+        .into_vec()
+        .ok()
+        .and_then(|bytes| bincode::deserialize::<Transaction>(&bytes).ok())
+        .and_then(|transaction| transaction.signatures.first().map(|signature| signature.to_string()))
+        .unwrap_or_else(|| format!("mock-signature-{}", index))
+}
+
+fn rpc_result<R: Serialize>(id: u64, result: R) -> String {
+    serde_json::to_string(&JsonRpcResponse {
+        jsonrpc: "2.0".to_string(),
+        id,
+        result: Some(result),
+        error: None,
+    })
+    .unwrap()
+}
+
+fn rpc_error(id: u64, code: i64, message: &str) -> String {
+    serde_json::to_string(&JsonRpcResponse::<()> {
+        jsonrpc: "2.0".to_string(),
+        id,
+        result: None,
+        error: Some(JsonRpcError {
+            code,
+            message: message.to_string(),
+        }),
+    })
+    .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::jito::{get_tip_amount, submit_and_watch_bundle, watch_bundles, BundleOutcome, JitoClient},
+        solana_sdk::{signature::Keypair, signer::Signer, system_instruction},
+        tokio_util::sync::CancellationToken,
+    };
+
+    fn test_bundle() -> Vec<Transaction> {
+        let payer = Keypair::new();
+        vec![Transaction::new_signed_with_payer(
+            &[system_instruction::transfer(&payer.pubkey(), &Pubkey::new_unique(), 1)],
+            Some(&payer.pubkey()),
+            &[&payer],
+            Default::default(),
+        )]
+    }
+
+    #[tokio::test]
+    async fn test_scripted_bundle_outcomes() -> Result<(), Box<dyn std::error::Error>> {
+        let engine = MockBlockEngine::start().await?;
+        engine.script([
+            MockBundleBehavior::Land,
+            MockBundleBehavior::LandWithError,
+            MockBundleBehavior::Fail,
+            MockBundleBehavior::Drop,
+            MockBundleBehavior::Hang,
+        ]);
+
+        let mut config = engine.config();
+        config.confirmation_timeout = Duration::from_millis(200);
+        let cancel = CancellationToken::new();
+        let bundle = test_bundle();
+
+        let landed = submit_and_watch_bundle(&config, &bundle, &cancel).await?;
+        assert_eq!(landed, BundleOutcome::Landed { transactions: vec![bundle[0].signatures[0].to_string()] });
+
+        assert!(matches!(submit_and_watch_bundle(&config, &bundle, &cancel).await?, BundleOutcome::Failed { .. }));
+        assert!(matches!(submit_and_watch_bundle(&config, &bundle, &cancel).await?, BundleOutcome::Failed { .. }));
+        assert_eq!(submit_and_watch_bundle(&config, &bundle, &cancel).await?, BundleOutcome::Dropped);
+        assert_eq!(submit_and_watch_bundle(&config, &bundle, &cancel).await?, BundleOutcome::TimedOut);
+
+        assert_eq!(engine.submitted_bundles().len(), 5);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_watch_several_bundles_and_cancel() -> Result<(), Box<dyn std::error::Error>> {
+        let engine = MockBlockEngine::start().await?;
+        engine.script([MockBundleBehavior::Land, MockBundleBehavior::Hang]);
+
+        let config = engine.config();
+        let client = JitoClient::new(&config);
+        let encoded = vec![bs58::encode(bincode::serialize(&test_bundle()[0])?).into_string()];
+        let landing_bundle = client.send_bundle(&encoded).await?;
+        let hanging_bundle = client.send_bundle(&encoded).await?;
+
+        let cancel = CancellationToken::new();
+        let canceller = cancel.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            canceller.cancel();
+        });
+
+        let outcomes = watch_bundles(&config, &[landing_bundle.clone(), hanging_bundle.clone()], &cancel).await?;
+        assert!(matches!(outcomes[&landing_bundle], BundleOutcome::Landed { .. }));
+        assert_eq!(outcomes[&hanging_bundle], BundleOutcome::Cancelled);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_watch_more_bundles_than_one_status_request() -> Result<(), Box<dyn std::error::Error>> {
+        let engine = MockBlockEngine::start().await?;
+        let config = engine.config();
+        let client = JitoClient::new(&config);
+        let encoded = vec![bs58::encode(bincode::serialize(&test_bundle()[0])?).into_string()];
+
+        let mut bundle_ids = vec![];
+        for _ in 0..7 {
+            bundle_ids.push(client.send_bundle(&encoded).await?);
+        }
+
+        let outcomes = watch_bundles(&config, &bundle_ids, &CancellationToken::new()).await?;
+        assert_eq!(outcomes.len(), bundle_ids.len());
+        assert!(outcomes.values().all(|outcome| matches!(outcome, BundleOutcome::Landed { .. })));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_invalid_polls_must_be_consecutive_to_drop() -> Result<(), Box<dyn std::error::Error>> {
+        let engine = MockBlockEngine::start().await?;
+        engine.script([MockBundleBehavior::Flicker]);
+        // Invalid, pending, invalid, pending: never two unknown polls in a row.
+        engine.set_pending_polls(4);
+
+        let outcome = submit_and_watch_bundle(&engine.config(), &test_bundle(), &CancellationToken::new()).await?;
+        assert!(matches!(outcome, BundleOutcome::Landed { .. }));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_tip_accounts_and_tip_policy() -> Result<(), Box<dyn std::error::Error>> {
+        let engine = MockBlockEngine::start().await?;
+        let mut config = engine.config();
+        config.tip_policy.max_lamports = 1_500_000;
+
+        let client = JitoClient::new(&config);
+        assert_eq!(client.get_tip_accounts().await?, engine.tip_accounts());
+        assert!(engine.tip_accounts().contains(&client.get_random_tip_account().await?));
+
+        // The mock's 99th percentile is 0.001 SOL; escalation is capped by the policy.
+        assert_eq!(get_tip_amount(&config, 0).await?, 1_000_000);
+        assert_eq!(get_tip_amount(&config, 1).await?, 1_500_000);
+        assert_eq!(get_tip_amount(&config, 5).await?, 1_500_000);
+        Ok(())
+    }
+}
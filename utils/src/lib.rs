@@ -2,18 +2,26 @@ use gcp::GcpSigner;
 use solana_client::nonblocking::rpc_client::RpcClient as NonBlockingRpcClient;
 use solana_client::rpc_client::RpcClient;
 use solana_sdk::commitment_config::CommitmentConfig;
+use solana_sdk::message::Message;
+use solana_sdk::pubkey::Pubkey;
 use solana_sdk::signer::keypair::Keypair;
 use solana_sdk::signer::Signer;
 use solana_zk_sdk::encryption::auth_encryption::AeKey;
 use solana_zk_sdk::encryption::elgamal::{ElGamalKeypair, ElGamalSecretKey};
 use std::env;
 use std::error::Error;
+use std::str::FromStr;
 use std::fs::OpenOptions;
 use std::io::Write;
 use dotenvy;
 
+#[cfg(feature = "dry-run")]
+pub mod dry_run;
 pub mod gcp;
 pub mod jito;
+pub mod nonce;
+pub mod proof_context;
+pub mod proof_generation;
 
 pub const ENV_FILE_PATH: &str = "../.env";
 pub const RUNTIME_ENV_FILE_PATH: &str = "../runtime_output.env";
@@ -142,6 +150,23 @@ pub fn record_value<'a, T: serde::Serialize>(variable_name: &str, value: T) -> R
     Ok(value)
 }
 
+/// Removes a value written by `record_value`, from runtime_output.env and the process environment.
+pub fn remove_value(variable_name: &str) -> Result<(), Box<dyn Error>> {
+    if std::path::Path::new(RUNTIME_ENV_FILE_PATH).exists() {
+        let content = std::fs::read_to_string(RUNTIME_ENV_FILE_PATH)?;
+        let content = content
+            .lines()
+            .filter(|line| !line.starts_with(&format!("{}=", variable_name)))
+            .collect::<Vec<&str>>()
+            .join("\n");
+        std::fs::write(RUNTIME_ENV_FILE_PATH, content)?;
+    }
+
+    // `load_value` reads through the process environment, which still holds the old value.
+    env::remove_var(variable_name);
+    Ok(())
+}
+
 pub fn load_value<T: serde::de::DeserializeOwned>(variable_name: &str) -> Result<T, Box<dyn Error>> {
     // First try to load from runtime_output.env
     if std::path::Path::new(RUNTIME_ENV_FILE_PATH).exists() {
@@ -183,6 +208,15 @@ pub fn load_value<T: serde::de::DeserializeOwned>(variable_name: &str) -> Result
     }
 }
 
+/// Address of the mint the ingredients work with: the last one created with `MintConfig::create`,
+/// falling back to the `mint` keypair's address.
+pub fn current_mint() -> Result<Pubkey, Box<dyn Error>> {
+    match load_value::<String>("mint_pubkey") {
+        Ok(mint_pubkey) => Ok(Pubkey::from_str(&mint_pubkey)?),
+        Err(_) => Ok(get_or_create_keypair("mint")?.pubkey()),
+    }
+}
+
 pub fn get_rpc_client() -> Result<RpcClient, Box<dyn Error>> {
     dotenvy::from_filename_override(ENV_FILE_PATH).ok();
 
@@ -250,6 +284,32 @@ pub async fn get_gcp_signer_from_env(
     Ok(signer)
 }
 
+/// The subset of `signers` required by a message, e.g. to sign one transaction of a multi-signer flow.
+pub fn required_signers<'a>(message: &Message, signers: &[&'a dyn Signer]) -> Vec<&'a dyn Signer> {
+    let signer_keys = message.signer_keys();
+    signers
+        .iter()
+        .copied()
+        .filter(|signer| signer_keys.contains(&&signer.pubkey()))
+        .collect()
+}
+
+/// An error that retrying can't fix; `run_with_retry` returns it without further attempts.
+#[derive(Debug)]
+pub struct PermanentError(pub Box<dyn Error>);
+
+impl std::fmt::Display for PermanentError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl Error for PermanentError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(self.0.as_ref())
+    }
+}
+
 pub async fn run_with_retry<F, Fut>(
     max_retries: usize,
     operation: F,
@@ -263,6 +323,10 @@ where
         match operation().await {
             Ok(_) => return Ok(()),
             Err(e) => {
+                if e.is::<PermanentError>() {
+                    println!("Error: {}. Not retrying.", e);
+                    return Err(e);
+                }
                 println!("Error: {}. Retrying...", e);
                 if attempt == max_retries {
                     return Err(e);
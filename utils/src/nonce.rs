@@ -0,0 +1,301 @@
+use {
+    crate::{get_rpc_client, load_value, print_transaction_url, record_value},
+    solana_client::rpc_client::RpcClient,
+    solana_sdk::{
+        hash::Hash,
+        instruction::Instruction,
+        nonce::state::{Data, State, Versions},
+        packet::PACKET_DATA_SIZE,
+        pubkey::Pubkey,
+        signature::{Keypair, Signer},
+        signers::Signers,
+        system_instruction,
+        transaction::Transaction,
+    },
+    std::{error::Error, str::FromStr},
+};
+
+/// Where a transaction's blockhash comes from.
+///
+/// A recent blockhash expires after ~150 slots (about a minute). A durable nonce stays valid until
+/// it is advanced, which suits slow signers (Turnkey, GCP KMS) and offline approval. Each nonce can
+/// only back one transaction at a time: the transaction itself advances it.
+#[derive(Clone, Debug, PartialEq)]
+pub enum BlockhashSource {
+    Recent,
+    DurableNonce {
+        nonce_account: Pubkey,
+        nonce_authority: Pubkey,
+    },
+}
+
+impl BlockhashSource {
+    /// The blockhash to sign against: the latest one, or the value stored in the nonce account.
+    pub fn blockhash(&self, client: &RpcClient) -> Result<Hash, Box<dyn Error>> {
+        match self {
+            BlockhashSource::Recent => Ok(client.get_latest_blockhash()?),
+            BlockhashSource::DurableNonce { nonce_account, .. } => Ok(get_nonce_data(client, nonce_account)?.blockhash()),
+        }
+    }
+
+    /// `instructions`, preceded by the nonce advance a durable-nonce transaction must start with.
+    pub fn instructions(&self, instructions: &[Instruction]) -> Vec<Instruction> {
+        match self {
+            BlockhashSource::Recent => instructions.to_vec(),
+            BlockhashSource::DurableNonce { nonce_account, nonce_authority } => {
+                let mut nonce_instructions = vec![system_instruction::advance_nonce_account(nonce_account, nonce_authority)];
+                nonce_instructions.extend_from_slice(instructions);
+                nonce_instructions
+            }
+        }
+    }
+}
+
+/// One blockhash source per transaction of a flow paid by `authority`.
+///
+/// Durable nonces are only used if `use_durable_nonces` is set, and never for the stages listed in
+/// `recent_only` (transactions too close to the packet limit to take the nonce advance). Every other
+/// stage needs its own nonce, since transactions signed up front can't share one; if the authority
+/// has fewer, all stages use recent blockhashes.
+pub fn blockhash_sources(
+    authority: &Pubkey,
+    count: usize,
+    use_durable_nonces: bool,
+    recent_only: &[usize],
+) -> Vec<BlockhashSource> {
+    if !use_durable_nonces {
+        return vec![BlockhashSource::Recent; count];
+    }
+
+    let needed = (0..count).filter(|stage| !recent_only.contains(stage)).count();
+    let nonce_accounts = nonce_accounts(authority);
+    if nonce_accounts.len() < needed {
+        println!(
+            "{} has {} durable nonce account(s) but {} are needed; using recent blockhashes",
+            authority,
+            nonce_accounts.len(),
+            needed
+        );
+        return vec![BlockhashSource::Recent; count];
+    }
+
+    let mut nonce_accounts = nonce_accounts.into_iter();
+    (0..count)
+        .map(|stage| {
+            if recent_only.contains(&stage) {
+                return BlockhashSource::Recent;
+            }
+            BlockhashSource::DurableNonce {
+                nonce_account: nonce_accounts.next().expect("enough nonce accounts were checked above"),
+                nonce_authority: *authority,
+            }
+        })
+        .collect()
+}
+
+/// The blockhash source of transactions paid by `authority` that are each signed only once the
+/// previous one has landed, e.g. a single transaction or sequential batches. They can all share one
+/// durable nonce: each transaction advances it before the next is signed against the new value.
+pub fn sequential_blockhash_source(authority: &Pubkey, use_durable_nonces: bool) -> BlockhashSource {
+    blockhash_sources(authority, 1, use_durable_nonces, &[]).remove(0)
+}
+
+/// Signs `instructions` against `source`. For a durable nonce, the nonce authority must be among `signers`.
+pub fn signed_transaction<T: Signers + ?Sized>(
+    client: &RpcClient,
+    instructions: &[Instruction],
+    payer: &Pubkey,
+    signers: &T,
+    source: &BlockhashSource,
+) -> Result<Transaction, Box<dyn Error>> {
+    let mut transaction = Transaction::new_with_payer(&source.instructions(instructions), Some(payer));
+    transaction.try_sign(signers, source.blockhash(client)?)?;
+
+    // The nonce advance adds an instruction and accounts, which may not fit in saturated transactions.
+    if let BlockhashSource::DurableNonce { nonce_account, .. } = source {
+        let size = bincode::serialized_size(&transaction)? as usize;
+        if size > PACKET_DATA_SIZE {
+            return Err(format!(
+                "Transaction is {} bytes with durable nonce {}, over the {} byte limit",
+                size, nonce_account, PACKET_DATA_SIZE
+            ).into());
+        }
+    }
+
+    Ok(transaction)
+}
+
+/// Whether a signed `transaction` that hasn't landed yet still can: its recent blockhash hasn't
+/// expired, or the durable nonce it was signed against hasn't been advanced.
+pub fn can_still_land(client: &RpcClient, transaction: &Transaction) -> Result<bool, Box<dyn Error>> {
+    let blockhash = transaction.message.recent_blockhash;
+    match solana_sdk::transaction::uses_durable_nonce(transaction) {
+        Some(advance_nonce_instruction) => {
+            let nonce_account = advance_nonce_instruction
+                .accounts
+                .first()
+                .and_then(|index| transaction.message.account_keys.get(*index as usize))
+                .ok_or("Malformed nonce advance instruction")?;
+            Ok(get_nonce_data(client, nonce_account)?.blockhash() == blockhash)
+        }
+        None => Ok(client.is_blockhash_valid(&blockhash, client.commitment())?),
+    }
+}
+
+/// Reads the state of an initialized nonce account.
+pub fn get_nonce_data(client: &RpcClient, nonce_account: &Pubkey) -> Result<Data, Box<dyn Error>> {
+    let account = client.get_account(nonce_account)?;
+    if account.owner != solana_sdk::system_program::id() {
+        return Err(format!("{} is not a nonce account", nonce_account).into());
+    }
+
+    let versions: Versions = bincode::deserialize(&account.data)?;
+    match versions.state() {
+        State::Initialized(data) => Ok(data.clone()),
+        State::Uninitialized => Err(format!("Nonce account {} is not initialized", nonce_account).into()),
+    }
+}
+
+/// Durable nonce accounts registered for `authority`, in creation order.
+pub fn nonce_accounts(authority: &Pubkey) -> Vec<Pubkey> {
+    load_value::<Vec<String>>(&nonce_accounts_variable(authority))
+        .unwrap_or_default()
+        .iter()
+        .filter_map(|nonce_account| Pubkey::from_str(nonce_account).ok())
+        .collect()
+}
+
+/// Creates a durable nonce account controlled by `authority`, funded by `payer`, and registers it
+/// as one of the authority's nonces.
+pub fn create_nonce_account(payer: &dyn Signer, authority: &Pubkey) -> Result<Pubkey, Box<dyn Error>> {
+    let client = get_rpc_client()?;
+
+    // The nonce account's own key is only needed to create it.
+    let nonce_keypair = Keypair::new();
+    let rent = client.get_minimum_balance_for_rent_exemption(State::size())?;
+
+    let transaction = Transaction::new_signed_with_payer(
+        &system_instruction::create_nonce_account(&payer.pubkey(), &nonce_keypair.pubkey(), authority, rent),
+        Some(&payer.pubkey()),
+        &[payer, &nonce_keypair as &dyn Signer],
+        client.get_latest_blockhash()?,
+    );
+    let transaction_signature = client.send_and_confirm_transaction(&transaction)?;
+    print_transaction_url("Create Nonce Account", &transaction_signature.to_string());
+
+    register_nonce_account(authority, &nonce_keypair.pubkey())?;
+    Ok(nonce_keypair.pubkey())
+}
+
+/// Advances a nonce, invalidating any transaction signed against its current value. Returns the new value.
+pub fn advance_nonce_account(authority: &dyn Signer, nonce_account: &Pubkey) -> Result<Hash, Box<dyn Error>> {
+    let client = get_rpc_client()?;
+
+    let transaction = Transaction::new_signed_with_payer(
+        &[system_instruction::advance_nonce_account(nonce_account, &authority.pubkey())],
+        Some(&authority.pubkey()),
+        &[authority],
+        client.get_latest_blockhash()?,
+    );
+    let transaction_signature = client.send_and_confirm_transaction(&transaction)?;
+    print_transaction_url("Advance Nonce Account", &transaction_signature.to_string());
+
+    Ok(get_nonce_data(&client, nonce_account)?.blockhash())
+}
+
+/// Hands a nonce account over to `new_authority`, moving its registration along with it.
+pub fn authorize_nonce_account(
+    authority: &dyn Signer,
+    nonce_account: &Pubkey,
+    new_authority: &Pubkey,
+) -> Result<(), Box<dyn Error>> {
+    let client = get_rpc_client()?;
+
+    let transaction = Transaction::new_signed_with_payer(
+        &[system_instruction::authorize_nonce_account(nonce_account, &authority.pubkey(), new_authority)],
+        Some(&authority.pubkey()),
+        &[authority],
+        client.get_latest_blockhash()?,
+    );
+    let transaction_signature = client.send_and_confirm_transaction(&transaction)?;
+    print_transaction_url("Authorize Nonce Account", &transaction_signature.to_string());
+
+    unregister_nonce_account(&authority.pubkey(), nonce_account)?;
+    register_nonce_account(new_authority, nonce_account)
+}
+
+/// Withdraws a nonce account's lamports to `destination`, closing it, and unregisters it.
+pub fn close_nonce_account(
+    authority: &dyn Signer,
+    nonce_account: &Pubkey,
+    destination: &Pubkey,
+) -> Result<(), Box<dyn Error>> {
+    let client = get_rpc_client()?;
+    let lamports = client.get_balance(nonce_account)?;
+
+    let transaction = Transaction::new_signed_with_payer(
+        &[system_instruction::withdraw_nonce_account(nonce_account, &authority.pubkey(), destination, lamports)],
+        Some(&authority.pubkey()),
+        &[authority],
+        client.get_latest_blockhash()?,
+    );
+    let transaction_signature = client.send_and_confirm_transaction(&transaction)?;
+    print_transaction_url("Close Nonce Account", &transaction_signature.to_string());
+
+    unregister_nonce_account(&authority.pubkey(), nonce_account)
+}
+
+fn nonce_accounts_variable(authority: &Pubkey) -> String {
+    format!("durable_nonce_accounts_{}", authority)
+}
+
+fn register_nonce_account(authority: &Pubkey, nonce_account: &Pubkey) -> Result<(), Box<dyn Error>> {
+    let mut nonce_accounts = nonce_accounts(authority);
+    if !nonce_accounts.contains(nonce_account) {
+        nonce_accounts.push(*nonce_account);
+    }
+    save_nonce_accounts(authority, &nonce_accounts)
+}
+
+fn unregister_nonce_account(authority: &Pubkey, nonce_account: &Pubkey) -> Result<(), Box<dyn Error>> {
+    let mut nonce_accounts = nonce_accounts(authority);
+    nonce_accounts.retain(|registered| registered != nonce_account);
+    save_nonce_accounts(authority, &nonce_accounts)
+}
+
+fn save_nonce_accounts(authority: &Pubkey, nonce_accounts: &[Pubkey]) -> Result<(), Box<dyn Error>> {
+    let nonce_accounts: Vec<String> = nonce_accounts.iter().map(Pubkey::to_string).collect();
+    // Recorded as a JSON *string* so the .env parser keeps the inner quotes intact.
+    record_value(&nonce_accounts_variable(authority), serde_json::to_string(&nonce_accounts)?)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, solana_sdk::system_program};
+
+    #[test]
+    fn test_durable_nonce_instructions_start_with_advance() {
+        let payer = Pubkey::new_unique();
+        let transfer = system_instruction::transfer(&payer, &Pubkey::new_unique(), 1);
+
+        assert_eq!(BlockhashSource::Recent.instructions(&[transfer.clone()]), vec![transfer.clone()]);
+
+        let nonce_account = Pubkey::new_unique();
+        let source = BlockhashSource::DurableNonce {
+            nonce_account,
+            nonce_authority: payer,
+        };
+        let instructions = source.instructions(&[transfer.clone()]);
+        assert_eq!(instructions.len(), 2);
+        assert_eq!(instructions[0], system_instruction::advance_nonce_account(&nonce_account, &payer));
+        assert_eq!(instructions[0].program_id, system_program::id());
+        assert_eq!(instructions[1], transfer);
+    }
+
+    #[test]
+    fn test_blockhash_sources_without_durable_nonces_are_recent() {
+        let sources = blockhash_sources(&Pubkey::new_unique(), 5, false, &[1]);
+        assert_eq!(sources, vec![BlockhashSource::Recent; 5]);
+    }
+}
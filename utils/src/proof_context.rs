@@ -0,0 +1,45 @@
+use {
+    solana_client::rpc_client::RpcClient,
+    solana_sdk::{instruction::Instruction, pubkey::Pubkey, system_instruction},
+    solana_zk_sdk::zk_elgamal_proof_program::{
+        self,
+        instruction::ContextStateInfo,
+        proof_data::ZkProofData,
+        state::ProofContextState,
+    },
+    spl_token_confidential_transfer_proof_extraction::instruction::zk_proof_type_to_instruction,
+    std::{error::Error, mem::size_of},
+};
+
+/// Instructions to allocate a ZK proof context state account and verify `proof_data` into it, and
+/// the rent the account holds until it is closed.
+///
+/// Like `spl_token_client::token::Token::confidential_transfer_create_context_state_account`, but
+/// returns the instructions instead of sending them, so callers can place them in their own transactions.
+pub fn context_state_instructions<ZK: bytemuck::Pod + ZkProofData<U>, U: bytemuck::Pod>(
+    client: &RpcClient,
+    payer_pubkey: &Pubkey,
+    context_state_account: &Pubkey,
+    context_state_authority: &Pubkey,
+    proof_data: &ZK,
+) -> Result<(Instruction, Instruction, u64), Box<dyn Error>> {
+    let space = size_of::<ProofContextState<U>>();
+    let rent = client.get_minimum_balance_for_rent_exemption(space)?;
+
+    let create_account_ix = system_instruction::create_account(
+        payer_pubkey,
+        context_state_account,
+        rent,
+        space as u64,
+        &zk_elgamal_proof_program::id(),
+    );
+    let verify_proof_ix = zk_proof_type_to_instruction(ZK::PROOF_TYPE)?.encode_verify_proof(
+        Some(ContextStateInfo {
+            context_state_account,
+            context_state_authority,
+        }),
+        proof_data,
+    );
+
+    Ok((create_account_ix, verify_proof_ix, rent))
+}
@@ -0,0 +1,439 @@
+use {
+    solana_zk_sdk::{
+        encryption::{
+            auth_encryption::{AeCiphertext, AeKey},
+            elgamal::{ElGamal, ElGamalCiphertext, ElGamalKeypair, ElGamalPubkey},
+            grouped_elgamal::GroupedElGamal,
+            pedersen::{Pedersen, PedersenOpening},
+        },
+        zk_elgamal_proof_program::proof_data::{
+            BatchedGroupedCiphertext3HandlesValidityProofData, BatchedRangeProofU128Data,
+            BatchedRangeProofU64Data, CiphertextCommitmentEqualityProofData, ZkProofData,
+        },
+    },
+    spl_token_confidential_transfer_proof_generation::{
+        errors::TokenProofGenerationError, transfer::TransferProofData, try_combine_lo_hi_ciphertexts,
+        try_split_u64, withdraw::WithdrawProofData, CiphertextValidityProofWithAuditorCiphertext,
+        REMAINING_BALANCE_BIT_LENGTH, TRANSFER_AMOUNT_HI_BITS, TRANSFER_AMOUNT_LO_BITS,
+    },
+    std::{
+        collections::BTreeMap,
+        error::Error,
+        panic::{catch_unwind, AssertUnwindSafe},
+        sync::{Mutex, OnceLock},
+        time::{Duration, Instant},
+    },
+    tokio::sync::{oneshot, Semaphore},
+};
+
+/// Padding bit length used by the transfer range proof (mirrors the proof generation crate).
+const RANGE_PROOF_PADDING_BIT_LENGTH: usize = 16;
+
+/// Sizing of the dedicated proof generation pool.
+#[derive(Clone, Copy, Debug)]
+pub struct ProofPoolConfig {
+    /// Threads in the dedicated rayon pool.
+    pub threads: usize,
+    /// Proof jobs allowed to run at once; further jobs wait (asynchronously) for a slot.
+    pub max_concurrent_jobs: usize,
+}
+
+impl Default for ProofPoolConfig {
+    fn default() -> Self {
+        let threads = std::thread::available_parallelism().map(|n| n.get()).unwrap_or(4);
+        Self {
+            threads,
+            max_concurrent_jobs: threads,
+        }
+    }
+}
+
+/// Latency recorded for one kind of proof job.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ProofLatency {
+    pub jobs: u64,
+    /// Time spent waiting for a free slot in the pool.
+    pub total_queued: Duration,
+    /// Time spent generating proofs.
+    pub total_generation: Duration,
+    pub max_generation: Duration,
+}
+
+struct ProofPool {
+    threads: rayon::ThreadPool,
+    permits: Semaphore,
+}
+
+static PROOF_POOL: OnceLock<ProofPool> = OnceLock::new();
+static PROOF_LATENCY: Mutex<BTreeMap<&'static str, ProofLatency>> = Mutex::new(BTreeMap::new());
+
+/// Sizes the proof generation pool. Must be called before the first proof job; otherwise the
+/// pool is created with `ProofPoolConfig::default()`.
+pub fn configure_proof_pool(config: ProofPoolConfig) -> Result<(), Box<dyn Error>> {
+    PROOF_POOL
+        .set(build_proof_pool(config)?)
+        .map_err(|_| "Proof generation pool is already initialized".into())
+}
+
+fn build_proof_pool(config: ProofPoolConfig) -> Result<ProofPool, Box<dyn Error>> {
+    let threads = rayon::ThreadPoolBuilder::new()
+        .num_threads(config.threads)
+        .thread_name(|index| format!("proof-generation-{}", index))
+        .build()?;
+
+    Ok(ProofPool {
+        threads,
+        permits: Semaphore::new(config.max_concurrent_jobs.max(1)),
+    })
+}
+
+fn proof_pool() -> &'static ProofPool {
+    PROOF_POOL.get_or_init(|| {
+        build_proof_pool(ProofPoolConfig::default()).expect("Failed to build proof generation pool")
+    })
+}
+
+/// Runs a CPU-heavy proof generation job on the dedicated pool, keeping the async runtime free.
+///
+/// A panicking job is reported as an error rather than taking down the pool.
+pub async fn run_proof_job<T, F>(label: &'static str, job: F) -> Result<T, Box<dyn Error>>
+where
+    T: Send + 'static,
+    F: FnOnce() -> T + Send + 'static,
+{
+    let pool = proof_pool();
+
+    let queued_at = Instant::now();
+    let _permit = pool.permits.acquire().await?;
+    let queued = queued_at.elapsed();
+
+    let (sender, receiver) = oneshot::channel();
+    pool.threads.spawn(move || {
+        let started_at = Instant::now();
+        // Rayon aborts the process when a spawned closure panics, so the panic is caught here.
+        let output = catch_unwind(AssertUnwindSafe(job));
+        let _ = sender.send((output, started_at.elapsed()));
+    });
+
+    let (output, generation) = receiver.await?;
+    let output = output.map_err(|panic| {
+        let message = panic
+            .downcast_ref::<&str>()
+            .map(|message| message.to_string())
+            .or_else(|| panic.downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| "unknown panic".to_string());
+        format!("Proof generation job [{}] panicked: {}", label, message)
+    })?;
+    record_latency(label, queued, generation);
+    println!("Proof generation [{}]: queued {:?}, generated in {:?}", label, queued, generation);
+
+    Ok(output)
+}
+
+/// Snapshot of the proof generation latency recorded so far, keyed by job label.
+pub fn proof_latency_metrics() -> BTreeMap<&'static str, ProofLatency> {
+    PROOF_LATENCY.lock().unwrap().clone()
+}
+
+fn record_latency(label: &'static str, queued: Duration, generation: Duration) {
+    let mut metrics = PROOF_LATENCY.lock().unwrap();
+    let latency = metrics.entry(label).or_default();
+    latency.jobs += 1;
+    latency.total_queued += queued;
+    latency.total_generation += generation;
+    latency.max_generation = latency.max_generation.max(generation);
+}
+
+/// Same as `spl_token_confidential_transfer_proof_generation::transfer::transfer_split_proof_data`,
+/// but generates the equality, ciphertext validity and range proofs as three parallel jobs on the
+/// proof pool.
+#[allow(clippy::too_many_arguments)]
+pub async fn transfer_split_proof_data_parallel(
+    current_available_balance: &ElGamalCiphertext,
+    current_decryptable_available_balance: &AeCiphertext,
+    transfer_amount: u64,
+    source_elgamal_keypair: &ElGamalKeypair,
+    aes_key: &AeKey,
+    destination_elgamal_pubkey: &ElGamalPubkey,
+    auditor_elgamal_pubkey: Option<&ElGamalPubkey>,
+) -> Result<TransferProofData, Box<dyn Error>> {
+    let source_elgamal_pubkey = *source_elgamal_keypair.pubkey();
+    let destination_elgamal_pubkey = *destination_elgamal_pubkey;
+    let auditor_elgamal_pubkey = auditor_elgamal_pubkey.copied().unwrap_or_default();
+    let grouped_pubkeys = [&source_elgamal_pubkey, &destination_elgamal_pubkey, &auditor_elgamal_pubkey];
+
+    // Everything the three proofs share is computed up front; the proofs themselves are independent.
+    let (transfer_amount_lo, transfer_amount_hi) =
+        try_split_u64(transfer_amount, TRANSFER_AMOUNT_LO_BITS)
+            .ok_or(TokenProofGenerationError::IllegalAmountBitLength)?;
+
+    let transfer_amount_opening_lo = PedersenOpening::new_rand();
+    let transfer_amount_opening_hi = PedersenOpening::new_rand();
+    let transfer_amount_grouped_ciphertext_lo =
+        GroupedElGamal::encrypt_with(grouped_pubkeys, transfer_amount_lo, &transfer_amount_opening_lo);
+    let transfer_amount_grouped_ciphertext_hi =
+        GroupedElGamal::encrypt_with(grouped_pubkeys, transfer_amount_hi, &transfer_amount_opening_hi);
+
+    let current_decrypted_available_balance = current_decryptable_available_balance
+        .decrypt(aes_key)
+        .ok_or(TokenProofGenerationError::IllegalAmountBitLength)?;
+    let new_decrypted_available_balance = current_decrypted_available_balance
+        .checked_sub(transfer_amount)
+        .ok_or(TokenProofGenerationError::NotEnoughFunds)?;
+    let (new_available_balance_commitment, new_source_opening) =
+        Pedersen::new(new_decrypted_available_balance);
+
+    let transfer_amount_source_ciphertext_lo = transfer_amount_grouped_ciphertext_lo
+        .to_elgamal_ciphertext(0)
+        .map_err(|_| TokenProofGenerationError::CiphertextExtraction)?;
+    let transfer_amount_source_ciphertext_hi = transfer_amount_grouped_ciphertext_hi
+        .to_elgamal_ciphertext(0)
+        .map_err(|_| TokenProofGenerationError::CiphertextExtraction)?;
+    let new_available_balance_ciphertext = current_available_balance
+        - try_combine_lo_hi_ciphertexts(
+            &transfer_amount_source_ciphertext_lo,
+            &transfer_amount_source_ciphertext_hi,
+            TRANSFER_AMOUNT_LO_BITS,
+        )
+        .ok_or(TokenProofGenerationError::IllegalAmountBitLength)?;
+
+    let equality_job = {
+        let source_elgamal_keypair = source_elgamal_keypair.clone();
+        let new_source_opening = new_source_opening.clone();
+        run_proof_job("transfer_equality", move || {
+            CiphertextCommitmentEqualityProofData::new(
+                &source_elgamal_keypair,
+                &new_available_balance_ciphertext,
+                &new_available_balance_commitment,
+                &new_source_opening,
+                new_decrypted_available_balance,
+            )
+        })
+    };
+    let ciphertext_validity_job = {
+        let transfer_amount_opening_lo = transfer_amount_opening_lo.clone();
+        let transfer_amount_opening_hi = transfer_amount_opening_hi.clone();
+        run_proof_job("transfer_ciphertext_validity", move || {
+            BatchedGroupedCiphertext3HandlesValidityProofData::new(
+                &source_elgamal_pubkey,
+                &destination_elgamal_pubkey,
+                &auditor_elgamal_pubkey,
+                &transfer_amount_grouped_ciphertext_lo,
+                &transfer_amount_grouped_ciphertext_hi,
+                transfer_amount_lo,
+                transfer_amount_hi,
+                &transfer_amount_opening_lo,
+                &transfer_amount_opening_hi,
+            )
+        })
+    };
+    let range_job = run_proof_job("transfer_range", move || {
+        let (padding_commitment, padding_opening) = Pedersen::new(0_u64);
+        BatchedRangeProofU128Data::new(
+            vec![
+                &new_available_balance_commitment,
+                &transfer_amount_grouped_ciphertext_lo.commitment,
+                &transfer_amount_grouped_ciphertext_hi.commitment,
+                &padding_commitment,
+            ],
+            vec![new_decrypted_available_balance, transfer_amount_lo, transfer_amount_hi, 0],
+            vec![
+                REMAINING_BALANCE_BIT_LENGTH,
+                TRANSFER_AMOUNT_LO_BITS,
+                TRANSFER_AMOUNT_HI_BITS,
+                RANGE_PROOF_PADDING_BIT_LENGTH,
+            ],
+            vec![
+                &new_source_opening,
+                &transfer_amount_opening_lo,
+                &transfer_amount_opening_hi,
+                &padding_opening,
+            ],
+        )
+    });
+
+    let (equality_proof_data, ciphertext_validity_proof_data, range_proof_data) =
+        tokio::try_join!(equality_job, ciphertext_validity_job, range_job)?;
+
+    let ciphertext_validity_proof_data = ciphertext_validity_proof_data?;
+    let context_data = ciphertext_validity_proof_data.context_data();
+    let ciphertext_validity_proof_data_with_ciphertext = CiphertextValidityProofWithAuditorCiphertext {
+        ciphertext_lo: context_data
+            .grouped_ciphertext_lo
+            .try_extract_ciphertext(2)
+            .map_err(|_| TokenProofGenerationError::CiphertextExtraction)?,
+        ciphertext_hi: context_data
+            .grouped_ciphertext_hi
+            .try_extract_ciphertext(2)
+            .map_err(|_| TokenProofGenerationError::CiphertextExtraction)?,
+        proof_data: ciphertext_validity_proof_data,
+    };
+
+    Ok(TransferProofData {
+        equality_proof_data: equality_proof_data?,
+        ciphertext_validity_proof_data_with_ciphertext,
+        range_proof_data: range_proof_data?,
+    })
+}
+
+/// Same as `spl_token_confidential_transfer_proof_generation::withdraw::withdraw_proof_data`,
+/// but generates the equality and range proofs as two parallel jobs on the proof pool.
+pub async fn withdraw_proof_data_parallel(
+    current_available_balance: &ElGamalCiphertext,
+    current_balance: u64,
+    withdraw_amount: u64,
+    elgamal_keypair: &ElGamalKeypair,
+) -> Result<WithdrawProofData, Box<dyn Error>> {
+    let remaining_balance = current_balance
+        .checked_sub(withdraw_amount)
+        .ok_or(TokenProofGenerationError::NotEnoughFunds)?;
+
+    let (remaining_balance_commitment, remaining_balance_opening) = Pedersen::new(remaining_balance);
+    let remaining_balance_ciphertext = current_available_balance - ElGamal::encode(withdraw_amount);
+
+    let equality_job = {
+        let elgamal_keypair = elgamal_keypair.clone();
+        let remaining_balance_opening = remaining_balance_opening.clone();
+        run_proof_job("withdraw_equality", move || {
+            CiphertextCommitmentEqualityProofData::new(
+                &elgamal_keypair,
+                &remaining_balance_ciphertext,
+                &remaining_balance_commitment,
+                &remaining_balance_opening,
+                remaining_balance,
+            )
+        })
+    };
+    let range_job = run_proof_job("withdraw_range", move || {
+        BatchedRangeProofU64Data::new(
+            vec![&remaining_balance_commitment],
+            vec![remaining_balance],
+            vec![REMAINING_BALANCE_BIT_LENGTH],
+            vec![&remaining_balance_opening],
+        )
+    });
+
+    let (equality_proof_data, range_proof_data) = tokio::try_join!(equality_job, range_job)?;
+    Ok(WithdrawProofData {
+        equality_proof_data: equality_proof_data?,
+        range_proof_data: range_proof_data?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        spl_token_confidential_transfer_proof_generation::{
+            transfer::transfer_split_proof_data, withdraw::withdraw_proof_data,
+        },
+    };
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_proof_jobs_verify() -> Result<(), Box<dyn Error>> {
+        let source_elgamal_keypair = ElGamalKeypair::new_rand();
+        let destination_elgamal_keypair = ElGamalKeypair::new_rand();
+        let auditor_elgamal_keypair = ElGamalKeypair::new_rand();
+        let aes_key = AeKey::new_rand();
+
+        let available_balance = source_elgamal_keypair.pubkey().encrypt(100_00_u64);
+        let decryptable_available_balance = aes_key.encrypt(100_00);
+
+        let transfer_proof_data = run_proof_job("test_transfer", move || {
+            transfer_split_proof_data(
+                &available_balance,
+                &decryptable_available_balance,
+                50_00,
+                &source_elgamal_keypair,
+                &aes_key,
+                destination_elgamal_keypair.pubkey(),
+                Some(auditor_elgamal_keypair.pubkey()),
+            )
+        })
+        .await??;
+        transfer_proof_data.equality_proof_data.verify_proof()?;
+        transfer_proof_data.ciphertext_validity_proof_data_with_ciphertext.proof_data.verify_proof()?;
+        transfer_proof_data.range_proof_data.verify_proof()?;
+
+        let withdraw_elgamal_keypair = ElGamalKeypair::new_rand();
+        let withdraw_available_balance = withdraw_elgamal_keypair.pubkey().encrypt(20_00_u64);
+        let withdraw_proof_data = run_proof_job("test_withdraw", move || {
+            withdraw_proof_data(&withdraw_available_balance, 20_00, 5_00, &withdraw_elgamal_keypair)
+        })
+        .await??;
+        withdraw_proof_data.equality_proof_data.verify_proof()?;
+        withdraw_proof_data.range_proof_data.verify_proof()?;
+
+        assert_eq!(proof_latency_metrics()["test_transfer"].jobs, 1);
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_panicking_job_returns_error() -> Result<(), Box<dyn Error>> {
+        let result = run_proof_job("test_panic", || -> u64 { panic!("proof generation failed") }).await;
+        let error = result.expect_err("a panicking job must surface as an error").to_string();
+        assert!(error.contains("proof generation failed"), "{}", error);
+
+        // The pool keeps serving jobs after a panic.
+        assert_eq!(run_proof_job("test_after_panic", || 42).await?, 42);
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_parallel_proofs_verify_like_sequential() -> Result<(), Box<dyn Error>> {
+        let source_elgamal_keypair = ElGamalKeypair::new_rand();
+        let destination_elgamal_keypair = ElGamalKeypair::new_rand();
+        let auditor_elgamal_keypair = ElGamalKeypair::new_rand();
+        let aes_key = AeKey::new_rand();
+        let available_balance = source_elgamal_keypair.pubkey().encrypt(100_000_u64);
+        let decryptable_available_balance = aes_key.encrypt(100_000);
+
+        // The transfer amount spans both the lo and hi halves.
+        let transfer_amount = 70_000;
+        let sequential = transfer_split_proof_data(
+            &available_balance,
+            &decryptable_available_balance,
+            transfer_amount,
+            &source_elgamal_keypair,
+            &aes_key,
+            destination_elgamal_keypair.pubkey(),
+            Some(auditor_elgamal_keypair.pubkey()),
+        )?;
+        let parallel = transfer_split_proof_data_parallel(
+            &available_balance,
+            &decryptable_available_balance,
+            transfer_amount,
+            &source_elgamal_keypair,
+            &aes_key,
+            destination_elgamal_keypair.pubkey(),
+            Some(auditor_elgamal_keypair.pubkey()),
+        )
+        .await?;
+
+        for proof_data in [&sequential, &parallel] {
+            proof_data.equality_proof_data.verify_proof()?;
+            proof_data.ciphertext_validity_proof_data_with_ciphertext.proof_data.verify_proof()?;
+            proof_data.range_proof_data.verify_proof()?;
+
+            // Both prove the same new available balance and give the auditor the same amount halves.
+            let new_available_balance: ElGamalCiphertext = proof_data.equality_proof_data.context_data().ciphertext.try_into()?;
+            assert_eq!(source_elgamal_keypair.secret().decrypt_u32(&new_available_balance), Some(30_000));
+            let with_ciphertext = &proof_data.ciphertext_validity_proof_data_with_ciphertext;
+            let auditor_ciphertext_lo: ElGamalCiphertext = with_ciphertext.ciphertext_lo.try_into()?;
+            let auditor_ciphertext_hi: ElGamalCiphertext = with_ciphertext.ciphertext_hi.try_into()?;
+            assert_eq!(auditor_elgamal_keypair.secret().decrypt_u32(&auditor_ciphertext_lo), Some(transfer_amount & 0xFFFF));
+            assert_eq!(auditor_elgamal_keypair.secret().decrypt_u32(&auditor_ciphertext_hi), Some(transfer_amount >> 16));
+        }
+
+        let withdraw_available_balance = source_elgamal_keypair.pubkey().encrypt(20_00_u64);
+        let sequential = withdraw_proof_data(&withdraw_available_balance, 20_00, 5_00, &source_elgamal_keypair)?;
+        let parallel = withdraw_proof_data_parallel(&withdraw_available_balance, 20_00, 5_00, &source_elgamal_keypair).await?;
+        for proof_data in [&sequential, &parallel] {
+            proof_data.equality_proof_data.verify_proof()?;
+            proof_data.range_proof_data.verify_proof()?;
+            let remaining_balance: ElGamalCiphertext = proof_data.equality_proof_data.context_data().ciphertext.try_into()?;
+            assert_eq!(source_elgamal_keypair.secret().decrypt_u32(&remaining_balance), Some(15_00));
+        }
+        Ok(())
+    }
+}